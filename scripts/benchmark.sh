@@ -0,0 +1,78 @@
+#!/usr/bin/env bash
+# Reproduces the benchmark table of the README.
+#
+# Builds a revision in release mode without OpenCV and, for every resolution and mode,
+# embeds random data on a single core into a Y4M file and dislodges it again. Prints one
+# markdown row per resolution and mode: the render time per frame (the sum of the
+# `Etching frame` timers over the frame count) and the end to end throughput of both
+# directions. Y4M frames are written and read directly, so no codec time is included.
+#
+# Usage: scripts/benchmark.sh [revision] [frames]
+#   revision  Git revision to measure, HEAD by default
+#   frames    Frames' worth of data embedded per run, 24 by default
+set -euo pipefail
+
+revision=${1:-HEAD}
+frames=${2:-24}
+
+repo=$(git rev-parse --show-toplevel)
+work=$(mktemp -d)
+trap 'git -C "$repo" worktree remove --force "$work/build" >/dev/null 2>&1 || true
+      rm -rf "$work"' EXIT
+
+git -C "$repo" worktree add --detach "$work/build" "$revision" >/dev/null
+cargo build --release --quiet --no-default-features \
+    --manifest-path "$work/build/Cargo.toml" --target-dir "$repo/target/benchmark"
+binary="$repo/target/benchmark/release/infinite-data-storage"
+
+# Frame count of a Y4M file of $2 x $3 pixels: its header line, then frames of a
+# `FRAME` line and three planes of 16-bit samples
+count_frames() {
+    local header
+    header=$(head -n 1 "$1" | wc -c)
+    echo $((($(stat -c %s "$1") - header) / (6 + $2 * $3 * 6)))
+}
+
+# Sum of the `Etching frame` timers of a run, in milliseconds
+render_total() {
+    grep -o 'Etching frame ended in [0-9]*[^ ]*' "$1" |
+        awk '{ value = $5 + 0; if ($5 !~ /ms$/) value /= 1000; total += value }
+            END { print total }'
+}
+
+# Formats $1 / $2 with one decimal
+ratio() {
+    awk -v numerator="$1" -v denominator="$2" 'BEGIN { printf "%.1f", numerator / denominator }'
+}
+
+cd "$work"
+echo "| Resolution | Mode | Render, ms/frame | Embed, fps | Dislodge, fps |"
+echo "|---|---|---|---|---|"
+for resolution in 720p:1280:720 1080p:1920:1080 2160p:3840:2160; do
+    IFS=: read -r name width height <<< "$resolution"
+    for mode in colored binary gray8; do
+        # Roughly a frame's worth of data per frame, with 2x2 pixel blocks
+        case $mode in
+            colored) per_frame=$((width * height / 4 * 3)) ;;
+            binary) per_frame=$((width * height / 4 / 8)) ;;
+            gray8) per_frame=$((width * height / 4 * 3 / 8)) ;;
+        esac
+        head -c $((per_frame * frames)) /dev/urandom > in.bin
+
+        rm -f output.y4m out.bin
+        start=$(date +%s%N)
+        "$binary" embed --in-path in.bin --output output.y4m --mode "$mode" --block-size 2 \
+            --resolution "$name" --fps 10 --threads 1 \
+            --compression none --parity 0 --overhead 0 > embed.log
+        middle=$(date +%s%N)
+        "$binary" dislodge -i output.y4m -o out.bin --threads 1 > dislodge.log
+        end=$(date +%s%N)
+        cmp -s in.bin out.bin || { echo "$name $mode did not round trip" >&2; exit 1; }
+
+        etched=$(count_frames output.y4m "$width" "$height")
+        render=$(ratio "$(render_total embed.log)" "$etched")
+        embed=$(ratio "$etched" "$(((middle - start) / 1000))e-6")
+        dislodge=$(ratio "$etched" "$(((end - middle) / 1000))e-6")
+        echo "| $name | $mode | $render | $embed | $dislodge |"
+    done
+done
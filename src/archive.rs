@@ -0,0 +1,435 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, bail};
+
+use crate::header::{ByteReader, UnexpectedEnd};
+use crate::metadata::FileMeta;
+use crate::spool::{self, Spool};
+
+/// Magic signature at the start of an archive payload.
+const ARCHIVE_MAGIC: [u8; 4] = *b"ISAR";
+
+/// Bytes read at first when looking for the end of the file table while unpacking.
+const TABLE_CHUNK: usize = 64 * 1024;
+
+/// The kind of filesystem object an archive entry describes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+}
+
+/// A single archive entry. `meta.name` holds the entry's relative path, `/` separated.
+struct Entry {
+    kind: EntryKind,
+    meta: FileMeta,
+    /// Target of a symbolic link, empty for other kinds.
+    link_target: String,
+    /// Where the contents of a file are read from while packing.
+    source: Option<PathBuf>,
+}
+
+/// Packs files, directories and symbolic links into a single archive payload.
+///
+/// The archive starts with a file table (kind, relative path, size, timestamps, permissions
+/// and link target of every entry), followed by the contents of every file in table order.
+/// Directories are walked recursively, symbolic links are stored as links, not followed.
+///
+/// Layout (big-endian):
+/// ```text
+/// magic        [u8; 4]   "ISAR"
+/// entry_count  u32
+/// entries      entry_count * (kind u8, FileMeta, target_len u16, target [u8])
+/// contents     concatenated file contents
+/// ```
+///
+/// File contents are streamed into the archive, so it never has to fit in memory.
+///
+/// # Arguments
+/// * `paths` - The files and directories to pack. Each one becomes a top-level entry.
+///
+/// # Returns
+/// The archive, staged in a spool.
+pub fn pack(paths: &[PathBuf]) -> anyhow::Result<Spool> {
+    let mut entries: Vec<Entry> = Vec::new();
+
+    for path in paths {
+        let name = path
+            .file_name()
+            .ok_or_else(|| anyhow!("{} has no file name", path.display()))?
+            .to_string_lossy()
+            .into_owned();
+
+        if entries.iter().any(|e| e.meta.name == name) {
+            bail!("Two inputs share the name {:?}, rename one of them", name);
+        }
+
+        collect(path, name, &mut entries)?;
+    }
+
+    // File table
+    let mut archive = Spool::new()?;
+    archive.write_all(&ARCHIVE_MAGIC)?;
+    archive.write_all(&(entries.len() as u32).to_be_bytes())?;
+    for entry in &entries {
+        archive.write_all(&[match entry.kind {
+            EntryKind::File => 0,
+            EntryKind::Directory => 1,
+            EntryKind::Symlink => 2,
+        }])?;
+        archive.write_all(&entry.meta.encode())?;
+        archive.write_all(&(entry.link_target.len() as u16).to_be_bytes())?;
+        archive.write_all(entry.link_target.as_bytes())?;
+    }
+
+    // File contents
+    for entry in &entries {
+        if let Some(source) = &entry.source {
+            let copied = io::copy(
+                &mut fs::File::open(source)?.take(entry.meta.size + 1),
+                &mut archive,
+            )?;
+            if copied != entry.meta.size {
+                bail!("{} changed while it was being packed", source.display());
+            }
+        }
+    }
+
+    println!(
+        "Packed {} entries into a {} byte archive",
+        entries.len(),
+        archive.size()
+    );
+
+    Ok(archive)
+}
+
+/// Recursively adds `path` and, for directories, everything below it.
+///
+/// # Arguments
+/// * `path` - The filesystem object to add.
+/// * `name` - Its relative path inside the archive.
+/// * `entries` - The file table being built.
+fn collect(path: &Path, name: String, entries: &mut Vec<Entry>) -> anyhow::Result<()> {
+    // Don't follow symbolic links, they are stored as links
+    let metadata = fs::symlink_metadata(path)?;
+    let file_type = metadata.file_type();
+
+    let mut meta = FileMeta::from_metadata(name, &metadata)?;
+
+    if file_type.is_symlink() {
+        meta.size = 0;
+        let target = fs::read_link(path)?.to_string_lossy().into_owned();
+        entries.push(Entry {
+            kind: EntryKind::Symlink,
+            meta,
+            link_target: target,
+            source: None,
+        });
+    } else if file_type.is_dir() {
+        meta.size = 0;
+        let prefix = meta.name.clone();
+        entries.push(Entry {
+            kind: EntryKind::Directory,
+            meta,
+            link_target: String::new(),
+            source: None,
+        });
+
+        // Sort children so the same tree always produces the same archive
+        let mut children: Vec<PathBuf> = fs::read_dir(path)?
+            .map(|child| child.map(|c| c.path()))
+            .collect::<Result<_, _>>()?;
+        children.sort();
+
+        for child in children {
+            let child_name = child
+                .file_name()
+                .expect("Directory entries always have a name")
+                .to_string_lossy()
+                .into_owned();
+            collect(&child, format!("{}/{}", prefix, child_name), entries)?;
+        }
+    } else if file_type.is_file() {
+        entries.push(Entry {
+            kind: EntryKind::File,
+            meta,
+            link_target: String::new(),
+            source: Some(path.to_path_buf()),
+        });
+    } else {
+        println!("Skipping {}: not a file, directory or link", path.display());
+    }
+
+    Ok(())
+}
+
+/// Recreates the packed files, directories and links below `out_dir`.
+///
+/// # Arguments
+/// * `archive` - The archive produced by `pack`, read from the start. File contents are
+///   streamed to disk, so it never has to fit in memory.
+/// * `out_dir` - The directory to extract into. It is created if missing.
+///
+/// # Returns
+/// The number of entries extracted.
+pub fn unpack(archive: &mut impl Read, out_dir: &Path) -> anyhow::Result<usize> {
+    let mut table = spool::read_chunk(archive, TABLE_CHUNK)?;
+    if !table.starts_with(&ARCHIVE_MAGIC) {
+        bail!("Payload is not a valid archive");
+    }
+
+    // Read the whole file table before touching the filesystem. It has no length
+    // prefix, so read more while it runs past the bytes read so far
+    let (entries, table_len) = loop {
+        match read_table(&table) {
+            Ok(parsed) => break parsed,
+            Err(error) if error.is::<UnexpectedEnd>() => {
+                let more = spool::read_chunk(archive, table.len())?;
+                if more.is_empty() {
+                    return Err(error);
+                }
+                table.extend(more);
+            }
+            Err(error) => return Err(error),
+        }
+    };
+    let mut contents = (&table[table_len..]).chain(archive);
+
+    fs::create_dir_all(out_dir)?;
+
+    for entry in &entries {
+        let path = out_dir.join(safe_relative_path(&entry.meta.name)?);
+
+        match entry.kind {
+            EntryKind::Directory => fs::create_dir_all(&path)?,
+            EntryKind::File => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let copied = io::copy(
+                    &mut (&mut contents).take(entry.meta.size),
+                    &mut fs::File::create(&path)?,
+                )?;
+                if copied != entry.meta.size {
+                    bail!("Archive is truncated: {} is incomplete", entry.meta.name);
+                }
+                entry.meta.apply(&path)?;
+            }
+            // Links are created last, so no file can be written through one
+            EntryKind::Symlink => {}
+        }
+    }
+
+    for entry in entries.iter().filter(|e| e.kind == EntryKind::Symlink) {
+        let path = out_dir.join(safe_relative_path(&entry.meta.name)?);
+        create_symlink(&entry.link_target, &path)?;
+    }
+
+    // Directory timestamps and permissions go last, deepest first, since writing
+    // their contents would change them (or be blocked by read-only modes)
+    for entry in entries.iter().rev() {
+        if entry.kind == EntryKind::Directory {
+            entry
+                .meta
+                .apply(&out_dir.join(safe_relative_path(&entry.meta.name)?))?;
+        }
+    }
+
+    Ok(entries.len())
+}
+
+/// Parses the file table at the start of an archive.
+///
+/// # Returns
+/// The entries and the length of the table, or an `UnexpectedEnd` error if `bytes` ends
+/// before the table does.
+fn read_table(bytes: &[u8]) -> anyhow::Result<(Vec<Entry>, usize)> {
+    let mut reader = ByteReader::new(bytes);
+    reader.take(ARCHIVE_MAGIC.len())?;
+
+    let entry_count = reader.u32()?;
+    let mut entries: Vec<Entry> = Vec::new();
+    for _ in 0..entry_count {
+        let kind = match reader.u8()? {
+            0 => EntryKind::File,
+            1 => EntryKind::Directory,
+            2 => EntryKind::Symlink,
+            other => bail!("Unknown archive entry kind {}", other),
+        };
+        let meta = FileMeta::decode(&mut reader)?;
+        let target_len = reader.u16()? as usize;
+        let link_target = String::from_utf8(reader.take(target_len)?.to_vec())?;
+
+        entries.push(Entry {
+            kind,
+            meta,
+            link_target,
+            source: None,
+        });
+    }
+
+    Ok((entries, reader.position()))
+}
+
+/// Validates an archive path, refusing anything that could escape the output
+/// directory (absolute paths, `..` components, empty names).
+fn safe_relative_path(name: &str) -> anyhow::Result<PathBuf> {
+    let path = PathBuf::from(name);
+    let safe = !name.is_empty()
+        && !name.contains('\\')
+        && path.components().all(|c| matches!(c, Component::Normal(_)));
+
+    if !safe {
+        bail!("Refusing to extract unsafe archive path {:?}", name);
+    }
+
+    Ok(path)
+}
+
+/// Creates a symbolic link at `path` pointing to `target`.
+#[cfg(unix)]
+fn create_symlink(target: &str, path: &Path) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    std::os::unix::fs::symlink(target, path)?;
+    Ok(())
+}
+
+/// Symbolic links are only restored on Unix.
+#[cfg(not(unix))]
+fn create_symlink(target: &str, path: &Path) -> anyhow::Result<()> {
+    println!(
+        "Skipping symbolic link {} -> {}: not supported on this platform",
+        path.display(),
+        target
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counts the bytes read through it, to check how much of an archive was consumed.
+    struct Counting<R> {
+        inner: R,
+        read: usize,
+    }
+
+    impl<R: Read> Read for Counting<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let read = self.inner.read(buf)?;
+            self.read += read;
+            Ok(read)
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn round_trip_restores_every_kind_of_entry() {
+        let input = tempfile::tempdir().unwrap();
+        let tree = input.path().join("tree");
+        fs::create_dir_all(tree.join("nested/deeper")).unwrap();
+        fs::create_dir(tree.join("empty_dir")).unwrap();
+        fs::write(tree.join("nested/deeper/data.bin"), [7u8; 3000]).unwrap();
+        fs::write(tree.join("empty.txt"), b"").unwrap();
+        std::os::unix::fs::symlink("nested/deeper/data.bin", tree.join("link")).unwrap();
+        let single = input.path().join("single.txt");
+        fs::write(&single, b"top-level file").unwrap();
+
+        let mut archive = pack(&[tree, single]).unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let count = unpack(&mut archive.reader().unwrap(), output.path()).unwrap();
+
+        // tree, nested, deeper, data.bin, empty_dir, empty.txt, link and single.txt
+        assert_eq!(count, 8);
+        let out = output.path();
+        assert_eq!(
+            fs::read(out.join("tree/nested/deeper/data.bin")).unwrap(),
+            [7u8; 3000]
+        );
+        assert_eq!(fs::read(out.join("tree/empty.txt")).unwrap(), b"");
+        assert!(out.join("tree/empty_dir").is_dir());
+        assert_eq!(fs::read_dir(out.join("tree/empty_dir")).unwrap().count(), 0);
+        assert_eq!(
+            fs::read_link(out.join("tree/link")).unwrap(),
+            PathBuf::from("nested/deeper/data.bin")
+        );
+        assert_eq!(fs::read(out.join("single.txt")).unwrap(), b"top-level file");
+    }
+
+    #[test]
+    fn table_longer_than_the_first_chunk_is_read_in_full() {
+        let input = tempfile::tempdir().unwrap();
+        let tree = input.path().join("many");
+        fs::create_dir(&tree).unwrap();
+        for index in 0..600 {
+            fs::write(tree.join(format!("{:0>120}", index)), index.to_string()).unwrap();
+        }
+
+        let mut archive = pack(&[tree]).unwrap();
+        let output = tempfile::tempdir().unwrap();
+        let count = unpack(&mut archive.reader().unwrap(), output.path()).unwrap();
+
+        assert_eq!(count, 601);
+        let last = output.path().join(format!("many/{:0>120}", 599));
+        assert_eq!(fs::read_to_string(last).unwrap(), "599");
+    }
+
+    #[test]
+    fn unknown_entry_kind_fails_without_reading_the_payload() {
+        let mut archive = ARCHIVE_MAGIC.to_vec();
+        archive.extend_from_slice(&1u32.to_be_bytes());
+        archive.push(9);
+        archive.resize(16 * TABLE_CHUNK, 0);
+
+        let mut reader = Counting {
+            inner: archive.as_slice(),
+            read: 0,
+        };
+        let output = tempfile::tempdir().unwrap();
+        let error = unpack(&mut reader, output.path()).unwrap_err();
+
+        assert!(error.to_string().contains("Unknown archive entry kind 9"));
+        assert_eq!(reader.read, TABLE_CHUNK);
+    }
+
+    #[test]
+    fn truncated_table_is_reported() {
+        let mut archive = ARCHIVE_MAGIC.to_vec();
+        archive.extend_from_slice(&2u32.to_be_bytes());
+
+        let output = tempfile::tempdir().unwrap();
+        let error = unpack(&mut archive.as_slice(), output.path()).unwrap_err();
+
+        assert!(error.is::<UnexpectedEnd>());
+    }
+
+    #[test]
+    fn safe_relative_path_accepts_nested_names() {
+        assert_eq!(
+            safe_relative_path("dir/sub/file.txt").unwrap(),
+            PathBuf::from("dir/sub/file.txt")
+        );
+    }
+
+    #[test]
+    fn safe_relative_path_rejects_escaping_names() {
+        for name in [
+            "",
+            "..",
+            "../outside",
+            "dir/../../outside",
+            "/etc/passwd",
+            "dir\\..\\outside",
+            "C:\\Windows",
+        ] {
+            assert!(safe_relative_path(name).is_err(), "accepted {:?}", name);
+        }
+    }
+}
@@ -26,6 +26,9 @@ pub enum Commands {
 
     /// Subcommand for extracting (dislodging) data from a video.
     Dislodge(DislodgeParams),
+
+    /// Subcommand for generating an identity to receive encrypted videos.
+    Keygen(KeygenParams),
 }
 
 /// Presets for embedding data with different levels of compression resistance or efficiency.
@@ -43,7 +46,9 @@ pub enum EmbedPreset {
 }
 
 /// Output mode for embedding data, determining how the data is represented in the video.
-/// Each mode has unique characteristics for handling compression.
+/// Each mode has unique characteristics for handling compression: `Binary` is the most
+/// robust, the gray and palette modes pack 2 or 3 bits into every block, and `Colored`
+/// packs a byte into every channel.
 #[derive(Debug, Clone, ValueEnum)]
 pub enum EmbedOutputMode {
     /// Uses RGB values, resulting in vibrant colors, but the encoding is susceptible to compression artifacts.
@@ -51,6 +56,18 @@ pub enum EmbedOutputMode {
 
     /// Uses black and white pixels for better resistance to compression, sacrificing color fidelity.
     Binary,
+
+    /// Uses four gray levels (2 bits per block), twice as dense as binary and still compression tolerant.
+    Gray4,
+
+    /// Uses eight gray levels (3 bits per block), three times as dense as binary.
+    Gray8,
+
+    /// Uses four maximally separated colors (2 bits per block) that survive chroma subsampling.
+    Palette4,
+
+    /// Uses the eight corners of the RGB cube as colors (3 bits per block).
+    Palette8,
 }
 
 /// Implements conversion from `EmbedOutputMode` to `OutputMode`.
@@ -67,8 +84,9 @@ impl From<EmbedOutputMode> for crate::settings::OutputMode {
     ///
     /// # Example
     /// ```
-    /// let embed_mode = EmbedOutputMode::Colored;
-    /// let output_mode: OutputMode = embed_mode.into();
+    /// let output_mode: OutputMode = EmbedOutputMode::Colored.into(); // OutputMode::Color
+    /// let output_mode: OutputMode = EmbedOutputMode::Gray8.into(); // OutputMode::Gray8
+    /// let output_mode: OutputMode = EmbedOutputMode::Palette4.into(); // OutputMode::Palette4
     /// ```
     fn from(value: EmbedOutputMode) -> Self {
         match value {
@@ -76,26 +94,157 @@ impl From<EmbedOutputMode> for crate::settings::OutputMode {
             EmbedOutputMode::Colored => Self::Color,
             // Map the `Binary` variant of `EmbedOutputMode` to `Binary` in `OutputMode`.
             EmbedOutputMode::Binary => Self::Binary,
+            // Map the gray variants to their `OutputMode` counterparts.
+            EmbedOutputMode::Gray4 => Self::Gray4,
+            EmbedOutputMode::Gray8 => Self::Gray8,
+            // Map the palette variants to their `OutputMode` counterparts.
+            EmbedOutputMode::Palette4 => Self::Palette4,
+            EmbedOutputMode::Palette8 => Self::Palette8,
         }
     }
 }
 
+/// Compression applied to the payload before it is etched.
+#[derive(Debug, Clone, ValueEnum)]
+pub enum EmbedCompression {
+    /// Store the payload as-is.
+    None,
+
+    /// Zstandard: fast with a good ratio.
+    Zstd,
+
+    /// XZ (LZMA2): slower, usually the smallest output for text.
+    Xz,
+}
+
+/// Implements conversion from `EmbedCompression` to `compression::Algorithm`.
+impl From<EmbedCompression> for crate::compression::Algorithm {
+    fn from(value: EmbedCompression) -> Self {
+        match value {
+            EmbedCompression::None => Self::None,
+            EmbedCompression::Zstd => Self::Zstd,
+            EmbedCompression::Xz => Self::Xz,
+        }
+    }
+}
+
+/// Authenticated cipher used to encrypt the payload.
+#[derive(Debug, Clone, ValueEnum)]
+pub enum EmbedCipher {
+    /// ChaCha20-Poly1305, fast on any CPU.
+    #[value(name = "chacha20-poly1305")]
+    ChaCha20Poly1305,
+
+    /// AES-256-GCM, fastest on CPUs with AES instructions.
+    #[value(name = "aes-256-gcm")]
+    Aes256Gcm,
+}
+
+/// Implements conversion from `EmbedCipher` to `crypto::Cipher`.
+impl From<EmbedCipher> for crate::crypto::Cipher {
+    fn from(value: EmbedCipher) -> Self {
+        match value {
+            EmbedCipher::ChaCha20Poly1305 => Self::ChaCha20Poly1305,
+            EmbedCipher::Aes256Gcm => Self::Aes256Gcm,
+        }
+    }
+}
+
+/// Codec the embedded video is written with.
+#[derive(Debug, Clone, ValueEnum)]
+pub enum EmbedCodec {
+    /// Lossless PNG frames, the default for `.avi`.
+    Png,
+
+    /// Lossless FFV1, smaller than PNG, the default for `.mkv`.
+    Ffv1,
+
+    /// Motion JPEG, lossy.
+    Mjpg,
+
+    /// H.264, lossy, the default for `.mp4`.
+    Avc1,
+
+    /// MPEG-4 Part 2, lossy.
+    Mp4v,
+
+    /// Uncompressed frames, large but lossless.
+    Raw,
+}
+
+/// Implements conversion from `EmbedCodec` to `video::Codec`.
+impl From<EmbedCodec> for crate::video::Codec {
+    fn from(value: EmbedCodec) -> Self {
+        match value {
+            EmbedCodec::Png => Self::Png,
+            EmbedCodec::Ffv1 => Self::Ffv1,
+            EmbedCodec::Mjpg => Self::Mjpg,
+            EmbedCodec::Avc1 => Self::Avc1,
+            EmbedCodec::Mp4v => Self::Mp4v,
+            EmbedCodec::Raw => Self::Raw,
+        }
+    }
+}
+
+/// Library videos are written or read with.
+#[derive(Debug, Clone, ValueEnum)]
+pub enum VideoBackend {
+    /// OpenCV, the default.
+    Opencv,
+
+    /// A local `ffmpeg` process, fed or read through a pipe of raw frames.
+    Ffmpeg,
+}
+
+/// Implements conversion from `VideoBackend` to `video::Backend`.
+impl From<VideoBackend> for crate::video::Backend {
+    fn from(value: VideoBackend) -> Self {
+        match value {
+            VideoBackend::Opencv => Self::OpenCv,
+            VideoBackend::Ffmpeg => Self::Ffmpeg,
+        }
+    }
+}
 
 /// Parameters specific to the `embed` subcommand, which handles embedding data into a video.
 /// All fields are optional, and defaults may be applied based on the user interface or runtime logic.
 #[derive(Args, Default, Debug)]
 pub struct EmbedParams {
-    /// Path to the input file containing the data to be encoded into the video.
-    /// Example: `"data.txt"`
+    /// Paths to the files or directories containing the data to be encoded into the video.
+    /// A single file is embedded as-is; several paths, directories, links or empty files
+    /// are packed into an archive that dislodge extracts again.
+    /// Example: `"data.txt"` or `"photos/" "notes.txt"`
+    #[arg(short, long, num_args = 1..)]
+    pub in_path: Vec<String>,
+
+    /// Path of the video to create. Its extension picks how it is written: `.avi`, `.mkv` and
+    /// `.mp4` go through `--backend`, `.y4m` is written directly as lossless 10-bit 4:4:4, and
+    /// a directory (e.g. `frames/`) gets numbered PNG frames.
+    /// Defaults to `"output.avi"`, or `"output.y4m"` in builds without the `opencv` feature.
     #[arg(short, long)]
-    pub in_path: Option<String>,
+    pub output: Option<String>,
+
+    /// Codec of the video: `png`, `ffv1`, `mjpg`, `avc1`, `mp4v` or `raw`.
+    /// Defaults to `png` for `.avi`, `ffv1` for `.mkv`, `avc1` for `.mp4` and `raw` for `.y4m`.
+    #[arg(long)]
+    pub codec: Option<EmbedCodec>,
+
+    /// Library the video is written with: `opencv` (the default) or `ffmpeg`, which must be on the PATH
+    /// and is the default in builds without OpenCV.
+    #[arg(long)]
+    pub backend: Option<VideoBackend>,
+
+    /// Encoder arguments for ffmpeg, replacing those picked from `--codec`; implies `--backend ffmpeg`.
+    /// Example: `"-c:v libx264 -preset slow -crf 18 -pix_fmt yuv444p"`
+    #[arg(long, allow_hyphen_values = true)]
+    pub ffmpeg_args: Option<String>,
 
     /// Preset for the embedding process.
     /// Allows selecting predefined configurations such as `Optimal`, `Paranoid`, or `MaxEfficiency`.
     #[arg(short, long)]
     pub preset: Option<EmbedPreset>,
 
-    /// Mode for embedding data: `Colored` or `Binary`.
+    /// Mode for embedding data: `Colored`, `Binary`, `Gray4`, `Gray8`, `Palette4` or `Palette8`.
     /// This determines the visual and compression properties of the output.
     #[arg(long)]
     pub mode: Option<EmbedOutputMode>,
@@ -116,10 +265,48 @@ pub struct EmbedParams {
     pub fps: Option<i32>,
 
     /// Resolution of the output video.
-    /// Valid options: `"144"`, `"240"`, `"360"`, `"480"`, `"720"`.
+    /// Valid options: `"144p"`, `"240p"`, `"360p"`, `"480p"`, `"720p"`, `"1080p"`, `"2160p"`.
     /// Defaults to `"360"` if an invalid value is provided.
     #[arg(long)]
     pub resolution: Option<String>,
+
+    /// Number of Reed-Solomon parity bytes per 255-byte codeword (even, up to 254).
+    /// 32 gives RS(255,223), correcting 16 damaged bytes per codeword. `0` disables error correction.
+    #[arg(long)]
+    pub parity: Option<u8>,
+
+    /// Fountain code repair symbols to add, in percent of the data frames.
+    /// 10 lets roughly any 10 frames in 11 restore the file. `0` disables the fountain code.
+    #[arg(long)]
+    pub overhead: Option<u8>,
+
+    /// Compression applied to the data before etching: `none`, `zstd` or `xz`. Defaults to `zstd`.
+    /// Compression is skipped automatically when the data is already incompressible.
+    #[arg(long)]
+    pub compression: Option<EmbedCompression>,
+
+    /// Compression level: 1 to 22 for zstd (default 9), 0 to 9 for xz (default 6).
+    #[arg(long)]
+    pub compression_level: Option<i32>,
+
+    /// Encrypts the data with a passphrase, using `chacha20-poly1305` (the default) or `aes-256-gcm`.
+    /// The key is derived with Argon2id. File names, sizes and timestamps are encrypted as well.
+    #[arg(long, num_args = 0..=1, default_missing_value = "chacha20-poly1305")]
+    pub encrypt: Option<EmbedCipher>,
+
+    /// Reads the encryption passphrase from the first line of this file instead of prompting for it.
+    #[arg(long)]
+    pub passphrase_file: Option<String>,
+
+    /// Encrypts the data to this recipient's public key (`isrs-pk-...`, as printed by `keygen`)
+    /// instead of a passphrase. Repeat to share the video with several people.
+    #[arg(long)]
+    pub recipient: Vec<String>,
+
+    /// Signs the video with the Ed25519 key in this file (written by `keygen --signing`),
+    /// so downloaders can check it came from you with `dislodge --verify`.
+    #[arg(long)]
+    pub sign: Option<String>,
 }
 
 /// Parameters specific to the `download` subcommand, which handles downloading videos or other resources.
@@ -142,7 +329,54 @@ pub struct DislodgeParams {
     pub in_path: Option<String>,
 
     /// Path to the output file where the extracted data will be saved (including the file extension).
+    /// When omitted, the file is restored under its original name inside `out_dir`.
     /// Example: `"output.txt"`
     #[arg(short, long)]
     pub out_path: Option<String>,
+
+    /// Directory to restore the file into under its original name, used when `out_path` is omitted.
+    /// Defaults to the current directory.
+    #[arg(long)]
+    pub out_dir: Option<String>,
+
+    /// Number of threads to use for decoding frames. Defaults to the number of available cores.
+    #[arg(long)]
+    pub threads: Option<usize>,
+
+    /// Library the video is read with: `opencv` (the default) or `ffmpeg`, which must be on the PATH
+    /// along with `ffprobe` and is the default in builds without OpenCV.
+    #[arg(long)]
+    pub backend: Option<VideoBackend>,
+
+    /// Reads the passphrase of an encrypted video from the first line of this file instead of prompting for it.
+    #[arg(long)]
+    pub passphrase_file: Option<String>,
+
+    /// Identity file written by `keygen`, used to decrypt videos encrypted to recipients.
+    #[arg(long)]
+    pub identity: Option<String>,
+
+    /// Refuses to extract unless the video is signed by this trusted public key (`isrs-sig-...`).
+    /// Signed videos are otherwise checked for consistency and their signer reported.
+    #[arg(long)]
+    pub verify: Option<String>,
+
+    /// Deletes the partially restored data when the restore fails (e.g. the integrity check
+    /// doesn't pass) instead of keeping it with a `.corrupt` suffix.
+    #[arg(long)]
+    pub delete_partial: bool,
+}
+
+/// Parameters specific to the `keygen` subcommand, which generates an X25519 identity
+/// or an Ed25519 signing key.
+#[derive(Args, Default)]
+pub struct KeygenParams {
+    /// Path of the key file to create. It must not exist yet.
+    /// Example: `"identity.key"`
+    #[arg(short, long)]
+    pub out_path: Option<String>,
+
+    /// Generates an Ed25519 signing key for `embed --sign` instead of an encryption identity.
+    #[arg(long)]
+    pub signing: bool,
 }
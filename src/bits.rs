@@ -0,0 +1,301 @@
+/// A sequence of bits packed eight to a byte, most significant bit first.
+///
+/// Bits are written one at a time or a few at a time with `push` and `push_bits`, and
+/// read back with a `BitReader`. Packing keeps binary data at the size of the bytes it
+/// came from instead of a byte per bit.
+#[derive(Clone, Default)]
+pub struct Bits {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl Bits {
+    /// Creates an empty sequence with room for `bits` bits.
+    pub fn with_capacity(bits: usize) -> Bits {
+        Bits {
+            bytes: Vec::with_capacity(bits.div_ceil(8)),
+            len: 0,
+        }
+    }
+
+    /// The bit at `index`.
+    ///
+    /// # Panics
+    /// If `index` is beyond the end of the sequence.
+    pub fn get(&self, index: usize) -> bool {
+        assert!(index < self.len, "bit {} out of {}", index, self.len);
+        self.bytes[index / 8] & (0x80 >> (index % 8)) != 0
+    }
+
+    /// Appends a bit.
+    pub fn push(&mut self, bit: bool) {
+        if self.len.is_multiple_of(8) {
+            self.bytes.push(0);
+        }
+        if bit {
+            self.bytes[self.len / 8] |= 0x80 >> (self.len % 8);
+        }
+        self.len += 1;
+    }
+
+    /// Appends the `count` lowest bits of `value` (at most 32), most significant first.
+    pub fn push_bits(&mut self, value: u32, count: usize) {
+        let mut remaining = count;
+        while remaining > 0 {
+            // Fill what is left of the last byte in one go
+            let used = self.len % 8;
+            if used == 0 {
+                self.bytes.push(0);
+            }
+            let taken = (8 - used).min(remaining);
+            let chunk = (value >> (remaining - taken)) & ((1 << taken) - 1);
+            let last = self.bytes.len() - 1;
+            self.bytes[last] |= (chunk << (8 - used - taken)) as u8;
+
+            self.len += taken;
+            remaining -= taken;
+        }
+    }
+
+    /// Appends every bit of `other`.
+    pub fn extend(&mut self, other: &Bits) {
+        if self.len.is_multiple_of(8) {
+            // Byte aligned, the bytes can be copied as they are
+            self.bytes.extend_from_slice(&other.bytes);
+            self.len += other.len;
+        } else {
+            for bit in other.reader() {
+                self.push(bit);
+            }
+        }
+    }
+
+    /// Shortens the sequence to its first `len` bits. Has no effect if it is already shorter.
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        self.len = len;
+        self.bytes.truncate(len.div_ceil(8));
+
+        // Clear the dropped bits of the last byte, so later pushes start from zero
+        if !len.is_multiple_of(8) {
+            self.bytes[len / 8] &= !(0xFF >> (len % 8));
+        }
+    }
+
+    /// Reads the sequence from its first bit.
+    pub fn reader(&self) -> BitReader<'_> {
+        BitReader {
+            bytes: &self.bytes,
+            len: self.len,
+            position: 0,
+        }
+    }
+
+    /// The whole bytes of the sequence. Trailing bits that don't complete a byte are left out.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.len / 8]
+    }
+
+    /// Turns the sequence into its whole bytes, dropping trailing bits that don't complete a byte.
+    pub fn into_bytes(mut self) -> Vec<u8> {
+        self.bytes.truncate(self.len / 8);
+        self.bytes
+    }
+}
+
+/// Reads packed bits in order, most significant bit of every byte first.
+#[derive(Clone)]
+pub struct BitReader<'a> {
+    bytes: &'a [u8],
+    len: usize,
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    /// Reads the bits of `bytes`, eight per byte.
+    pub fn new(bytes: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            bytes,
+            len: bytes.len() * 8,
+            position: 0,
+        }
+    }
+
+    /// Number of bits left to read.
+    pub fn remaining(&self) -> usize {
+        self.len - self.position
+    }
+
+    /// Reads the next `count` bits (at most 32) as a number, most significant first.
+    /// Bits past the end read as zeros.
+    ///
+    /// # Returns
+    /// The number, or `None` if every bit was already read.
+    pub fn read_bits(&mut self, count: usize) -> Option<u32> {
+        if self.remaining() == 0 {
+            return None;
+        }
+        let mut value = 0u32;
+        let mut needed = count;
+        while needed > 0 {
+            if self.position >= self.len {
+                value <<= needed;
+                break;
+            }
+
+            // Take what is needed from the current byte in one go
+            let used = self.position % 8;
+            let taken = (8 - used).min(self.len - self.position).min(needed);
+            let byte = self.bytes[self.position / 8] as u32;
+            let chunk = (byte >> (8 - used - taken)) & ((1 << taken) - 1);
+            value = (value << taken) | chunk;
+
+            self.position += taken;
+            needed -= taken;
+        }
+        Some(value)
+    }
+}
+
+impl Iterator for BitReader<'_> {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<bool> {
+        if self.position >= self.len {
+            return None;
+        }
+        let bit = self.bytes[self.position / 8] & (0x80 >> (self.position % 8)) != 0;
+        self.position += 1;
+        Some(bit)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining(), Some(self.remaining()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A pattern of `len` bits that isn't periodic in bytes.
+    fn pattern(len: usize) -> Vec<bool> {
+        (0..len).map(|i| (i * i + i / 3) % 5 < 2).collect()
+    }
+
+    fn collect(bits: &[bool]) -> Bits {
+        let mut packed = Bits::default();
+        for &bit in bits {
+            packed.push(bit);
+        }
+        packed
+    }
+
+    #[test]
+    fn push_round_trips_any_length() {
+        for len in [0, 1, 7, 8, 9, 15, 16, 17, 100, 1001] {
+            let bits = pattern(len);
+            let packed = collect(&bits);
+
+            assert_eq!(packed.reader().collect::<Vec<_>>(), bits, "length {}", len);
+            assert_eq!(packed.reader().remaining(), len);
+            for (index, &bit) in bits.iter().enumerate() {
+                assert_eq!(packed.get(index), bit);
+            }
+            assert_eq!(packed.bytes().len(), len / 8);
+        }
+    }
+
+    #[test]
+    fn bytes_round_trip_through_the_reader() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let packed = collect(&BitReader::new(&bytes).collect::<Vec<_>>());
+
+        assert_eq!(packed.bytes(), &bytes[..]);
+        assert_eq!(packed.into_bytes(), bytes);
+    }
+
+    #[test]
+    fn push_bits_round_trips_through_read_bits() {
+        // Widths that straddle byte boundaries in every way, 67 bits in total
+        let values = [
+            (1, 1),
+            (0b101, 3),
+            (0x1ff, 9),
+            (0, 2),
+            (0xdead_beef, 32),
+            (0x5, 4),
+            (0xabcd, 16),
+        ];
+        let mut packed = Bits::default();
+        for &(value, count) in &values {
+            packed.push_bits(value, count);
+        }
+
+        let mut reader = packed.reader();
+        assert_eq!(reader.remaining(), 67);
+        for &(value, count) in &values {
+            assert_eq!(reader.read_bits(count), Some(value));
+        }
+        assert_eq!(reader.read_bits(1), None);
+    }
+
+    #[test]
+    fn push_bits_matches_push() {
+        let mut by_bits = Bits::default();
+        let mut by_bit = Bits::default();
+        for (i, count) in [3, 8, 5, 12, 1, 7].into_iter().enumerate() {
+            let value = (i as u32).wrapping_mul(0x9e37_79b9) & ((1 << count) - 1);
+            by_bits.push_bits(value, count);
+            for shift in (0..count).rev() {
+                by_bit.push(value >> shift & 1 == 1);
+            }
+        }
+
+        assert_eq!(
+            by_bits.reader().collect::<Vec<_>>(),
+            by_bit.reader().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn read_bits_pads_past_the_end_with_zeros() {
+        let mut reader = BitReader::new(&[0b1011_0000]);
+        assert_eq!(reader.read_bits(6), Some(0b101100));
+        assert_eq!(reader.read_bits(4), Some(0b0000));
+        assert_eq!(reader.read_bits(4), None);
+
+        let packed = collect(&[true, true, true]);
+        assert_eq!(packed.reader().read_bits(5), Some(0b11100));
+    }
+
+    #[test]
+    fn extend_appends_at_any_alignment() {
+        for head in [0, 3, 8, 13] {
+            for tail in [0, 5, 8, 21] {
+                let (first, second) = (pattern(head), pattern(tail + 1)[1..].to_vec());
+                let mut packed = collect(&first);
+                packed.extend(&collect(&second));
+
+                let expected: Vec<bool> = first.iter().chain(&second).copied().collect();
+                assert_eq!(packed.reader().collect::<Vec<_>>(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn truncate_clears_the_dropped_bits() {
+        let mut packed = collect(&[true; 20]);
+        packed.truncate(30);
+        assert_eq!(packed.reader().remaining(), 20);
+
+        packed.truncate(11);
+        packed.push_bits(0, 5);
+        let mut expected = vec![true; 11];
+        expected.extend([false; 5]);
+        assert_eq!(packed.reader().collect::<Vec<_>>(), expected);
+        assert_eq!(packed.into_bytes(), vec![0xff, 0xe0]);
+    }
+}
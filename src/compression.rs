@@ -0,0 +1,256 @@
+use std::io::{self, Read, Write};
+
+use anyhow::bail;
+
+use crate::spool::{self, Spool};
+
+/// Size of the leading sample compressed to decide whether the payload is worth compressing.
+const PROBE_LEN: usize = 256 * 1024;
+
+/// The payload is only compressed if the probe shrinks to at most this fraction (in percent)
+/// of its size. Already compressed data (archives, media) barely shrinks and is left as-is.
+const PROBE_THRESHOLD_PERCENT: usize = 98;
+
+/// Compression algorithms available for the payload.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    /// Store the payload as-is.
+    None,
+    /// Zstandard: fast, with a good ratio. Levels 1 to 22.
+    Zstd,
+    /// XZ (LZMA2): slower, usually the best ratio on text. Levels 0 to 9.
+    Xz,
+}
+
+impl Algorithm {
+    /// The level used when none is given.
+    pub fn default_level(&self) -> i32 {
+        match self {
+            Algorithm::None => 0,
+            Algorithm::Zstd => 9,
+            Algorithm::Xz => 6,
+        }
+    }
+}
+
+/// Compression parameters recorded in the header so the decoder can reverse the compression.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Compression {
+    /// Algorithm the payload was compressed with. Never `Algorithm::None`.
+    pub algorithm: Algorithm,
+    /// Level the payload was compressed at, for information only.
+    pub level: i32,
+    /// Length of the payload before compression.
+    pub original_len: u64,
+}
+
+/// Compresses the payload, unless it turns out to be incompressible.
+///
+/// A leading sample is compressed first with fast settings; if it barely shrinks the
+/// payload is assumed to be already compressed and is left untouched. The full result
+/// is also discarded if it ends up no smaller than the input. The payload is streamed
+/// through the compressor, so it never has to fit in memory.
+///
+/// # Arguments
+/// * `data` - The payload.
+/// * `algorithm` - The algorithm to use.
+/// * `level` - The compression level, within the algorithm's range.
+///
+/// # Returns
+/// The compressed payload and its parameters, or `None` if compression was skipped.
+pub fn compress(
+    data: &mut Spool,
+    algorithm: Algorithm,
+    level: i32,
+) -> anyhow::Result<Option<(Spool, Compression)>> {
+    match algorithm {
+        Algorithm::None => return Ok(None),
+        Algorithm::Zstd if !(1..=22).contains(&level) => {
+            bail!(
+                "Zstd compression level must be between 1 and 22, got {}",
+                level
+            )
+        }
+        Algorithm::Xz if !(0..=9).contains(&level) => {
+            bail!(
+                "Xz compression level must be between 0 and 9, got {}",
+                level
+            )
+        }
+        _ => (),
+    }
+
+    // Probe a sample with a fast setting before spending time on the whole payload
+    let probe = spool::read_chunk(&mut data.reader()?, PROBE_LEN)?;
+    let probed = zstd::bulk::compress(&probe, 1)?;
+    if probed.len() * 100 > probe.len() * PROBE_THRESHOLD_PERCENT {
+        println!("Payload looks incompressible, skipping compression");
+        return Ok(None);
+    }
+
+    let mut input = data.reader()?;
+    let compressed = match algorithm {
+        Algorithm::Zstd => {
+            let mut encoder = zstd::stream::write::Encoder::new(Spool::new()?, level)?;
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?
+        }
+        Algorithm::Xz => {
+            let mut encoder = xz2::write::XzEncoder::new(Spool::new()?, level as u32);
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?
+        }
+        Algorithm::None => unreachable!(),
+    };
+
+    if compressed.size() >= data.size() {
+        println!("Compression didn't shrink the payload, storing it as-is");
+        return Ok(None);
+    }
+
+    println!(
+        "Compressed {} bytes into {} with {:?} level {} ({:.1}%)",
+        data.size(),
+        compressed.size(),
+        algorithm,
+        level,
+        compressed.size() as f64 * 100.0 / data.size() as f64
+    );
+
+    let original_len = data.size();
+    Ok(Some((
+        compressed,
+        Compression {
+            algorithm,
+            level,
+            original_len,
+        },
+    )))
+}
+
+/// Reverses `compress`, streaming the original payload into `out`.
+///
+/// # Arguments
+/// * `data` - The compressed payload.
+/// * `compression` - The parameters recorded in the header.
+/// * `out` - Where the original payload is written.
+pub fn decompress(
+    data: &mut Spool,
+    compression: &Compression,
+    out: &mut impl Write,
+) -> anyhow::Result<()> {
+    // Never inflate past the recorded length, so a corrupted stream can't fill the disk
+    let limit = compression.original_len + 1;
+    let mut input = data.reader()?;
+    let written = match compression.algorithm {
+        Algorithm::Zstd => io::copy(
+            &mut zstd::stream::read::Decoder::new(input)?.take(limit),
+            out,
+        )?,
+        Algorithm::Xz => io::copy(&mut xz2::read::XzDecoder::new(input).take(limit), out)?,
+        Algorithm::None => {
+            io::copy(&mut input, out)?;
+            return Ok(());
+        }
+    };
+
+    if written != compression.original_len {
+        bail!(
+            "Decompressed {} bytes but {} were expected",
+            written,
+            compression.original_len
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Text-like bytes that compress well.
+    fn compressible(len: usize) -> Vec<u8> {
+        b"the quick brown fox jumps over the lazy dog "
+            .iter()
+            .cycle()
+            .zip(0..len)
+            .map(|(&byte, i)| if i % 97 == 0 { b'\n' } else { byte })
+            .collect()
+    }
+
+    /// Pseudo-random bytes that don't compress at all.
+    fn incompressible(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 32) as u8
+            })
+            .collect()
+    }
+
+    fn spool(data: &[u8]) -> Spool {
+        let mut spool = Spool::new().unwrap();
+        spool.write_all(data).unwrap();
+        spool
+    }
+
+    fn round_trip(algorithm: Algorithm) {
+        let data = compressible(300_000);
+        let (mut compressed, compression) =
+            compress(&mut spool(&data), algorithm, algorithm.default_level())
+                .unwrap()
+                .expect("compression was skipped");
+        assert_eq!(compression.algorithm, algorithm);
+        assert_eq!(compression.original_len, data.len() as u64);
+        assert!(compressed.size() < data.len() as u64 / 10);
+
+        let mut out = Vec::new();
+        decompress(&mut compressed, &compression, &mut out).unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn zstd_round_trips() {
+        round_trip(Algorithm::Zstd);
+    }
+
+    #[test]
+    fn xz_round_trips() {
+        round_trip(Algorithm::Xz);
+    }
+
+    #[test]
+    fn incompressible_payloads_are_left_alone() {
+        // The probe only looks at the first 256 KiB, which decides for the rest
+        let mut data = incompressible(PROBE_LEN);
+        data.extend(compressible(PROBE_LEN));
+        assert!(compress(&mut spool(&data), Algorithm::Zstd, 19)
+            .unwrap()
+            .is_none());
+
+        let mut data = compressible(PROBE_LEN);
+        data.extend(incompressible(PROBE_LEN));
+        assert!(compress(&mut spool(&data), Algorithm::Zstd, 19)
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn streams_inflating_past_the_recorded_length_are_rejected() {
+        let data = compressible(100_000);
+        let (mut compressed, mut compression) = compress(&mut spool(&data), Algorithm::Zstd, 3)
+            .unwrap()
+            .unwrap();
+
+        // A header claiming less than the stream holds stops the output one byte past it
+        compression.original_len = 1000;
+        let mut out = Vec::new();
+        assert!(decompress(&mut compressed, &compression, &mut out).is_err());
+        assert_eq!(out.len(), 1001);
+        assert_eq!(out, data[..1001]);
+    }
+}
@@ -0,0 +1,677 @@
+use std::io::Write;
+
+use aes_gcm::Aes256Gcm;
+use anyhow::{anyhow, bail};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng};
+use chacha20poly1305::ChaCha20Poly1305;
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+use crate::spool::{self, Spool};
+
+/// Length of the random salt fed to Argon2id.
+pub const SALT_LEN: usize = 16;
+
+/// Length of the AEAD nonce. Both ciphers use 96-bit nonces.
+pub const NONCE_LEN: usize = 12;
+
+/// Length of the authentication tag both ciphers append.
+const TAG_LEN: usize = 16;
+
+/// Length of a wrapped file key: the 32-byte key plus a 16-byte authentication tag.
+pub const WRAPPED_KEY_LEN: usize = 32 + TAG_LEN;
+
+/// Plaintext bytes sealed per segment. The payload is encrypted segment by segment so
+/// it can be streamed, each segment carrying its own authentication tag.
+pub const SEGMENT_LEN: u32 = 64 * 1024;
+
+/// Default Argon2id memory cost in KiB (64 MiB).
+const DEFAULT_MEMORY_KIB: u32 = 64 * 1024;
+
+/// Default Argon2id number of passes.
+const DEFAULT_ITERATIONS: u32 = 3;
+
+/// Default Argon2id degree of parallelism.
+const DEFAULT_PARALLELISM: u32 = 4;
+
+/// Upper bound on the memory cost accepted from a header (4 GiB), so a tampered
+/// video can't make the decoder allocate unbounded memory.
+pub const MAX_MEMORY_KIB: u32 = 4 * 1024 * 1024;
+
+/// Upper bound on the number of passes accepted from a header.
+pub const MAX_ITERATIONS: u32 = 64;
+
+/// Prefix of a textual recipient (public) key.
+const PUBLIC_KEY_PREFIX: &str = "isrs-pk-";
+
+/// Prefix of a textual identity (secret) key.
+const SECRET_KEY_PREFIX: &str = "ISRS-SECRET-KEY-";
+
+/// Domain separation string for the key wrapping HKDF.
+const WRAP_INFO: &[u8] = b"infinite-storage x25519 file key";
+
+/// Authenticated ciphers available for the payload.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cipher {
+    /// ChaCha20-Poly1305: fast everywhere, no hardware support needed.
+    ChaCha20Poly1305,
+    /// AES-256-GCM: fastest on CPUs with AES instructions.
+    Aes256Gcm,
+}
+
+/// Argon2id parameters used to derive the key from the passphrase.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Kdf {
+    /// Memory cost in KiB.
+    pub memory_kib: u32,
+    /// Number of passes over the memory.
+    pub iterations: u32,
+    /// Degree of parallelism.
+    pub parallelism: u32,
+    /// Random salt, unique to every video.
+    pub salt: [u8; SALT_LEN],
+}
+
+/// The file key wrapped for a single recipient.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stanza {
+    /// Ephemeral X25519 public key, combined with the recipient's secret key to unwrap.
+    pub ephemeral: [u8; 32],
+    /// The file key, sealed with ChaCha20-Poly1305 under the shared secret.
+    pub wrapped_key: [u8; WRAPPED_KEY_LEN],
+}
+
+/// Where the key sealing the payload comes from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum KeySource {
+    /// Derived from a passphrase with Argon2id.
+    Passphrase(Kdf),
+    /// A random file key, wrapped for every recipient's X25519 public key.
+    Recipients(Vec<Stanza>),
+}
+
+/// Encryption parameters recorded in the header so the decoder can decrypt the payload.
+/// None of them are secret: without the passphrase or an identity they reveal nothing about the data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Encryption {
+    /// Cipher the payload was sealed with.
+    pub cipher: Cipher,
+    /// Where the key comes from.
+    pub key_source: KeySource,
+    /// Random nonce, unique to every video. Each segment's nonce is derived from it.
+    pub nonce: [u8; NONCE_LEN],
+    /// Plaintext bytes per sealed segment.
+    pub segment_len: u32,
+}
+
+/// Encrypts the payload with a key derived from `passphrase`.
+///
+/// A fresh salt and nonce are drawn for every call, so embedding the same data twice
+/// with the same passphrase gives unrelated ciphertexts. The payload is sealed in
+/// segments of `SEGMENT_LEN` bytes, each followed by its authentication tag.
+///
+/// # Arguments
+/// * `data` - The payload.
+/// * `cipher` - The cipher to seal the payload with.
+/// * `passphrase` - The passphrase to derive the key from.
+///
+/// # Returns
+/// The ciphertext and the parameters needed to decrypt it.
+pub fn encrypt_with_passphrase(
+    data: &mut Spool,
+    cipher: Cipher,
+    passphrase: &str,
+) -> anyhow::Result<(Spool, Encryption)> {
+    if passphrase.is_empty() {
+        bail!("The passphrase can't be empty");
+    }
+
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let kdf = Kdf {
+        memory_kib: DEFAULT_MEMORY_KIB,
+        iterations: DEFAULT_ITERATIONS,
+        parallelism: DEFAULT_PARALLELISM,
+        salt,
+    };
+
+    let key = derive_key(passphrase, &kdf)?;
+    let (sealed, nonce) = seal(cipher, &key, data)?;
+
+    println!("Encrypted payload with {:?}", cipher);
+
+    Ok((
+        sealed,
+        Encryption {
+            cipher,
+            key_source: KeySource::Passphrase(kdf),
+            nonce,
+            segment_len: SEGMENT_LEN,
+        },
+    ))
+}
+
+/// Encrypts the payload with a random file key, wrapped for every recipient.
+///
+/// Each recipient gets a stanza holding a fresh ephemeral X25519 public key and the
+/// file key sealed under the shared secret, so any one of them can decrypt alone.
+///
+/// # Arguments
+/// * `data` - The payload.
+/// * `cipher` - The cipher to seal the payload with.
+/// * `recipients` - The recipients' public keys.
+///
+/// # Returns
+/// The ciphertext and the parameters needed to decrypt it.
+pub fn encrypt_to_recipients(
+    data: &mut Spool,
+    cipher: Cipher,
+    recipients: &[PublicKey],
+) -> anyhow::Result<(Spool, Encryption)> {
+    if recipients.is_empty() {
+        bail!("At least one recipient is needed");
+    }
+
+    let mut file_key = [0u8; 32];
+    OsRng.fill_bytes(&mut file_key);
+
+    let stanzas = recipients
+        .iter()
+        .map(|recipient| wrap_key(&file_key, recipient))
+        .collect::<anyhow::Result<Vec<Stanza>>>()?;
+
+    let (sealed, nonce) = seal(cipher, &file_key, data)?;
+
+    println!(
+        "Encrypted payload with {:?} for {} recipient(s)",
+        cipher,
+        recipients.len()
+    );
+
+    Ok((
+        sealed,
+        Encryption {
+            cipher,
+            key_source: KeySource::Recipients(stanzas),
+            nonce,
+            segment_len: SEGMENT_LEN,
+        },
+    ))
+}
+
+/// Reverses `encrypt_with_passphrase`, verifying the authentication tag.
+///
+/// # Arguments
+/// * `data` - The ciphertext, tags included.
+/// * `encryption` - The parameters recorded in the header.
+/// * `passphrase` - The passphrase the payload was encrypted with.
+///
+/// # Returns
+/// The original payload, or an error if the passphrase is wrong or the data was damaged.
+pub fn decrypt_with_passphrase(
+    data: &mut Spool,
+    encryption: &Encryption,
+    passphrase: &str,
+) -> anyhow::Result<Spool> {
+    let KeySource::Passphrase(kdf) = &encryption.key_source else {
+        bail!("The video isn't encrypted with a passphrase");
+    };
+
+    let key = derive_key(passphrase, kdf)?;
+    open(
+        encryption,
+        &key,
+        data,
+        "Decryption failed: wrong passphrase or damaged data",
+    )
+}
+
+/// Reverses `encrypt_to_recipients` with one recipient's identity.
+///
+/// # Arguments
+/// * `data` - The ciphertext, tags included.
+/// * `encryption` - The parameters recorded in the header.
+/// * `identity` - The secret key of one of the recipients.
+///
+/// # Returns
+/// The original payload, or an error if the identity isn't a recipient or the data was damaged.
+pub fn decrypt_with_identity(
+    data: &mut Spool,
+    encryption: &Encryption,
+    identity: &StaticSecret,
+) -> anyhow::Result<Spool> {
+    let KeySource::Recipients(stanzas) = &encryption.key_source else {
+        bail!("The video isn't encrypted to recipients");
+    };
+
+    // The stanzas don't say who they're for, so try each of them
+    let file_key = stanzas
+        .iter()
+        .find_map(|stanza| unwrap_key(stanza, identity))
+        .ok_or_else(|| anyhow!("This identity isn't one of the video's recipients"))?;
+
+    open(
+        encryption,
+        &file_key,
+        data,
+        "Decryption failed: damaged data",
+    )
+}
+
+/// Generates a new X25519 identity.
+///
+/// # Returns
+/// The identity, ready to be written to a key file, and its public key to share.
+pub fn generate_identity() -> (String, String) {
+    let secret = StaticSecret::random_from_rng(OsRng);
+    let public = PublicKey::from(&secret);
+
+    (
+        format!(
+            "{}{}",
+            SECRET_KEY_PREFIX,
+            to_hex(secret.as_bytes()).to_uppercase()
+        ),
+        format!("{}{}", PUBLIC_KEY_PREFIX, to_hex(public.as_bytes())),
+    )
+}
+
+/// Parses a recipient public key as printed by `keygen`.
+pub fn parse_recipient(text: &str) -> anyhow::Result<PublicKey> {
+    let hex = text.trim().strip_prefix(PUBLIC_KEY_PREFIX).ok_or_else(|| {
+        anyhow!(
+            "{:?} is not a recipient key ({}...)",
+            text,
+            PUBLIC_KEY_PREFIX
+        )
+    })?;
+    Ok(PublicKey::from(from_hex(hex)?))
+}
+
+/// Parses an identity file written by `keygen`. Lines starting with `#` are comments.
+pub fn parse_identity(text: &str) -> anyhow::Result<StaticSecret> {
+    let hex = text
+        .lines()
+        .map(str::trim)
+        .find_map(|line| line.strip_prefix(SECRET_KEY_PREFIX))
+        .ok_or_else(|| {
+            anyhow!(
+                "No {}... line found in the identity file",
+                SECRET_KEY_PREFIX
+            )
+        })?;
+    Ok(StaticSecret::from(from_hex(hex)?))
+}
+
+/// Seals `data` under `key` with a fresh random nonce, segment by segment.
+fn seal(
+    cipher: Cipher,
+    key: &[u8; 32],
+    data: &mut Spool,
+) -> anyhow::Result<(Spool, [u8; NONCE_LEN])> {
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+
+    // An empty payload still gets a single, empty segment
+    let segments = data.size().div_ceil(SEGMENT_LEN as u64).max(1);
+    if segments > u32::MAX as u64 {
+        bail!("The payload is too large to encrypt");
+    }
+
+    let mut input = data.reader()?;
+    let mut sealed = Spool::new()?;
+    for index in 0..segments {
+        let segment = spool::read_chunk(&mut input, SEGMENT_LEN as usize)?;
+        let segment_nonce = segment_nonce(&nonce, index as u32, index + 1 == segments).into();
+        let ciphertext = match cipher {
+            Cipher::ChaCha20Poly1305 => {
+                ChaCha20Poly1305::new(key.into()).encrypt(&segment_nonce, segment.as_slice())
+            }
+            Cipher::Aes256Gcm => {
+                Aes256Gcm::new(key.into()).encrypt(&segment_nonce, segment.as_slice())
+            }
+        }
+        .map_err(|_| anyhow!("Encryption failed"))?;
+        sealed.write_all(&ciphertext)?;
+    }
+
+    Ok((sealed, nonce))
+}
+
+/// Opens a payload sealed by `seal`, verifying the authentication tag of every segment.
+///
+/// # Arguments
+/// * `failure` - The error reported when a tag doesn't match.
+fn open(
+    encryption: &Encryption,
+    key: &[u8; 32],
+    data: &mut Spool,
+    failure: &'static str,
+) -> anyhow::Result<Spool> {
+    let open_segment = |nonce: [u8; NONCE_LEN], segment: &[u8]| {
+        match encryption.cipher {
+            Cipher::ChaCha20Poly1305 => {
+                ChaCha20Poly1305::new(key.into()).decrypt(&nonce.into(), segment)
+            }
+            Cipher::Aes256Gcm => Aes256Gcm::new(key.into()).decrypt(&nonce.into(), segment),
+        }
+        .map_err(|_| anyhow!(failure))
+    };
+
+    let mut input = data.reader()?;
+    let mut opened = Spool::new()?;
+
+    let sealed_len = encryption.segment_len as u64 + TAG_LEN as u64;
+    let segments = data.size().div_ceil(sealed_len).max(1);
+    for index in 0..segments {
+        let segment = spool::read_chunk(&mut input, sealed_len as usize)?;
+        let nonce = segment_nonce(&encryption.nonce, index as u32, index + 1 == segments);
+        opened.write_all(&open_segment(nonce, &segment)?)?;
+    }
+
+    Ok(opened)
+}
+
+/// Derives the nonce of a segment: the video's nonce with the segment index and a flag
+/// marking the final segment mixed into its last five bytes. The flag keeps a payload
+/// cut short at a segment boundary from passing as complete.
+fn segment_nonce(nonce: &[u8; NONCE_LEN], index: u32, last: bool) -> [u8; NONCE_LEN] {
+    let mut segment_nonce = *nonce;
+    for (byte, counter) in segment_nonce[NONCE_LEN - 5..NONCE_LEN - 1]
+        .iter_mut()
+        .zip(index.to_be_bytes())
+    {
+        *byte ^= counter;
+    }
+    segment_nonce[NONCE_LEN - 1] ^= last as u8;
+    segment_nonce
+}
+
+/// Derives a 256-bit key from the passphrase with Argon2id.
+fn derive_key(passphrase: &str, kdf: &Kdf) -> anyhow::Result<[u8; 32]> {
+    let params = Params::new(kdf.memory_kib, kdf.iterations, kdf.parallelism, Some(32))
+        .map_err(|e| anyhow!("Invalid key derivation parameters: {}", e))?;
+
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), &kdf.salt, &mut key)
+        .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+
+    Ok(key)
+}
+
+/// Derives the key wrapping the file key from an X25519 shared secret, bound to
+/// both public keys involved.
+fn wrapping_key(shared: &[u8; 32], ephemeral: &PublicKey, recipient: &PublicKey) -> [u8; 32] {
+    let mut salt = [0u8; 64];
+    salt[..32].copy_from_slice(ephemeral.as_bytes());
+    salt[32..].copy_from_slice(recipient.as_bytes());
+
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(WRAP_INFO, &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    key
+}
+
+/// Wraps the file key for a single recipient.
+fn wrap_key(file_key: &[u8; 32], recipient: &PublicKey) -> anyhow::Result<Stanza> {
+    let secret = EphemeralSecret::random_from_rng(OsRng);
+    let ephemeral = PublicKey::from(&secret);
+    let shared = secret.diffie_hellman(recipient);
+    if !shared.was_contributory() {
+        bail!("Refusing to encrypt to an invalid recipient key");
+    }
+
+    // Every wrapping key is used exactly once, so a zero nonce is safe
+    let key = wrapping_key(shared.as_bytes(), &ephemeral, recipient);
+    let wrapped = ChaCha20Poly1305::new(&key.into())
+        .encrypt(&[0u8; NONCE_LEN].into(), file_key.as_slice())
+        .map_err(|_| anyhow!("Key wrapping failed"))?;
+
+    Ok(Stanza {
+        ephemeral: ephemeral.to_bytes(),
+        wrapped_key: wrapped
+            .try_into()
+            .expect("A wrapped key is 32 bytes plus the tag"),
+    })
+}
+
+/// Unwraps the file key from a stanza, if it was made for `identity`.
+fn unwrap_key(stanza: &Stanza, identity: &StaticSecret) -> Option<[u8; 32]> {
+    let ephemeral = PublicKey::from(stanza.ephemeral);
+    let shared = identity.diffie_hellman(&ephemeral);
+    let key = wrapping_key(shared.as_bytes(), &ephemeral, &PublicKey::from(identity));
+
+    ChaCha20Poly1305::new(&key.into())
+        .decrypt(&[0u8; NONCE_LEN].into(), stanza.wrapped_key.as_slice())
+        .ok()?
+        .try_into()
+        .ok()
+}
+
+/// Formats bytes as a lowercase hexadecimal string.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parses a 64-digit hexadecimal key.
+pub fn from_hex(hex: &str) -> anyhow::Result<[u8; 32]> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        bail!("Keys must be 64 hexadecimal digits");
+    }
+
+    let mut bytes = [0u8; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| anyhow!("Keys must be 64 hexadecimal digits"))?;
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    /// Bytes spanning two full segments and a partial one.
+    fn sample() -> Vec<u8> {
+        (0..2 * SEGMENT_LEN as usize + 1000)
+            .map(|i| (i * 7 + i / 251) as u8)
+            .collect()
+    }
+
+    fn spool(data: &[u8]) -> Spool {
+        let mut spool = Spool::new().unwrap();
+        spool.write_all(data).unwrap();
+        spool
+    }
+
+    fn contents(spool: &mut Spool) -> Vec<u8> {
+        let mut data = Vec::new();
+        spool.reader().unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    /// Seals `data` like `encrypt_with_passphrase`, with a key derivation cheap enough
+    /// to run many times.
+    fn seal_with_passphrase(data: &[u8], cipher: Cipher, passphrase: &str) -> (Spool, Encryption) {
+        let kdf = Kdf {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+            salt: [7; SALT_LEN],
+        };
+        let key = derive_key(passphrase, &kdf).unwrap();
+        let (sealed, nonce) = seal(cipher, &key, &mut spool(data)).unwrap();
+        let encryption = Encryption {
+            cipher,
+            key_source: KeySource::Passphrase(kdf),
+            nonce,
+            segment_len: SEGMENT_LEN,
+        };
+        (sealed, encryption)
+    }
+
+    #[test]
+    fn passphrase_round_trips_with_both_ciphers() {
+        let data = sample();
+        for cipher in [Cipher::ChaCha20Poly1305, Cipher::Aes256Gcm] {
+            let (mut sealed, encryption) =
+                encrypt_with_passphrase(&mut spool(&data), cipher, "correct horse").unwrap();
+            assert_eq!(encryption.cipher, cipher);
+            assert_eq!(sealed.size(), data.len() as u64 + 3 * TAG_LEN as u64);
+
+            let mut opened =
+                decrypt_with_passphrase(&mut sealed, &encryption, "correct horse").unwrap();
+            assert_eq!(contents(&mut opened), data);
+        }
+    }
+
+    #[test]
+    fn empty_payload_round_trips() {
+        for cipher in [Cipher::ChaCha20Poly1305, Cipher::Aes256Gcm] {
+            let (mut sealed, encryption) = seal_with_passphrase(&[], cipher, "secret");
+            assert_eq!(sealed.size(), TAG_LEN as u64);
+
+            let mut opened = decrypt_with_passphrase(&mut sealed, &encryption, "secret").unwrap();
+            assert!(contents(&mut opened).is_empty());
+        }
+    }
+
+    #[test]
+    fn empty_passphrase_is_refused() {
+        assert!(encrypt_with_passphrase(&mut spool(b"data"), Cipher::Aes256Gcm, "").is_err());
+    }
+
+    #[test]
+    fn wrong_passphrase_is_rejected() {
+        for cipher in [Cipher::ChaCha20Poly1305, Cipher::Aes256Gcm] {
+            let (mut sealed, encryption) = seal_with_passphrase(&sample(), cipher, "secret");
+
+            let error = decrypt_with_passphrase(&mut sealed, &encryption, "Secret")
+                .err()
+                .expect("the wrong passphrase was accepted");
+            assert!(error.to_string().contains("wrong passphrase"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn tampered_segment_is_rejected() {
+        for cipher in [Cipher::ChaCha20Poly1305, Cipher::Aes256Gcm] {
+            let (mut sealed, encryption) = seal_with_passphrase(&sample(), cipher, "secret");
+            let mut bytes = contents(&mut sealed);
+            bytes[SEGMENT_LEN as usize + TAG_LEN + 10] ^= 1;
+
+            assert!(decrypt_with_passphrase(&mut spool(&bytes), &encryption, "secret").is_err());
+        }
+    }
+
+    #[test]
+    fn payload_cut_at_a_segment_boundary_is_rejected() {
+        let (mut sealed, encryption) = seal_with_passphrase(&sample(), Cipher::Aes256Gcm, "secret");
+        let bytes = contents(&mut sealed);
+        let cut = &bytes[..2 * (SEGMENT_LEN as usize + TAG_LEN)];
+
+        assert!(decrypt_with_passphrase(&mut spool(cut), &encryption, "secret").is_err());
+    }
+
+    #[test]
+    fn reordered_segments_are_rejected() {
+        let (mut sealed, encryption) =
+            seal_with_passphrase(&sample(), Cipher::ChaCha20Poly1305, "secret");
+        let bytes = contents(&mut sealed);
+        let sealed_len = SEGMENT_LEN as usize + TAG_LEN;
+        let mut swapped = bytes[sealed_len..2 * sealed_len].to_vec();
+        swapped.extend_from_slice(&bytes[..sealed_len]);
+        swapped.extend_from_slice(&bytes[2 * sealed_len..]);
+
+        assert!(decrypt_with_passphrase(&mut spool(&swapped), &encryption, "secret").is_err());
+    }
+
+    #[test]
+    fn every_recipient_can_decrypt() {
+        let data = sample();
+        let identities: Vec<StaticSecret> = (0..2)
+            .map(|_| parse_identity(&generate_identity().0).unwrap())
+            .collect();
+        let recipients: Vec<PublicKey> = identities.iter().map(PublicKey::from).collect();
+
+        for cipher in [Cipher::ChaCha20Poly1305, Cipher::Aes256Gcm] {
+            let (mut sealed, encryption) =
+                encrypt_to_recipients(&mut spool(&data), cipher, &recipients).unwrap();
+            let KeySource::Recipients(stanzas) = &encryption.key_source else {
+                panic!("expected recipient stanzas");
+            };
+            assert_eq!(stanzas.len(), 2);
+
+            for identity in &identities {
+                let mut opened = decrypt_with_identity(&mut sealed, &encryption, identity).unwrap();
+                assert_eq!(contents(&mut opened), data);
+            }
+        }
+    }
+
+    #[test]
+    fn wrong_identity_is_rejected() {
+        let (identity, recipient) = generate_identity();
+        let recipient = parse_recipient(&recipient).unwrap();
+        let (mut sealed, encryption) =
+            encrypt_to_recipients(&mut spool(&sample()), Cipher::Aes256Gcm, &[recipient]).unwrap();
+
+        let stranger = parse_identity(&generate_identity().0).unwrap();
+        let error = decrypt_with_identity(&mut sealed, &encryption, &stranger)
+            .err()
+            .expect("a stranger's identity was accepted");
+        assert!(error.to_string().contains("isn't one of"), "{}", error);
+
+        let identity = parse_identity(&identity).unwrap();
+        assert!(decrypt_with_identity(&mut sealed, &encryption, &identity).is_ok());
+    }
+
+    #[test]
+    fn tampered_recipient_segment_is_rejected() {
+        let identity = StaticSecret::random_from_rng(OsRng);
+        let recipients = [PublicKey::from(&identity)];
+        let (mut sealed, encryption) =
+            encrypt_to_recipients(&mut spool(&sample()), Cipher::ChaCha20Poly1305, &recipients)
+                .unwrap();
+        let mut bytes = contents(&mut sealed);
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x80;
+
+        assert!(decrypt_with_identity(&mut spool(&bytes), &encryption, &identity).is_err());
+    }
+
+    #[test]
+    fn tampered_wrapped_key_is_rejected() {
+        let identity = StaticSecret::random_from_rng(OsRng);
+        let recipients = [PublicKey::from(&identity)];
+        let (mut sealed, mut encryption) =
+            encrypt_to_recipients(&mut spool(b"data"), Cipher::Aes256Gcm, &recipients).unwrap();
+        let KeySource::Recipients(stanzas) = &mut encryption.key_source else {
+            panic!("expected recipient stanzas");
+        };
+        stanzas[0].wrapped_key[0] ^= 1;
+
+        assert!(decrypt_with_identity(&mut sealed, &encryption, &identity).is_err());
+    }
+
+    #[test]
+    fn keys_round_trip_through_text() {
+        let (identity, recipient) = generate_identity();
+        let identity = parse_identity(&format!("# created today\n{}\n", identity)).unwrap();
+
+        assert_eq!(
+            parse_recipient(&recipient).unwrap(),
+            PublicKey::from(&identity)
+        );
+        assert!(parse_recipient("isrs-pk-1234").is_err());
+        assert!(parse_recipient(&recipient.replace("isrs-pk-", "isrs-sig-")).is_err());
+        assert!(parse_identity("# no key here").is_err());
+    }
+}
@@ -0,0 +1,709 @@
+use std::io::{Read, Write};
+
+use anyhow::bail;
+
+use crate::spool::{self, Spool};
+
+/// Length of a Reed-Solomon codeword over GF(2^8), in bytes.
+pub const CODEWORD_LEN: usize = 255;
+
+/// Default number of parity bytes per codeword, giving the classic RS(255,223)
+/// which corrects up to 16 corrupted bytes in every codeword.
+pub const DEFAULT_PARITY: u8 = 32;
+
+/// Number of codewords interleaved together. Consecutive bytes of the output belong to
+/// different codewords, so a burst of damage (a smudged area of a frame) is spread thin.
+pub const DEFAULT_INTERLEAVE: u16 = 64;
+
+/// Reed-Solomon parameters recorded in the header so the decoder can reverse the encoding.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ecc {
+    /// Parity bytes per 255-byte codeword. Up to `parity / 2` byte errors can be corrected.
+    pub parity: u8,
+    /// Number of codewords interleaved together.
+    pub interleave: u16,
+    /// Length of the data before parity and padding were added.
+    pub data_len: u64,
+}
+
+impl Ecc {
+    /// Number of data bytes carried by each codeword.
+    pub fn data_per_codeword(&self) -> usize {
+        CODEWORD_LEN - self.parity as usize
+    }
+}
+
+/// Primitive polynomial x^8 + x^4 + x^3 + x^2 + 1 used to build GF(2^8).
+const PRIMITIVE: u16 = 0x11d;
+
+/// Exponent and logarithm tables for GF(2^8).
+/// The exponent table is doubled so products of two logs never need a modulo.
+struct Tables {
+    exp: [u8; 512],
+    log: [u8; 256],
+}
+
+const TABLES: Tables = build_tables();
+
+/// Builds the GF(2^8) lookup tables at compile time.
+const fn build_tables() -> Tables {
+    let mut exp = [0u8; 512];
+    let mut log = [0u8; 256];
+
+    let mut x: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = x as u8;
+        log[x as usize] = i as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= PRIMITIVE;
+        }
+        i += 1;
+    }
+    while i < 512 {
+        exp[i] = exp[i - 255];
+        i += 1;
+    }
+
+    Tables { exp, log }
+}
+
+fn gf_mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    TABLES.exp[TABLES.log[a as usize] as usize + TABLES.log[b as usize] as usize]
+}
+
+fn gf_div(a: u8, b: u8) -> u8 {
+    if a == 0 {
+        return 0;
+    }
+    TABLES.exp[(TABLES.log[a as usize] as usize + 255 - TABLES.log[b as usize] as usize) % 255]
+}
+
+fn gf_pow(a: u8, power: i32) -> u8 {
+    let exponent = (TABLES.log[a as usize] as i32 * power).rem_euclid(255);
+    TABLES.exp[exponent as usize]
+}
+
+fn gf_inverse(a: u8) -> u8 {
+    TABLES.exp[255 - TABLES.log[a as usize] as usize]
+}
+
+// Polynomials are stored with the highest degree coefficient first.
+
+fn poly_scale(p: &[u8], x: u8) -> Vec<u8> {
+    p.iter().map(|&c| gf_mul(c, x)).collect()
+}
+
+fn poly_add(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let len = p.len().max(q.len());
+    let mut r = vec![0u8; len];
+    for (i, &c) in p.iter().enumerate() {
+        r[i + len - p.len()] = c;
+    }
+    for (i, &c) in q.iter().enumerate() {
+        r[i + len - q.len()] ^= c;
+    }
+    r
+}
+
+fn poly_mul(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let mut r = vec![0u8; p.len() + q.len() - 1];
+    for (j, &qc) in q.iter().enumerate() {
+        for (i, &pc) in p.iter().enumerate() {
+            r[i + j] ^= gf_mul(pc, qc);
+        }
+    }
+    r
+}
+
+fn poly_eval(p: &[u8], x: u8) -> u8 {
+    let mut y = p[0];
+    for &c in &p[1..] {
+        y = gf_mul(y, x) ^ c;
+    }
+    y
+}
+
+/// Builds the generator polynomial for `parity` parity symbols.
+fn generator_poly(parity: usize) -> Vec<u8> {
+    let mut g = vec![1u8];
+    for i in 0..parity {
+        g = poly_mul(&g, &[1, gf_pow(2, i as i32)]);
+    }
+    g
+}
+
+/// Computes the parity bytes of a single codeword.
+fn encode_codeword(message: &[u8], generator: &[u8]) -> Vec<u8> {
+    let parity = generator.len() - 1;
+    let mut remainder = message.to_vec();
+    remainder.resize(message.len() + parity, 0);
+
+    // Synthetic division by the generator polynomial
+    for i in 0..message.len() {
+        let coef = remainder[i];
+        if coef != 0 {
+            for j in 1..generator.len() {
+                remainder[i + j] ^= gf_mul(generator[j], coef);
+            }
+        }
+    }
+
+    remainder[message.len()..].to_vec()
+}
+
+/// Calculates the syndromes of a codeword, padded with a leading zero.
+/// All-zero syndromes mean the codeword is intact.
+fn syndromes(codeword: &[u8], parity: usize) -> Vec<u8> {
+    let mut synd = vec![0u8; parity + 1];
+    for i in 0..parity {
+        synd[i + 1] = poly_eval(codeword, gf_pow(2, i as i32));
+    }
+    synd
+}
+
+/// Finds the error locator polynomial using the Berlekamp-Massey algorithm.
+fn find_error_locator(synd: &[u8], parity: usize) -> anyhow::Result<Vec<u8>> {
+    let mut err_loc = vec![1u8];
+    let mut old_loc = vec![1u8];
+    let shift = synd.len() - parity;
+
+    for i in 0..parity {
+        let k = i + shift;
+        let mut delta = synd[k];
+        for j in 1..err_loc.len() {
+            delta ^= gf_mul(err_loc[err_loc.len() - 1 - j], synd[k - j]);
+        }
+
+        old_loc.push(0);
+        if delta != 0 {
+            if old_loc.len() > err_loc.len() {
+                let new_loc = poly_scale(&old_loc, delta);
+                old_loc = poly_scale(&err_loc, gf_inverse(delta));
+                err_loc = new_loc;
+            }
+            err_loc = poly_add(&err_loc, &poly_scale(&old_loc, delta));
+        }
+    }
+
+    // Drop leading zero coefficients
+    let first = err_loc
+        .iter()
+        .position(|&c| c != 0)
+        .unwrap_or(err_loc.len());
+    let err_loc = err_loc[first..].to_vec();
+
+    if err_loc.is_empty() || (err_loc.len() - 1) * 2 > parity {
+        bail!("Too many errors to correct");
+    }
+
+    Ok(err_loc)
+}
+
+/// Finds the positions of the errors with a Chien search.
+fn find_errors(err_loc: &[u8], len: usize) -> anyhow::Result<Vec<usize>> {
+    let errs = err_loc.len() - 1;
+    let positions: Vec<usize> = (0..len)
+        .filter(|&i| poly_eval(err_loc, gf_pow(2, i as i32)) == 0)
+        .map(|i| len - 1 - i)
+        .collect();
+
+    if positions.len() != errs {
+        bail!("Could not locate all errors in the codeword");
+    }
+
+    Ok(positions)
+}
+
+/// Computes the error magnitudes with the Forney algorithm and applies them in place.
+fn correct_errata(codeword: &mut [u8], synd: &[u8], err_pos: &[usize]) {
+    let coef_pos: Vec<usize> = err_pos.iter().map(|&p| codeword.len() - 1 - p).collect();
+
+    // Errata locator polynomial from the known positions
+    let mut err_loc = vec![1u8];
+    for &i in &coef_pos {
+        err_loc = poly_mul(&err_loc, &poly_add(&[1], &[gf_pow(2, i as i32), 0]));
+    }
+
+    // Error evaluator polynomial: (S(x) * Lambda(x)) mod x^(errs + 1)
+    let synd_rev: Vec<u8> = synd.iter().rev().copied().collect();
+    let product = poly_mul(&synd_rev, &err_loc);
+    let err_eval = &product[product.len() - err_loc.len()..];
+
+    let x: Vec<u8> = coef_pos
+        .iter()
+        .map(|&p| gf_pow(2, -(255 - p as i32)))
+        .collect();
+
+    for (i, &xi) in x.iter().enumerate() {
+        let xi_inv = gf_inverse(xi);
+
+        // Formal derivative of the errata locator, evaluated at Xi^-1
+        let mut err_loc_prime = 1u8;
+        for (j, &xj) in x.iter().enumerate() {
+            if j != i {
+                err_loc_prime = gf_mul(err_loc_prime, 1 ^ gf_mul(xi_inv, xj));
+            }
+        }
+
+        let y = gf_mul(xi, poly_eval(err_eval, xi_inv));
+        codeword[err_pos[i]] ^= gf_div(y, err_loc_prime);
+    }
+}
+
+/// Corrects a single codeword in place.
+///
+/// # Returns
+/// The number of corrected bytes, or an error if the codeword is beyond repair.
+fn decode_codeword(codeword: &mut [u8], parity: usize) -> anyhow::Result<usize> {
+    let synd = syndromes(codeword, parity);
+    if synd.iter().all(|&s| s == 0) {
+        return Ok(0);
+    }
+
+    let err_loc: Vec<u8> = find_error_locator(&synd, parity)?
+        .into_iter()
+        .rev()
+        .collect();
+    let err_pos = find_errors(&err_loc, codeword.len())?;
+    correct_errata(codeword, &synd, &err_pos);
+
+    // Make sure the correction actually produced a valid codeword
+    if syndromes(codeword, parity).iter().any(|&s| s != 0) {
+        bail!("Codeword could not be corrected");
+    }
+
+    Ok(err_pos.len())
+}
+
+/// Checks that `parity` is a usable number of parity bytes.
+fn check_parity(parity: u8) -> anyhow::Result<()> {
+    if parity < 2 || parity >= CODEWORD_LEN as u8 || !parity.is_multiple_of(2) {
+        bail!(
+            "Parity must be an even number between 2 and 254, got {}",
+            parity
+        );
+    }
+    Ok(())
+}
+
+/// Splits `data` into codewords, adds their parity and interleaves them group by group.
+fn encode_codewords(data: &[u8], ecc: &Ecc, generator: &[u8]) -> Vec<u8> {
+    let k = ecc.data_per_codeword();
+
+    // Build every codeword
+    let codewords: Vec<Vec<u8>> = data
+        .chunks(k)
+        .map(|chunk| {
+            let mut codeword = chunk.to_vec();
+            codeword.resize(k, 0);
+            let parity_bytes = encode_codeword(&codeword, generator);
+            codeword.extend(parity_bytes);
+            codeword
+        })
+        .collect();
+
+    // Interleave codewords group by group
+    let mut encoded = Vec::with_capacity(codewords.len() * CODEWORD_LEN);
+    for group in codewords.chunks(ecc.interleave as usize) {
+        for i in 0..CODEWORD_LEN {
+            for codeword in group {
+                encoded.push(codeword[i]);
+            }
+        }
+    }
+
+    encoded
+}
+
+/// De-interleaves and corrects `codeword_count` codewords from the start of `encoded`.
+///
+/// # Returns
+/// The data bytes of every codeword, corrected where possible, the number of corrected
+/// bytes and the number of codewords that were beyond repair.
+fn decode_codewords(encoded: &[u8], ecc: &Ecc, codeword_count: usize) -> (Vec<u8>, usize, usize) {
+    let k = ecc.data_per_codeword();
+    let parity = ecc.parity as usize;
+
+    let mut data = Vec::with_capacity(codeword_count * k);
+    let mut corrected = 0;
+    let mut failed = 0;
+
+    let group_size = ecc.interleave as usize * CODEWORD_LEN;
+    for group in encoded[..codeword_count * CODEWORD_LEN].chunks(group_size) {
+        let width = group.len() / CODEWORD_LEN;
+
+        // De-interleave the group back into codewords
+        let mut codewords = vec![Vec::with_capacity(CODEWORD_LEN); width];
+        for (i, &byte) in group.iter().enumerate() {
+            codewords[i % width].push(byte);
+        }
+
+        for mut codeword in codewords {
+            match decode_codeword(&mut codeword, parity) {
+                Ok(count) => corrected += count,
+                Err(_) => failed += 1,
+            }
+            data.extend_from_slice(&codeword[..k]);
+        }
+    }
+
+    (data, corrected, failed)
+}
+
+/// Protects `data` with Reed-Solomon parity.
+///
+/// The data is split into codewords of `255 - parity` bytes (the last one zero padded),
+/// and codewords are interleaved in groups so bursts of errors hit many codewords lightly.
+///
+/// # Arguments
+/// * `data` - The data to protect.
+/// * `parity` - Number of parity bytes per codeword (2 to 254, even).
+///
+/// # Returns
+/// The encoded data and the parameters to record in the header.
+pub fn encode(data: &mut Spool, parity: u8) -> anyhow::Result<(Spool, Ecc)> {
+    check_parity(parity)?;
+
+    let ecc = Ecc {
+        parity,
+        interleave: DEFAULT_INTERLEAVE,
+        data_len: data.size(),
+    };
+    let generator = generator_poly(parity as usize);
+
+    // Encode one interleaved group at a time
+    let group_len = ecc.interleave as usize * ecc.data_per_codeword();
+    let mut input = data.reader()?;
+    let mut encoded = Spool::new()?;
+    loop {
+        let group = spool::read_chunk(&mut input, group_len)?;
+        if group.is_empty() {
+            break;
+        }
+        encoded.write_all(&encode_codewords(&group, &ecc, &generator))?;
+    }
+
+    println!(
+        "Reed-Solomon RS({},{}) applied: {} bytes -> {} bytes",
+        CODEWORD_LEN,
+        ecc.data_per_codeword(),
+        data.size(),
+        encoded.size()
+    );
+
+    Ok((encoded, ecc))
+}
+
+/// Number of data bytes a frame of `frame_bytes` bytes holds once protected
+/// frame by frame with `encode_frames`.
+pub fn frame_data_len(parity: u8, frame_bytes: usize) -> usize {
+    frame_bytes / CODEWORD_LEN * (CODEWORD_LEN - parity as usize)
+}
+
+/// Protects `data` with Reed-Solomon parity, frame by frame.
+///
+/// Each frame's share of `data` is encoded and interleaved on its own, so a frame can
+/// be corrected without the others and a lost frame doesn't take codewords of its
+/// neighbours down with it.
+///
+/// # Arguments
+/// * `data` - The data to protect, laid out in chunks of `frame_data_len` bytes per frame.
+/// * `parity` - Number of parity bytes per codeword (2 to 254, even).
+/// * `frame_bytes` - Bytes carried by each data frame.
+///
+/// # Returns
+/// The encoded data, with every frame but the last padded to `frame_bytes`, and the
+/// parameters to record in the header.
+pub fn encode_frames(
+    data: &mut Spool,
+    parity: u8,
+    frame_bytes: usize,
+) -> anyhow::Result<(Spool, Ecc)> {
+    check_parity(parity)?;
+
+    let codewords = frame_bytes / CODEWORD_LEN;
+    if codewords == 0 || codewords > u16::MAX as usize {
+        bail!(
+            "Frames of {} bytes can't hold whole Reed-Solomon codewords",
+            frame_bytes
+        );
+    }
+
+    let ecc = Ecc {
+        parity,
+        interleave: codewords as u16,
+        data_len: data.size(),
+    };
+    let generator = generator_poly(parity as usize);
+
+    let frame_data = frame_data_len(parity, frame_bytes);
+    let frames = data.size().div_ceil(frame_data as u64);
+    let mut input = data.reader()?;
+    let mut encoded = Spool::new()?;
+    for index in 0..frames {
+        let mut frame = encode_codewords(
+            &spool::read_chunk(&mut input, frame_data)?,
+            &ecc,
+            &generator,
+        );
+        if index + 1 < frames {
+            frame.resize(frame_bytes, 0);
+        }
+        encoded.write_all(&frame)?;
+    }
+
+    println!(
+        "Reed-Solomon RS({},{}) applied to each frame: {} bytes -> {} bytes",
+        CODEWORD_LEN,
+        ecc.data_per_codeword(),
+        data.size(),
+        encoded.size()
+    );
+
+    Ok((encoded, ecc))
+}
+
+/// Reverses `encode`, correcting errors along the way. Codewords are decoded one
+/// interleaved group at a time and streamed into `out`. Codewords beyond repair are
+/// passed on as read, for the integrity check to reject.
+///
+/// # Arguments
+/// * `encoded` - The encoded bytes as read from the video.
+/// * `ecc` - The parameters recorded in the header.
+/// * `out` - Where the original data is written.
+/// * `corrections` - Totals to add the corrections to.
+///
+/// # Returns
+/// Nothing, or an error if the encoded data is truncated or can't be written.
+pub fn decode(
+    encoded: &mut impl Read,
+    ecc: &Ecc,
+    out: &mut impl Write,
+    corrections: &mut Corrections,
+) -> anyhow::Result<()> {
+    let k = ecc.data_per_codeword();
+    let codeword_count = ecc.data_len.div_ceil(k as u64);
+
+    let mut remaining = ecc.data_len;
+    let mut decoded_codewords = 0;
+    while decoded_codewords < codeword_count {
+        let width = (codeword_count - decoded_codewords).min(ecc.interleave as u64) as usize;
+        let group = spool::read_chunk(encoded, width * CODEWORD_LEN)?;
+        if group.len() < width * CODEWORD_LEN {
+            bail!(
+                "Encoded data is truncated: expected {} bytes, got {}",
+                codeword_count * CODEWORD_LEN as u64,
+                decoded_codewords * CODEWORD_LEN as u64 + group.len() as u64
+            );
+        }
+
+        let (data, corrected, failed) = decode_codewords(&group, ecc, width);
+        corrections.corrected += corrected;
+        corrections.failed += failed;
+        corrections.codewords += width;
+        decoded_codewords += width as u64;
+
+        let len = remaining.min(data.len() as u64);
+        out.write_all(&data[..len as usize])?;
+        remaining -= len;
+    }
+
+    Ok(())
+}
+
+/// Running totals of the corrections made by `decode` and `decode_frame`, reported once
+/// everything has been decoded.
+#[derive(Debug, Default)]
+pub struct Corrections {
+    corrected: usize,
+    failed: usize,
+    codewords: usize,
+}
+
+impl Corrections {
+    /// Prints the totals.
+    pub fn report(&self) {
+        if self.corrected > 0 {
+            println!("Reed-Solomon corrected {} bytes", self.corrected);
+        }
+        if self.failed > 0 {
+            println!(
+                "{} of {} Reed-Solomon codewords were too damaged to correct",
+                self.failed, self.codewords
+            );
+        }
+    }
+}
+
+/// Reverses `encode_frames` on a single frame, correcting it on its own.
+/// Codewords beyond repair are passed on as read, for the layer above to reject.
+///
+/// # Arguments
+/// * `encoded` - The encoded bytes of the frame.
+/// * `ecc` - The parameters recorded in the header.
+/// * `corrections` - Totals to add this frame's corrections to.
+///
+/// # Returns
+/// The data bytes of the frame.
+pub fn decode_frame(encoded: &[u8], ecc: &Ecc, corrections: &mut Corrections) -> Vec<u8> {
+    let codeword_count = (encoded.len() / CODEWORD_LEN).min(ecc.interleave as usize);
+    let (data, corrected, failed) = decode_codewords(encoded, ecc, codeword_count);
+    corrections.corrected += corrected;
+    corrections.failed += failed;
+    corrections.codewords += codeword_count;
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sample_bytes;
+
+    fn spool(data: &[u8]) -> Spool {
+        let mut spool = Spool::new().unwrap();
+        spool.write_all(data).unwrap();
+        spool
+    }
+
+    fn contents(spool: &mut Spool) -> Vec<u8> {
+        let mut data = Vec::new();
+        spool.reader().unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    /// Flips `errors` distinct bytes of one codeword in an interleaved group.
+    fn corrupt(group: &mut [u8], codeword: usize, errors: usize) {
+        let width = group.len() / CODEWORD_LEN;
+        for i in 0..errors {
+            group[(i * 7 % CODEWORD_LEN) * width + codeword] ^= 0x5a;
+        }
+    }
+
+    /// Flips `errors` bytes of every codeword in the output of `encode`.
+    fn corrupt_all(encoded: &mut [u8], ecc: &Ecc, errors: usize) {
+        for group in encoded.chunks_mut(ecc.interleave as usize * CODEWORD_LEN) {
+            for codeword in 0..group.len() / CODEWORD_LEN {
+                corrupt(group, codeword, errors);
+            }
+        }
+    }
+
+    fn decode_all(encoded: &[u8], ecc: &Ecc) -> (Vec<u8>, Corrections) {
+        let mut corrections = Corrections::default();
+        let mut out = Vec::new();
+        decode(&mut &encoded[..], ecc, &mut out, &mut corrections).unwrap();
+        (out, corrections)
+    }
+
+    #[test]
+    fn decode_restores_clean_data() {
+        // Two full interleaved groups and a partial one
+        let data = sample_bytes(2 * 64 * 223 + 1000);
+        let (mut encoded, ecc) = encode(&mut spool(&data), 32).unwrap();
+
+        let (out, corrections) = decode_all(&contents(&mut encoded), &ecc);
+        assert_eq!(out, data);
+        assert_eq!(corrections.corrected, 0);
+        assert_eq!(corrections.failed, 0);
+    }
+
+    #[test]
+    fn decode_corrects_half_the_parity_in_every_codeword() {
+        for parity in [2, 16, 32] {
+            let data = sample_bytes(2 * 64 * 223 + 1000);
+            let (mut encoded, ecc) = encode(&mut spool(&data), parity).unwrap();
+            let mut encoded = contents(&mut encoded);
+            let errors = parity as usize / 2;
+            corrupt_all(&mut encoded, &ecc, errors);
+
+            let (out, corrections) = decode_all(&encoded, &ecc);
+            assert_eq!(out, data, "parity {}", parity);
+            assert_eq!(corrections.failed, 0);
+            assert_eq!(corrections.corrected, corrections.codewords * errors);
+        }
+    }
+
+    #[test]
+    fn decode_passes_through_codewords_beyond_repair() {
+        let data = sample_bytes(64 * 223 + 1000);
+        let (mut encoded, ecc) = encode(&mut spool(&data), 32).unwrap();
+        let mut encoded = contents(&mut encoded);
+        corrupt(&mut encoded[..64 * CODEWORD_LEN], 0, 17);
+
+        let (out, corrections) = decode_all(&encoded, &ecc);
+        assert_eq!(corrections.failed, 1);
+        assert_eq!(out.len(), data.len());
+        assert_ne!(out[..223], data[..223]);
+        assert_eq!(out[223..], data[223..]);
+    }
+
+    #[test]
+    fn decode_rejects_truncated_data() {
+        let data = sample_bytes(5000);
+        let (mut encoded, ecc) = encode(&mut spool(&data), 32).unwrap();
+        let encoded = contents(&mut encoded);
+
+        let mut corrections = Corrections::default();
+        let truncated = &encoded[..encoded.len() - 1];
+        assert!(decode(&mut &truncated[..], &ecc, &mut Vec::new(), &mut corrections).is_err());
+    }
+
+    #[test]
+    fn decode_frame_corrects_each_frame_on_its_own() {
+        let frame_bytes = 8 * CODEWORD_LEN + 10;
+        let frame_data = frame_data_len(32, frame_bytes);
+        let data = sample_bytes(3 * frame_data + 500);
+        let (mut encoded, ecc) = encode_frames(&mut spool(&data), 32, frame_bytes).unwrap();
+        let mut encoded = contents(&mut encoded);
+
+        let mut corrections = Corrections::default();
+        let mut out = Vec::new();
+        for frame in encoded.chunks_mut(frame_bytes) {
+            let codewords = frame.len() / CODEWORD_LEN;
+            let group = &mut frame[..codewords * CODEWORD_LEN];
+            for codeword in 0..codewords {
+                corrupt(group, codeword, 16);
+            }
+            out.extend(decode_frame(frame, &ecc, &mut corrections));
+        }
+        out.truncate(data.len());
+
+        assert_eq!(out, data);
+        assert_eq!(corrections.failed, 0);
+        assert_eq!(corrections.corrected, corrections.codewords * 16);
+    }
+
+    #[test]
+    fn decode_frame_passes_through_codewords_beyond_repair() {
+        let frame_bytes = 8 * CODEWORD_LEN;
+        let data = sample_bytes(2 * frame_data_len(32, frame_bytes));
+        let (mut encoded, ecc) = encode_frames(&mut spool(&data), 32, frame_bytes).unwrap();
+        let mut encoded = contents(&mut encoded);
+        corrupt(&mut encoded[frame_bytes..], 3, 17);
+
+        let mut corrections = Corrections::default();
+        let first = decode_frame(&encoded[..frame_bytes], &ecc, &mut corrections);
+        let second = decode_frame(&encoded[frame_bytes..], &ecc, &mut corrections);
+        assert_eq!(corrections.failed, 1);
+        assert_eq!(corrections.codewords, 16);
+        assert_eq!(first, data[..first.len()]);
+        assert_ne!(second, data[first.len()..]);
+        assert_eq!(second[..3 * 223], data[first.len()..first.len() + 3 * 223]);
+    }
+
+    #[test]
+    fn parity_must_be_even_and_in_range() {
+        for parity in [0, 1, 3, 255] {
+            assert!(
+                encode(&mut spool(b"data"), parity).is_err(),
+                "parity {}",
+                parity
+            );
+        }
+    }
+}
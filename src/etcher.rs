@@ -1,213 +1,225 @@
-use std::{fs, thread, vec};
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::sync::mpsc::sync_channel;
+use std::{thread, vec};
+
+use anyhow::{anyhow, bail, Error}; //anyhow::Error::msg("My err");
+
+use crate::bits::{BitReader, Bits};
+use crate::ecc;
+use crate::finder::{self, Transform};
+use crate::fountain;
+use crate::header::{self, Header};
+use crate::source::{EmbedSource, Pixels};
+use crate::settings::{Data, OutputMode, Settings, RESOLUTIONS};
+use crate::spool::{self, Spool};
+use crate::timer::Timer;
+use crate::video::{self, Backend, Encoder, Frame, FrameSource};
 
-use anyhow::{anyhow, Error}; //anyhow::Error::msg("My err");
+/// Block size, in pixels per side, used for the instruction frames.
+const INSTRUCTION_SIZE: i32 = 5;
 
-use opencv::core::Mat;
-use opencv::prelude::*;
-use opencv::videoio::{VideoCapture, VideoWriter, CAP_ANY};
+/// Luma at or above which a black-and-white block reads as white, used for the instruction
+/// frames and for videos without calibration frames.
+const DEFAULT_THRESHOLD: u8 = 127;
 
-use crate::source::EmbedSource;
-use crate::settings::{Data, OutputMode, Settings};
-use crate::timer::Timer;
+/// Number of bits in the header strip of every data frame: the frame index (u32) followed
+/// by the CRC32 of the units the frame holds.
+const STRIP_BITS: usize = 64;
 
-/// Reads bytes from a file specified by `path`.
-///
-/// # Arguments
-/// * `path` - The file path to read the bytes from.
-///
-/// # Returns
-/// A vector of bytes if successful, or an error if the file is empty or cannot be read.
-pub fn rip_bytes(path: &str) -> anyhow::Result<Vec<u8>> {
-    let byte_data: Vec<u8> = fs::read(path)?;
+/// Number of copies of the header strip etched into every data frame. They are decoded
+/// by a majority vote, so a damaged block in one copy doesn't misplace the frame.
+const STRIP_COPIES: usize = 3;
 
-    if byte_data.is_empty() {
-        return Err(anyhow!(
-            "Empty files cannot be embedded! File names are not retained, so it's pointless anyway"
-        ));
-    }
-    println!("Bytes ripped successfully");
-    println!("Byte length: {}", byte_data.len());
+/// Number of blocks reserved for the header strip at the start of every data frame.
+const STRIP_BLOCKS: usize = STRIP_BITS * STRIP_COPIES;
 
-    Ok(byte_data)
-}
+/// Number of frames queued per worker thread while embedding or dislodging, on the way in
+/// and on the way out. Bounds the memory the frame pipelines use.
+const QUEUED_FRAMES: usize = 2;
 
-/// Converts a vector of bytes into a vector of binary bits (`true` for 1, `false` for 0).
+/// Splits bytes into symbols of `bits` bits each, most significant bits first.
+/// The last symbol is padded with zero bits when the data doesn't divide evenly.
 ///
 /// # Arguments
-/// * `byte_data` - A vector of bytes to convert.
+/// * `byte_data` - The bytes to split.
+/// * `bits` - The number of bits per symbol (2 or 3 for the gray modes).
 ///
 /// # Returns
-/// A vector of binary bits or an error if conversion fails.
-pub fn rip_binary(byte_data: Vec<u8>) -> anyhow::Result<Vec<bool>> {
-    let mut binary_data: Vec<bool> = Vec::new();
-
-    for byte in byte_data {
-        // Convert the byte to a binary string representation.
-        let mut bits: String = format!("{:b}", byte);
-        let missing_0 = 8 - bits.len();
-
-        // Pad with leading zeros to ensure 8 bits.
-        for _ in 0..missing_0 {
-            bits.insert(0, '0');
-        }
-
-        // Convert each bit into a boolean and append to the result.
-        for bit in bits.chars() {
-            binary_data.push(bit == '1');
-        }
+/// A vector of symbols, each in `0..2^bits`.
+fn rip_symbols(byte_data: &[u8], bits: usize) -> Vec<u8> {
+    let mut symbols: Vec<u8> = Vec::with_capacity((byte_data.len() * 8).div_ceil(bits));
+    let mut reader = BitReader::new(byte_data);
+    while let Some(symbol) = reader.read_bits(bits) {
+        symbols.push(symbol as u8);
     }
-    println!("Binary ripped successfully!");
-    Ok(binary_data)
+
+    symbols
 }
 
-/// Converts a vector of `u32` integers into binary representation as a vector of `bool`.
+/// Translates symbols back into bytes. Padding bits that don't complete a byte are dropped.
 ///
 /// # Arguments
-/// * `bytes` - A vector of `u32` integers to convert.
+/// * `symbols` - The symbols read from a frame.
+/// * `bits` - The number of bits per symbol.
 ///
 /// # Returns
-/// A vector of binary bits or an error if conversion fails.
-pub fn rip_binary_u32(bytes: Vec<u32>) -> anyhow::Result<Vec<bool>> {
-    let mut binary_data: Vec<bool> = Vec::new();
-
-    for byte in bytes {
-        // Convert the `u32` value to binary.
-        let mut bits = format!("{:b}", byte);
-        let missing_0 = 32 - bits.len();
+/// A vector of bytes.
+fn translate_symbols(symbols: Vec<u8>, bits: usize) -> anyhow::Result<Vec<u8>> {
+    let mut binary_data = Bits::with_capacity(symbols.len() * bits);
+    for symbol in symbols {
+        binary_data.push_bits(symbol as u32, bits);
+    }
 
-        // Pad with leading zeros to ensure 32 bits.
-        for _ in 0..missing_0 {
-            bits.insert(0, '0');
-        }
+    Ok(binary_data.into_bytes())
+}
 
-        // Convert each bit into a boolean and append to the result.
-        for bit in bits.chars() {
-            binary_data.push(bit == '1');
-        }
+/// Maps a symbol to the gray level that represents it. Levels are Gray-coded, so
+/// neighbouring levels differ by a single bit and mistaking one for the next costs one bit.
+fn symbol_to_level(symbol: u8) -> usize {
+    let mut level = symbol;
+    let mut shift = symbol >> 1;
+    while shift > 0 {
+        level ^= shift;
+        shift >>= 1;
     }
+    level as usize
+}
 
-    Ok(binary_data)
+/// Maps a gray level back to the symbol it represents.
+fn level_to_symbol(level: usize) -> u8 {
+    (level ^ (level >> 1)) as u8
 }
 
-/// Translates binary data (as `bool` vector) back into bytes.
+/// Nominal brightness of each gray level, evenly spread from black to white.
 ///
 /// # Arguments
-/// * `binary_data` - A vector of binary bits (`bool`).
-///
-/// # Returns
-/// A vector of bytes.
-fn translate_u8(binary_data: Vec<bool>) -> anyhow::Result<Vec<u8>> {
-    let mut buffer: Vec<bool> = Vec::new();
-    let mut byte_data: Vec<u8> = Vec::new();
-
-    for bit in binary_data {
-        buffer.push(bit);
+/// * `bits` - The number of bits per block (2 for 4 levels, 3 for 8 levels).
+fn gray_levels(bits: usize) -> Vec<u8> {
+    let count = 1 << bits;
+    (0..count).map(|i| (i * 255 / (count - 1)) as u8).collect()
+}
 
-        if buffer.len() == 8 {
-            // Convert 8 bits into a single byte.
-            let byte: u8 = buffer.iter().fold(0u8, |v, b| (v << 1) + (*b as u8));
-            byte_data.push(byte);
-            buffer.clear();
-        }
+/// The palette of the palette modes. Four colors are the corners of a regular tetrahedron
+/// inside the RGB cube and eight colors are the cube's corners, so every pair of colors is as
+/// far apart as possible and also differs in luma, which chroma subsampling leaves intact.
+/// In the eight color palette the symbol's bits are the red, green and blue channels, so
+/// mistaking a single channel costs a single bit.
+///
+/// # Arguments
+/// * `bits` - The number of bits per block (2 for 4 colors, 3 for 8 colors).
+fn palette_colors(bits: usize) -> Vec<[u8; 3]> {
+    match bits {
+        2 => vec![[0, 0, 0], [255, 255, 0], [255, 0, 255], [0, 255, 255]],
+        _ => (0..8u8)
+            .map(|i| [(i >> 2 & 1) * 255, (i >> 1 & 1) * 255, (i & 1) * 255])
+            .collect(),
     }
-
-    Ok(byte_data)
 }
 
-/// Translates binary data (as `bool` vector) back into `u32` values.
+/// The RGB color etched for every symbol of a gray or palette mode.
 ///
 /// # Arguments
-/// * `binary_data` - A vector of binary bits (`bool`).
+/// * `out_mode` - The output mode.
 ///
 /// # Returns
-/// A vector of `u32` integers.
-fn translate_u32(binary_data: Vec<bool>) -> anyhow::Result<Vec<u32>> {
-    let mut buffer: Vec<bool> = Vec::new();
-    let mut byte_data: Vec<u32> = Vec::new();
-
-    for bit in binary_data {
-        buffer.push(bit);
-
-        if buffer.len() == 32 {
-            // Convert 32 bits into a single `u32` value.
-            let u32_byte = buffer.iter().fold(0u32, |v, b| (v << 1) + (*b as u32));
-            byte_data.push(u32_byte);
-            buffer.clear();
+/// The colors indexed by symbol, or an empty vector for the other modes.
+fn symbol_colors(out_mode: OutputMode) -> Vec<[u8; 3]> {
+    let bits = out_mode.bits_per_block();
+    match out_mode {
+        OutputMode::Gray4 | OutputMode::Gray8 => {
+            let levels = gray_levels(bits);
+            (0..1u8 << bits)
+                .map(|symbol| [levels[symbol_to_level(symbol)]; 3])
+                .collect()
         }
+        OutputMode::Palette4 | OutputMode::Palette8 => palette_colors(bits),
+        OutputMode::Binary | OutputMode::Color => Vec::new(),
     }
-
-    Ok(byte_data)
 }
 
-/// Writes bytes to a file specified by `path`.
+/// Decision thresholds halfway between consecutive calibration levels. A block whose
+/// luma reaches the `n`th threshold is at least at level `n + 1`.
 ///
 /// # Arguments
-/// * `path` - The file path to write the bytes to.
-/// * `data` - The byte data to write.
-///
-/// # Returns
-/// Nothing if successful, or an error if writing fails.
-pub fn write_bytes(path: &str, data: Vec<u8>) -> anyhow::Result<()> {
-    fs::write(path, data)?;
-    println!("File written successfully");
-    Ok(())
+/// * `levels` - The brightness of each level, in increasing order.
+fn gray_thresholds(levels: &[u8]) -> Vec<u8> {
+    levels
+        .windows(2)
+        .map(|pair| (pair[0] as u16 + pair[1] as u16).div_ceil(2) as u8)
+        .collect()
 }
 
 /// Gets the average RGB values of a pixel block in an image.
 ///
 /// # Arguments
-/// * `frame` - The source frame containing the image.
+/// * `frame` - The source frame, which tells where the block lies on its image.
+/// * `pixels` - The pixels of the frame's image, as borrowed with `EmbedSource::pixels`.
 /// * `x` - The x-coordinate of the block.
 /// * `y` - The y-coordinate of the block.
 ///
 /// # Returns
-/// The average RGB values of the block as a vector.
-fn get_pixel(frame: &EmbedSource, x: i32, y: i32) -> Option<Vec<u8>> {
-    let mut r_list: Vec<u8> = Vec::new();
-    let mut g_list: Vec<u8> = Vec::new();
-    let mut b_list: Vec<u8> = Vec::new();
-
-    for i in 0..frame.size {
-        for j in 0..frame.size {
-            let bgr = frame
-                .image
-                .at_2d::<opencv::core::Vec3b>(y + i, x + j)
-                .unwrap();
-
-            r_list.push(bgr[2]);
-            g_list.push(bgr[1]);
-            b_list.push(bgr[0]);
-        }
-    }
-
-    let r_average = r_list.iter().map(|&x| x as usize).sum::<usize>() / r_list.len();
-    let g_average = g_list.iter().map(|&x| x as usize).sum::<usize>() / g_list.len();
-    let b_average = b_list.iter().map(|&x| x as usize).sum::<usize>() / b_list.len();
+/// The average RGB values of the block.
+fn get_pixel(frame: &EmbedSource, pixels: &Pixels, x: i32, y: i32) -> Option<[u8; 3]> {
+    // Altered frames only sample the middle of the block, as its edges blur into the neighbours
+    let (rows, columns) = if frame.transform == Transform::identity() {
+        (y..y + frame.size, x..x + frame.size)
+    } else {
+        transformed_block(frame, pixels, x, y)
+    };
 
-    Some(vec![r_average as u8, g_average as u8, b_average as u8])
+    pixels.average(rows, columns)
 }
 
-/// Etches a pixel block with the specified RGB values into an image.
+/// Maps a block onto an altered image, keeping the pixels whose centers fall within the
+/// middle half of the block. Blocks shrunk below a couple of pixels fall back to the
+/// single pixel under their center.
 ///
 /// # Arguments
-/// * `frame` - The source frame containing the image.
-/// * `rgb` - The RGB values to etch.
-/// * `x` - The x-coordinate of the block.
-/// * `y` - The y-coordinate of the block.
+/// * `frame` - The source frame, whose transform maps the block onto its image.
+/// * `pixels` - The pixels of the frame's image.
+/// * `x` - The x-coordinate of the block on the original frame.
+/// * `y` - The y-coordinate of the block on the original frame.
 ///
 /// # Returns
-/// Nothing if successful, or an error if the operation fails.
-fn etch_pixel(frame: &mut EmbedSource, rgb: Vec<u8>, x: i32, y: i32) -> anyhow::Result<()> {
-    for i in 0..frame.size {
-        for j in 0..frame.size {
-            let bgr = frame.image.at_2d_mut::<opencv::core::Vec3b>(y + i, x + j)?;
-            bgr[2] = rgb[0];
-            bgr[1] = rgb[1];
-            bgr[0] = rgb[2];
-        }
-    }
+/// The ranges of image rows and columns to sample.
+fn transformed_block(
+    frame: &EmbedSource,
+    pixels: &Pixels,
+    x: i32,
+    y: i32,
+) -> (Range<i32>, Range<i32>) {
+    let size = frame.size as f64;
+    let (x, y) = (x as f64, y as f64);
+    let transform = &frame.transform;
+
+    let (center_x, center_y) = transform.apply(x + size / 2.0, y + size / 2.0);
+    let (left, _) = transform.apply(x, y + size / 2.0);
+    let (right, _) = transform.apply(x + size, y + size / 2.0);
+    let (_, top) = transform.apply(x + size / 2.0, y);
+    let (_, bottom) = transform.apply(x + size / 2.0, y + size);
+
+    let span = |center: f64, reach: f64, limit: i32| -> Range<i32> {
+        let first = (center - reach - 0.5).ceil() as i32;
+        let last = (center + reach - 0.5).floor() as i32;
+        let span = if first <= last {
+            first..last + 1
+        } else {
+            center as i32..center as i32 + 1
+        };
+        span.start.clamp(0, limit - 1)..span.end.clamp(1, limit)
+    };
 
-    Ok(())
+    (
+        span(center_y, (bottom - top).abs() / 4.0, pixels.height()),
+        span(center_x, (right - left).abs() / 4.0, pixels.width()),
+    )
+}
+
+/// Computes the luma (perceived brightness) of an RGB value with the BT.601 weights.
+fn luma(rgb: &[u8]) -> u8 {
+    ((299 * rgb[0] as u32 + 587 * rgb[1] as u32 + 114 * rgb[2] as u32) / 1000) as u8
 }
 
 /// Embeds RGB data (color) into a video frame.
@@ -215,120 +227,143 @@ fn etch_pixel(frame: &mut EmbedSource, rgb: Vec<u8>, x: i32, y: i32) -> anyhow::
 ///
 /// # Arguments
 /// - `source`: A mutable reference to an `EmbedSource` object, which represents the video frame.
-/// - `data`: A vector of `u8` values representing the RGB data to be embedded.
+/// - `data`: The RGB bytes to be embedded.
 /// - `global_index`: A mutable reference to the current index in the `data` vector.
+/// - `blocks`: The blocks of the frame to fill, as returned by `data_blocks`.
 ///
 /// # Returns
 /// - `Ok(())` if the operation succeeds.
 /// - `Err(anyhow::Error)` if the index exceeds the size of the `data` vector.
 fn etch_color(
     source: &mut EmbedSource,
-    data: &Vec<u8>,
+    data: &[u8],
     global_index: &mut usize,
+    blocks: Range<usize>,
 ) -> anyhow::Result<()> {
     // Timer object to measure and log the execution time of this function.
     let _timer = Timer::new("Etching frame");
 
-    // Dimensions of the source frame
-    let width = source.actual_size.width; // Frame width
-    let height = source.actual_size.height; // Frame height
-    let size = source.size as usize; // Size of each pixel block to be processed
-
-    // Iterate over each block of pixels in the frame, stepping by the block size.
-    for y in (0..height).step_by(size) {
-        for x in (0..width).step_by(size) {
-            // Clone the current index to determine which RGB triplet to embed.
-            let local_index = global_index.clone();
-
-            // Extract the RGB triplet from the data vector.
-            let rgb = vec![
-                data[local_index],     // Red channel
-                data[local_index + 1], // Green channel
-                data[local_index + 2], // Blue channel
-            ];
-
-            // Embed the RGB value into the frame at the specified pixel block.
-            etch_pixel(source, rgb, x, y).unwrap();
-
-            // Increment the global index to move to the next RGB triplet.
-            *global_index += 3;
-
-            // If the index exceeds the length of the data, return an error.
-            if *global_index + 2 >= data.len() {
-                return Err(Error::msg("Index beyond data"));
-            }
+    // Borrow the frame's pixels once, so every block is written straight into them.
+    let positions = block_positions(source, blocks);
+    let mut pixels = source.pixels_mut();
+
+    // Iterate over the frame's data blocks in raster order.
+    for (x, y) in positions {
+        // Copy the current index to determine which RGB triplet to embed.
+        let local_index = *global_index;
+
+        // Extract the RGB triplet from the data vector.
+        let rgb = [
+            data[local_index],     // Red channel
+            data[local_index + 1], // Green channel
+            data[local_index + 2], // Blue channel
+        ];
+
+        // Fill the block with the RGB value, a row of pixels at a time.
+        pixels.fill_block(x, y, rgb);
+
+        // Increment the global index to move to the next RGB triplet.
+        *global_index += 3;
+
+        // If the index exceeds the length of the data, return an error.
+        if *global_index + 2 >= data.len() {
+            return Err(Error::msg("Index beyond data"));
         }
     }
 
     // Return success if all RGB data is embedded without errors.
-    return Ok(());
+    Ok(())
 }
 
-/// Embeds binary data (black-and-white) into a video frame. Each boolean value
-/// in the `data` vector corresponds to a pixel where `true` represents white
-/// (255 brightness) and `false` represents black (0 brightness).
+/// Embeds binary data (black-and-white) into a video frame. Each bit read from
+/// `data` corresponds to a block where `1` represents white (255 brightness)
+/// and `0` represents black (0 brightness).
 ///
 /// # Arguments
 /// - `source`: A mutable reference to an `EmbedSource` object, which represents
 ///   the video frame where the data is being embedded.
-/// - `data`: A vector of boolean values that represents the binary data to embed
-///   (1 = white, 0 = black).
-/// - `global_index`: A mutable reference to the current index within the `data`
-///   vector that indicates the next bit to be embedded.
+/// - `data`: A reader over the packed bits to embed (1 = white, 0 = black),
+///   positioned at the next bit to be embedded.
+/// - `blocks`: The blocks of the frame to fill, in raster order.
 ///
 /// # Returns
 /// - `Ok(())` if the data was successfully embedded.
-/// - `Err(anyhow::Error)` if an error occurs, such as exceeding the bounds of `data`.
+/// - `Err(anyhow::Error)` if an error occurs, such as running out of `data`.
 fn etch_bw(
     source: &mut EmbedSource, // Frame source to embed data into
-    data: &Vec<bool>,         // Binary data to embed (true = white, false = black)
-    global_index: &mut usize, // Current index in the data vector
+    data: &mut BitReader,     // Binary data to embed (1 = white, 0 = black)
+    blocks: Range<usize>,     // Blocks of the frame to fill
 ) -> anyhow::Result<()> {
     // Timer to track and log the execution time of the etching operation
     let _timer = Timer::new("Etching frame");
 
-    // Dimensions of the source frame (width and height)
-    let width = source.actual_size.width; // Frame width
-    let height = source.actual_size.height; // Frame height
-
-    // The size of each pixel block (used to step through the frame in increments)
-    let size = source.size as usize; // Size of pixel blocks for etching
-
-    // Iterate over the frame's pixels in steps, ensuring that we process each pixel block
-    for y in (0..height).step_by(size) {
-        // Loop through the vertical pixels
-        for x in (0..width).step_by(size) {
-            // Loop through the horizontal pixels
-            // Clone the global index to determine which bit of data to use
-            let local_index = global_index.clone();
-
-            // Determine the brightness for the current pixel
-            // 255 (white) for `true` (1) and 0 (black) for `false` (0)
-            let brightness = if data[local_index] == true {
-                255 // White pixel (bit is 1)
-            } else {
-                0 // Black pixel (bit is 0)
-            };
+    // Borrow the frame's pixels once, so every block is written straight into them
+    let positions = block_positions(source, blocks);
+    let mut pixels = source.pixels_mut();
 
-            // Create an RGB value where R, G, and B channels have the same brightness
-            let rgb = vec![brightness, brightness, brightness];
+    // Iterate over the requested blocks of the frame, in raster order
+    for (x, y) in positions {
+        // Take the next bit of data
+        let Some(bit) = data.next() else {
+            return Err(Error::msg("Index beyond data"));
+        };
 
-            // Call the `etch_pixel` function to embed the RGB value at the specified (x, y) position
-            // This will place the current pixel in the frame.
-            etch_pixel(source, rgb, x, y).unwrap(); // Using unwrap here assumes success; consider proper error handling
+        // Determine the brightness for the current pixel
+        // 255 (white) for `true` (1) and 0 (black) for `false` (0)
+        let brightness = if bit {
+            255 // White pixel (bit is 1)
+        } else {
+            0 // Black pixel (bit is 0)
+        };
 
-            // Increment the global index to move to the next bit in the data vector
-            *global_index += 1;
+        // Fill the block with a gray where R, G, and B channels have the same brightness
+        pixels.fill_block(x, y, [brightness; 3]);
 
-            // If the global index exceeds the length of the data, return an error
-            if *global_index >= data.len() {
-                return Err(Error::msg("Index beyond data"));
-            }
+        // If every bit of the data was embedded, return an error
+        if data.remaining() == 0 {
+            return Err(Error::msg("Index beyond data"));
         }
     }
 
     // Return Ok if the function successfully completes without errors
-    return Ok(());
+    Ok(())
+}
+
+/// Embeds gray-level or palette symbols into a video frame. Each symbol is written as a
+/// block filled with the color assigned to it.
+///
+/// # Arguments
+/// - `source`: A mutable reference to an `EmbedSource` object, which represents the video frame.
+/// - `data`: The symbols to embed, as produced by `rip_symbols`.
+/// - `global_index`: A mutable reference to the current index in the `data` vector.
+/// - `colors`: The RGB color of each symbol, as built by `symbol_colors`.
+/// - `blocks`: The blocks of the frame to fill, as returned by `data_blocks`.
+///
+/// # Returns
+/// - `Ok(())` if the operation succeeds.
+/// - `Err(anyhow::Error)` if the index exceeds the size of the `data` vector.
+fn etch_symbols(
+    source: &mut EmbedSource,
+    data: &[u8],
+    global_index: &mut usize,
+    colors: &[[u8; 3]],
+    blocks: Range<usize>,
+) -> anyhow::Result<()> {
+    let _timer = Timer::new("Etching frame");
+
+    let positions = block_positions(source, blocks);
+    let mut pixels = source.pixels_mut();
+    for (x, y) in positions {
+        pixels.fill_block(x, y, colors[data[*global_index] as usize]);
+
+        *global_index += 1;
+
+        if *global_index >= data.len() {
+            return Err(Error::msg("Index beyond data"));
+        }
+    }
+
+    Ok(())
 }
 
 /// Reads black-and-white (binary) data from a source image by sampling pixel values
@@ -339,57 +374,45 @@ fn etch_bw(
 /// * `current_frame` - The index of the current frame being processed.
 /// * `final_frame` - The index of the last frame to process. Used to determine if this is the final frame.
 /// * `final_bit` - The number of bits to retain in the final frame. Only used if `current_frame == final_frame`.
+/// * `threshold` - The luma at or above which a block reads as white.
+/// * `blocks` - The blocks of the frame to read, in raster order.
 ///
 /// # Returns
-/// * `anyhow::Result<Vec<bool>>` - A vector of boolean values representing the binary data
-///   extracted from the image. Returns an error if something goes wrong during processing.
+/// * `anyhow::Result<Bits>` - The packed bits extracted from the image.
+///   Returns an error if something goes wrong during processing.
 fn read_bw(
     source: &EmbedSource,
     current_frame: i32,
     final_frame: i32,
     final_bit: i32,
-) -> anyhow::Result<Vec<bool>> {
-    // Extract the width and height of the source image.
-    let width: i32 = source.actual_size.width;
-    let height = source.actual_size.height;
-
-    // Block size determines the step size for sampling pixels in both x and y directions.
-    let size = source.size as usize;
-
-    // Initialize an empty vector to store the binary data extracted from the image.
-    let mut binary_data: Vec<bool> = Vec::new();
-
-    // Iterate over the image's pixels using a step size equal to the block size.
-    // This effectively divides the image into a grid and samples one pixel per block.
-    for y in (0..height).step_by(size) {
-        for x in (0..width).step_by(size) {
-            // Retrieve the RGB value of the pixel at (x, y).
-            let rgb = get_pixel(&source, x, y);
-
-            // If the pixel is out of bounds or cannot be retrieved, skip to the next iteration.
-            if rgb.is_none() {
-                continue;
-            } else {
-                // Unwrap the RGB value (since it's guaranteed to exist at this point).
-                let rgb = rgb.unwrap();
-
-                // Convert the red channel's value to a boolean.
-                // If the red channel's value is >= 127, it's considered `true` (white).
-                // Otherwise, it's considered `false` (black).
-                if rgb[0] >= 127 {
-                    binary_data.push(true);
-                } else {
-                    binary_data.push(false);
-                }
-            }
-        }
+    threshold: u8,
+    blocks: Range<usize>,
+) -> anyhow::Result<Bits> {
+    // Initialize an empty bit sequence to store the binary data extracted from the image.
+    let mut binary_data = Bits::with_capacity(blocks.len());
+
+    // Iterate over the requested blocks of the image, in raster order.
+    // Every block is sampled as the average of its pixels.
+    let pixels = source.pixels();
+    for (x, y) in block_positions(source, blocks) {
+        // Retrieve the RGB value of the pixel at (x, y).
+        let rgb = get_pixel(source, &pixels, x, y);
+
+        // If the pixel is out of bounds or cannot be retrieved, skip to the next iteration.
+        let Some(rgb) = rgb else {
+            continue;
+        };
+
+        // Convert the block's luma to a boolean.
+        // If it reaches the threshold, it's considered `true` (white).
+        // Otherwise, it's considered `false` (black).
+        binary_data.push(luma(&rgb) >= threshold);
     }
 
     // If this is the final frame, truncate the binary data to the specified length (`final_bit`).
     if current_frame == final_frame {
-        // Slice the binary data to retain only the first `final_bit` elements.
-        let slice = binary_data[0..final_bit as usize].to_vec();
-        return Ok(slice); // Return the truncated binary data.
+        // Retain only the first `final_bit` bits.
+        binary_data.truncate(final_bit as usize);
     }
 
     // Return the full binary data for non-final frames.
@@ -404,6 +427,8 @@ fn read_bw(
 /// * `current_frame` - The index of the current frame being processed.
 /// * `final_frame` - The index of the last frame to process. Used to determine if this is the final frame.
 /// * `final_byte` - The number of bytes to retain in the final frame. Only used if `current_frame == final_frame`.
+/// * `lut` - Per-channel lookup tables mapping an observed value back to the etched one.
+/// * `blocks` - The blocks of the frame to read, as returned by `data_blocks`.
 ///
 /// # Returns
 /// * `anyhow::Result<Vec<u8>>` - A vector of `u8` values representing the RGB data
@@ -413,37 +438,29 @@ fn read_color(
     current_frame: i32,
     final_frame: i32,
     final_byte: i32,
+    lut: &[[u8; 256]; 3],
+    blocks: Range<usize>,
 ) -> anyhow::Result<Vec<u8>> {
-    // Get the width and height of the source image.
-    let width = source.actual_size.width;
-    let height = source.actual_size.height;
-
-    // Block size determines the step size for sampling pixels in both x and y directions.
-    let size = source.size as usize;
-
     // Initialize an empty vector to store the byte data extracted from the image.
     let mut byte_data: Vec<u8> = Vec::new();
 
-    // Iterate over the image's pixels using a step size equal to the block size.
-    // This effectively divides the image into a grid and samples one pixel per block.
-    for y in (0..height).step_by(size) {
-        for x in (0..width).step_by(size) {
-            // Retrieve the RGB value of the pixel at (x, y).
-            let rgb = get_pixel(&source, x, y);
+    // Iterate over the requested blocks of the image, in raster order.
+    // Every block is sampled as the average of its pixels.
+    let pixels = source.pixels();
+    for (x, y) in block_positions(source, blocks) {
+        // Retrieve the RGB value of the pixel at (x, y).
+        let rgb = get_pixel(source, &pixels, x, y);
 
-            // If the pixel is out of bounds or cannot be retrieved, skip to the next iteration.
-            if rgb.is_none() {
-                continue;
-            } else {
-                // Unwrap the RGB value (since it's guaranteed to exist at this point).
-                let rgb = rgb.unwrap();
+        // If the pixel is out of bounds or cannot be retrieved, skip to the next iteration.
+        let Some(rgb) = rgb else {
+            continue;
+        };
 
-                // Push the R, G, and B channels of the pixel into the byte data vector.
-                byte_data.push(rgb[0]); // Red channel
-                byte_data.push(rgb[1]); // Green channel
-                byte_data.push(rgb[2]); // Blue channel
-            }
-        }
+        // Push the R, G, and B channels of the pixel into the byte data vector,
+        // correcting each of them for the shifts measured on the calibration frames.
+        byte_data.push(lut[0][rgb[0] as usize]); // Red channel
+        byte_data.push(lut[1][rgb[1] as usize]); // Green channel
+        byte_data.push(lut[2][rgb[2] as usize]); // Blue channel
     }
 
     // If this is the final frame, truncate the byte data to the specified length (`final_byte`).
@@ -457,332 +474,1860 @@ fn read_color(
     Ok(byte_data)
 }
 
-/// Generates etching instructions for encoding data into an image source.
-/// Depending on the output mode (Color or Binary), this function computes the frame
-/// and pixel positions where the data embedding ends.
+/// Reads gray-level symbols from a source image, classifying the luma of every block
+/// against the decision thresholds.
+///
+/// # Arguments
+/// * `source` - A reference to an `EmbedSource` containing the image and related metadata.
+/// * `current_frame` - The index of the current frame being processed.
+/// * `final_frame` - The index of the last frame to process. Used to determine if this is the final frame.
+/// * `final_symbol` - The number of symbols to retain in the final frame. Only used if `current_frame == final_frame`.
+/// * `thresholds` - The thresholds separating consecutive levels, as built by `gray_thresholds`.
+/// * `blocks` - The blocks of the frame to read, as returned by `data_blocks`.
+///
+/// # Returns
+/// * `anyhow::Result<Vec<u8>>` - The symbols extracted from the image.
+fn read_gray(
+    source: &EmbedSource,
+    current_frame: i32,
+    final_frame: i32,
+    final_symbol: i32,
+    thresholds: &[u8],
+    blocks: Range<usize>,
+) -> anyhow::Result<Vec<u8>> {
+    let mut symbols: Vec<u8> = Vec::new();
+
+    let pixels = source.pixels();
+    for (x, y) in block_positions(source, blocks) {
+        let Some(rgb) = get_pixel(source, &pixels, x, y) else {
+            continue;
+        };
+
+        // Use the luma rather than a single channel, so color shifts average out
+        let luma = luma(&rgb);
+        let level = thresholds.iter().filter(|&&t| luma >= t).count();
+        symbols.push(level_to_symbol(level));
+    }
+
+    if current_frame == final_frame {
+        symbols.truncate(final_symbol as usize);
+    }
+
+    Ok(symbols)
+}
+
+/// Reads palette symbols from a source image, classifying every block as the nearest
+/// palette color.
+///
+/// # Arguments
+/// * `source` - A reference to an `EmbedSource` containing the image and related metadata.
+/// * `current_frame` - The index of the current frame being processed.
+/// * `final_frame` - The index of the last frame to process. Used to determine if this is the final frame.
+/// * `final_symbol` - The number of symbols to retain in the final frame. Only used if `current_frame == final_frame`.
+/// * `palette` - The color expected for each symbol.
+/// * `blocks` - The blocks of the frame to read, as returned by `data_blocks`.
+///
+/// # Returns
+/// * `anyhow::Result<Vec<u8>>` - The symbols extracted from the image.
+fn read_palette(
+    source: &EmbedSource,
+    current_frame: i32,
+    final_frame: i32,
+    final_symbol: i32,
+    palette: &[[u8; 3]],
+    blocks: Range<usize>,
+) -> anyhow::Result<Vec<u8>> {
+    let mut symbols: Vec<u8> = Vec::new();
+
+    let pixels = source.pixels();
+    for (x, y) in block_positions(source, blocks) {
+        let Some(rgb) = get_pixel(source, &pixels, x, y) else {
+            continue;
+        };
+
+        // Pick the palette color with the smallest squared distance to the block
+        let distance = |color: &[u8; 3]| -> i32 {
+            (0..3)
+                .map(|c| (rgb[c] as i32 - color[c] as i32).pow(2))
+                .sum()
+        };
+        let (symbol, _) = palette
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, color)| distance(color))
+            .ok_or_else(|| anyhow!("The palette is empty"))?;
+        symbols.push(symbol as u8);
+    }
+
+    if current_frame == final_frame {
+        symbols.truncate(final_symbol as usize);
+    }
+
+    Ok(symbols)
+}
+
+/// Reference colors etched into the calibration frames: black and white for binary, every
+/// gray level from dark to bright, every palette color in symbol order, or a gray ramp of
+/// all 256 values for color mode.
+///
+/// # Arguments
+/// * `out_mode` - The output mode of the data frames.
+/// * `palette` - The palette of the palette modes, ignored by the other modes.
+fn calibration_references(out_mode: OutputMode, palette: &[[u8; 3]]) -> Vec<[u8; 3]> {
+    match out_mode {
+        OutputMode::Binary => vec![[0; 3], [255; 3]],
+        OutputMode::Gray4 | OutputMode::Gray8 => gray_levels(out_mode.bits_per_block())
+            .into_iter()
+            .map(|level| [level; 3])
+            .collect(),
+        OutputMode::Palette4 | OutputMode::Palette8 => palette.to_vec(),
+        OutputMode::Color => (0..=255u8).map(|value| [value; 3]).collect(),
+    }
+}
+
+/// Generates the calibration frames that follow the instruction frames.
+///
+/// The references are repeated cyclically over every block, in as many frames as it takes
+/// for each of them to appear at least once, so the decoder can observe what every symbol
+/// looks like after the video was transcoded, next to the same kind of neighbours as data.
 ///
 /// # Arguments
 /// * `settings` - Configuration settings for the etching process.
-/// * `data` - The data to be embedded into the image source.
+/// * `references` - The reference colors, as built by `calibration_references`.
 ///
 /// # Returns
-/// * `EmbedSource` containing the embedded instructions as an image.
-fn etch_instructions(settings: &Settings, data: &Data) -> anyhow::Result<EmbedSource> {
-    // Size of the instruction block in pixels
-    let instruction_size = 5;
+/// * The calibration frames as `EmbedSource`s.
+fn etch_calibration(
+    settings: &Settings,
+    references: &[[u8; 3]],
+) -> anyhow::Result<Vec<EmbedSource>> {
+    let blocks = blocks_per_frame(settings.size, settings.width, settings.height);
+
+    let mut frames = Vec::new();
+    let mut index = 0;
+    for _ in 0..references.len().div_ceil(blocks) {
+        let mut source = blank_frame(settings.size, settings.width, settings.height)?;
+
+        let positions = block_positions(&source, 0..blocks);
+        let mut pixels = source.pixels_mut();
+        for (x, y) in positions {
+            pixels.fill_block(x, y, references[index % references.len()]);
+            index += 1;
+        }
 
-    // List of 32-bit instructions to store embedding metadata
-    let mut u32_instructions: Vec<u32> = Vec::new();
+        frames.push(source);
+    }
 
-    // Calculate the number of pixels in a single frame
-    let frame_size = (settings.height * settings.width) as usize;
+    println!("Calibration written");
 
-    // Determine the final frame and pixel position based on the output mode
-    match data.out_mode {
-        OutputMode::Color => {
-            // Color mode marker: `u32::MAX`
-            u32_instructions.push(u32::MAX);
+    Ok(frames)
+}
 
-            let frame_data_size = frame_size / settings.size.pow(2) as usize;
-            let final_byte = data.bytes.len() % frame_data_size;
-            let mut final_frame = data.bytes.len() / frame_data_size;
+/// What the decoder learned from the calibration frames: how every reference color
+/// actually looks in this video.
+struct Calibration {
+    /// Observed color of every reference, in the order `calibration_references` returns them.
+    observed: Vec<[u8; 3]>,
+}
+
+impl Calibration {
+    /// Luma threshold halfway between the darkest and brightest observed references,
+    /// used for binary data and for the header strip of every data frame.
+    fn bw_threshold(&self) -> u8 {
+        let lumas = self.observed.iter().map(|rgb| luma(rgb));
+        let darkest = lumas.clone().min().unwrap_or(0);
+        let brightest = lumas.max().unwrap_or(255);
+        gray_thresholds(&[darkest, brightest])[0]
+    }
 
-            // Handle edge case: increment frame if data length perfectly matches frame size
-            if data.bytes.len() % frame_size != 0 {
-                final_frame += 1;
+    /// Luma thresholds halfway between consecutive observed gray levels.
+    fn gray_thresholds(&self) -> Vec<u8> {
+        let levels: Vec<u8> = self.observed.iter().map(|rgb| luma(rgb)).collect();
+        gray_thresholds(&levels)
+    }
+
+    /// Per-channel lookup tables mapping every observed value to the ramp value whose
+    /// observation is the closest, undoing brightness, contrast and gamma shifts.
+    fn color_lut(&self) -> [[u8; 256]; 3] {
+        let mut lut = [[0u8; 256]; 3];
+        for (channel, table) in lut.iter_mut().enumerate() {
+            for (value, entry) in table.iter_mut().enumerate() {
+                *entry = (0..self.observed.len())
+                    .min_by_key(|&r| (self.observed[r][channel] as i32 - value as i32).abs())
+                    .unwrap_or(value) as u8;
             }
+        }
+        lut
+    }
+}
+
+/// Reads the calibration frames following the instruction frames and learns how the
+/// reference colors look in this video. Videos without calibration frames, or whose
+/// calibration is unusable, fall back to the nominal colors.
+///
+/// # Arguments
+/// * `video` - The opened video, positioned right after the instruction frames.
+/// * `settings` - The settings read from the instruction frames.
+/// * `header` - The decoded header.
+/// * `alignment` - The alignment of the previous frame, updated as frames are read.
+///
+/// # Returns
+/// * The learned `Calibration`.
+fn read_calibration(
+    video: &mut dyn FrameSource,
+    settings: &Settings,
+    header: &Header,
+    alignment: &mut Option<Transform>,
+) -> anyhow::Result<Calibration> {
+    let references = calibration_references(header.out_mode, &header.palette);
+    let mut samples: Vec<Vec<[u8; 3]>> = vec![Vec::new(); references.len()];
+    let blocks = blocks_per_frame(settings.size, settings.width, settings.height);
+    let mut index = 0;
 
-            // Debugging final frame position
-            dbg!(final_frame);
-            u32_instructions.push(final_frame as u32);
-            u32_instructions.push(final_byte as u32);
+    for _ in 0..header.calibration_frames {
+        let Some(frame) = video.read_frame()? else {
+            return Err(anyhow!(
+                "Video ended before the calibration frames could be read"
+            ));
+        };
+
+        let source = frame_source(
+            frame,
+            settings.size,
+            settings.width,
+            settings.height,
+            alignment,
+        )?;
+        let pixels = source.pixels();
+        for (x, y) in block_positions(&source, 0..blocks) {
+            if let Some(rgb) = get_pixel(&source, &pixels, x, y) {
+                samples[index % references.len()].push(rgb);
+            }
+            index += 1;
         }
-        OutputMode::Binary => {
-            // Binary mode marker: `u32::MIN`
-            u32_instructions.push(u32::MIN);
+    }
+
+    if header.calibration_frames == 0 {
+        return Ok(Calibration {
+            observed: references,
+        });
+    }
+
+    // The median of every channel ignores the odd damaged block
+    let observed: Vec<[u8; 3]> = samples
+        .iter_mut()
+        .zip(&references)
+        .map(|(samples, reference)| {
+            if samples.is_empty() {
+                return *reference;
+            }
+            let mut color = [0u8; 3];
+            for (channel, value) in color.iter_mut().enumerate() {
+                let mut values: Vec<u8> = samples.iter().map(|rgb| rgb[channel]).collect();
+                values.sort_unstable();
+                *value = values[values.len() / 2];
+            }
+            color
+        })
+        .collect();
+
+    // Levels that no longer increase in brightness can't be told apart by thresholds
+    let ordered = observed
+        .windows(2)
+        .all(|pair| luma(&pair[0]) < luma(&pair[1]));
+    if matches!(
+        header.out_mode,
+        OutputMode::Binary | OutputMode::Gray4 | OutputMode::Gray8
+    ) && !ordered
+    {
+        println!("Calibration frames are unusable, falling back to the nominal levels");
+        return Ok(Calibration {
+            observed: references,
+        });
+    }
+
+    println!(
+        "Calibration learned from {} frame(s)",
+        header.calibration_frames
+    );
+
+    Ok(Calibration { observed })
+}
+
+/// Calculates how many embedding blocks fit in a single frame.
+///
+/// # Arguments
+/// * `size` - The block size in pixels per side.
+/// * `width` - The frame width in pixels.
+/// * `height` - The frame height in pixels.
+///
+/// # Returns
+/// The number of whole blocks per frame. Partial blocks at the edges and blocks
+/// reserved for the finder patterns are not used.
+fn blocks_per_frame(size: i32, width: i32, height: i32) -> usize {
+    block_grid(size, width, height).count()
+}
 
-            let frame_data_size = frame_size / settings.size.pow(2) as usize;
-            let final_byte = data.binary.len() % frame_data_size;
-            let mut final_frame = data.binary.len() / frame_data_size;
+/// Top-left corners of every usable block of a frame, in raster order, skipping the
+/// blocks that overlap the finder patterns and the margin along its edges.
+///
+/// # Arguments
+/// * `size` - The block size in pixels per side.
+/// * `width` - The frame width in pixels.
+/// * `height` - The frame height in pixels.
+fn block_grid(size: i32, width: i32, height: i32) -> impl Iterator<Item = (i32, i32)> {
+    BlockGrid {
+        size,
+        width,
+        height,
+        x: 0,
+        y: 0,
+    }
+}
 
-            // Handle edge case: increment frame if data length perfectly matches frame size
-            if data.binary.len() % frame_size != 0 {
-                final_frame += 1;
+/// Walks the block grid of a frame for `block_grid`. Every block of every frame is
+/// visited through it, so it is kept to a couple of comparisons per block rather than
+/// built from iterator adapters.
+struct BlockGrid {
+    size: i32,
+    width: i32,
+    height: i32,
+    /// Top-left corner of the next block to visit.
+    x: i32,
+    y: i32,
+}
+
+impl Iterator for BlockGrid {
+    type Item = (i32, i32);
+
+    fn next(&mut self) -> Option<(i32, i32)> {
+        // Blocks that don't advance would walk the grid forever
+        if self.size < 1 {
+            return None;
+        }
+
+        loop {
+            // Partial blocks at the end of a row are skipped
+            if self.x + self.size > self.width {
+                self.x = 0;
+                self.y += self.size;
+            }
+            if self.y + self.size > self.height {
+                return None;
             }
 
-            // Debugging final frame position
-            dbg!(final_frame);
-            u32_instructions.push(final_frame as u32);
-            u32_instructions.push(final_byte as u32);
+            let (x, y) = (self.x, self.y);
+            self.x += self.size;
+            if !finder::is_reserved(x, y, self.size, self.width, self.height) {
+                return Some((x, y));
+            }
         }
     }
+}
 
-    // Include the pixel block size in instructions
-    u32_instructions.push(settings.size as u32);
-    // End marker for size readability; this marker might be required for compatibility
-    u32_instructions.push(u32::MAX);
+/// Top-left corners of a range of blocks of a frame, numbered in raster order.
+///
+/// # Arguments
+/// * `source` - The frame the blocks belong to.
+/// * `blocks` - The block numbers to visit.
+fn block_positions(source: &EmbedSource, blocks: Range<usize>) -> impl Iterator<Item = (i32, i32)> {
+    let frame_size = source.frame_size;
+    block_grid(source.size, frame_size.width, frame_size.height)
+        .skip(blocks.start)
+        .take(blocks.len())
+}
 
-    // Convert instructions into binary format for embedding
-    let instruction_data = rip_binary_u32(u32_instructions)?;
+/// Creates a blank frame with the finder patterns etched into its corners.
+///
+/// # Arguments
+/// * `size` - The block size in pixels per side.
+/// * `width` - The frame width in pixels.
+/// * `height` - The frame height in pixels.
+fn blank_frame(size: i32, width: i32, height: i32) -> anyhow::Result<EmbedSource> {
+    let mut source = EmbedSource::new(size, width, height);
+    finder::etch_finders(&mut source)?;
+    Ok(source)
+}
 
-    // Create a new image source to store the instructions
-    let mut source = EmbedSource::new(instruction_size, settings.width, settings.height);
-    let mut index = 0;
+/// The blocks of a data frame that carry payload: everything after the header strip.
+/// Except in color mode, the count is rounded down to a multiple of 8 so every frame
+/// holds whole bytes and can be decoded on its own.
+///
+/// # Arguments
+/// * `out_mode` - The output mode of the data frames.
+/// * `blocks` - The number of blocks per frame, as returned by `blocks_per_frame`.
+fn data_blocks(out_mode: OutputMode, blocks: usize) -> Range<usize> {
+    let available = blocks.saturating_sub(STRIP_BLOCKS);
+    let used = match out_mode {
+        OutputMode::Color => available,
+        _ => available / 8 * 8,
+    };
+    STRIP_BLOCKS..STRIP_BLOCKS + used
+}
+
+/// Number of units (bits, bytes or symbols, depending on the mode) a data frame holds.
+fn frame_capacity(out_mode: OutputMode, blocks: usize) -> usize {
+    let data_blocks = data_blocks(out_mode, blocks).len();
+    match out_mode {
+        OutputMode::Color => data_blocks * 3,
+        _ => data_blocks,
+    }
+}
+
+/// Number of payload bytes a full data frame holds.
+///
+/// # Arguments
+/// * `out_mode` - The output mode of the data frames.
+/// * `settings` - The block size and frame dimensions of the video.
+pub fn frame_bytes(out_mode: OutputMode, settings: &Settings) -> usize {
+    let blocks = data_blocks(
+        out_mode,
+        blocks_per_frame(settings.size, settings.width, settings.height),
+    );
+    match out_mode {
+        OutputMode::Color => blocks.len() * 3,
+        _ => blocks.len() * out_mode.bits_per_block() / 8,
+    }
+}
+
+/// CRC32 of the units held by a data frame, as recorded in its header strip.
+fn units_crc<T: Copy + Into<u8>>(units: &[T]) -> u32 {
+    let bytes: Vec<u8> = units.iter().map(|&unit| unit.into()).collect();
+    crc32fast::hash(&bytes)
+}
 
-    // Attempt to etch instructions onto the source; handle potential errors
-    match etch_bw(&mut source, &instruction_data, &mut index) {
-        Ok(_) => {}
-        Err(_) => {
-            println!("Instructions written");
+/// CRC32 of the bits held by a binary data frame, as recorded in its header strip.
+/// Every bit counts as a unit of its own, hashed as a `0` or `1` byte.
+fn bits_crc(bits: BitReader) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    let mut units = [0u8; 4096];
+    let mut filled = 0;
+    for bit in bits {
+        units[filled] = bit as u8;
+        filled += 1;
+        if filled == units.len() {
+            hasher.update(&units);
+            filled = 0;
         }
     }
+    hasher.update(&units[..filled]);
+    hasher.finalize()
+}
 
-    // Optional display code (commented out for typical use cases)
-    // Uncomment for debugging visualization
-    // highgui::named_window("window", WINDOW_FULLSCREEN)?;
-    // highgui::imshow("window", &source.image)?;
-    // highgui::wait_key(10000000)?;
+/// Etches the header strip of a data frame: its index and the CRC32 of its units,
+/// as black and white blocks repeated `STRIP_COPIES` times.
+///
+/// # Arguments
+/// * `source` - The data frame.
+/// * `frame` - The index of the frame (data frames are numbered from 1).
+/// * `crc` - The CRC32 of the units the frame holds, as returned by `units_crc`.
+fn etch_strip(source: &mut EmbedSource, frame: u32, crc: u32) -> anyhow::Result<()> {
+    let mut strip = frame.to_be_bytes().to_vec();
+    strip.extend_from_slice(&crc.to_be_bytes());
+    let strip = strip.repeat(STRIP_COPIES);
 
-    // Save the resulting image for verification
-    // imwrite("src/out/test1.png", &source.image, &Vector::new())?;
+    // Running out of bits at the end of the strip is expected
+    let _ = etch_bw(source, &mut BitReader::new(&strip), 0..STRIP_BLOCKS);
 
-    Ok(source)
+    Ok(())
 }
 
-/// Reads and parses embedding instructions from the given `EmbedSource`.
+/// Reads the header strip of a data frame, taking a majority vote over its copies.
+///
+/// # Arguments
+/// * `source` - The data frame.
+/// * `threshold` - The luma at or above which a block reads as white.
+///
+/// # Returns
+/// * The index of the frame and the CRC32 of its units.
+fn read_strip(source: &EmbedSource, threshold: u8) -> anyhow::Result<(u32, u32)> {
+    let copies = read_bw(source, 0, 1, 0, threshold, 0..STRIP_BLOCKS)?;
+    let mut bits = Bits::with_capacity(STRIP_BITS);
+    for bit in 0..STRIP_BITS {
+        let votes = (0..STRIP_COPIES)
+            .filter(|copy| copies.get(copy * STRIP_BITS + bit))
+            .count();
+        bits.push(votes * 2 > STRIP_COPIES);
+    }
+
+    let strip = bits.into_bytes();
+    Ok((
+        u32::from_be_bytes(strip[0..4].try_into()?),
+        u32::from_be_bytes(strip[4..8].try_into()?),
+    ))
+}
+
+/// Wraps a frame read from the video for sampling on the block grid of a `width` x `height`
+/// frame. The finder patterns are located to align the blocks if the video was rescaled,
+/// cropped, padded or distorted after embedding. Frames whose patterns can't be found
+/// (e.g. damaged by compression) reuse the alignment of the previous frame, or are
+/// assumed to have only been rescaled.
+///
+/// # Arguments
+/// * `frame` - The frame read from the video.
+/// * `size` - The block size, in pixels per side of the original frame.
+/// * `width` - The width of the frame as etched.
+/// * `height` - The height of the frame as etched.
+/// * `alignment` - The alignment of the previous frame, updated with this frame's.
+fn frame_source(
+    frame: Frame,
+    size: i32,
+    width: i32,
+    height: i32,
+    alignment: &mut Option<Transform>,
+) -> anyhow::Result<EmbedSource> {
+    let found = finder::locate(&frame);
+    let transform = finder::transform(&found, width, height, alignment.as_ref())
+        .or(*alignment)
+        .unwrap_or_else(|| {
+            Transform::scale(
+                frame.width as f64 / width as f64,
+                frame.height as f64 / height as f64,
+            )
+        });
+    *alignment = Some(transform);
+
+    // Frames within half a pixel of their original grid are sampled directly
+    if frame.width == width && frame.height == height && transform.is_identity(width, height, 0.5) {
+        EmbedSource::from(frame, size, true).map_err(Error::msg)
+    } else {
+        Ok(EmbedSource::aligned(frame, size, width, height, transform))
+    }
+}
+
+/// Finds the resolution the video was etched at from its first instruction frame. The
+/// frame's own size is tried first, then every resolution the embedder offers, until the
+/// magic signature shows up.
+///
+/// # Arguments
+/// * `frame` - The first frame of the video.
+///
+/// # Returns
+/// * The width and height of the frames as etched. The frame's own size if nothing matched,
+///   so the header check reports the video as not encoded.
+fn instruction_resolution(frame: &Frame) -> anyhow::Result<(i32, i32)> {
+    let own = (frame.width, frame.height);
+    let candidates = RESOLUTIONS
+        .iter()
+        .map(|&(_, width, height)| (width, height))
+        .filter(|&candidate| candidate != own);
+
+    for (width, height) in std::iter::once(own).chain(candidates) {
+        let source = frame_source(frame.clone(), INSTRUCTION_SIZE, width, height, &mut None)?;
+        let bits = read_bw(
+            &source,
+            0,
+            1,
+            0,
+            DEFAULT_THRESHOLD,
+            0..header::MAGIC.len() * 8,
+        )?;
+        if bits.bytes() == header::MAGIC {
+            return Ok((width, height));
+        }
+    }
+
+    Ok(own)
+}
+
+/// Generates the instruction frames, which carry the container header describing
+/// how the data frames that follow must be decoded.
+/// Depending on the output mode (Color or Binary), this function computes the frame
+/// and position where the data embedding ends.
+///
+/// # Arguments
+/// * `settings` - Configuration settings for the etching process.
+/// * `data` - The data to be embedded into the image source.
+///
+/// # Returns
+/// * The instruction frames as `EmbedSource`s. Large headers span several frames.
+fn etch_instructions(settings: &Settings, data: &Data) -> anyhow::Result<Vec<EmbedSource>> {
+    // Number of units (bits, bytes or symbols) a single data frame can hold, and the total to embed
+    let blocks = blocks_per_frame(settings.size, settings.width, settings.height);
+    let frame_data_size = frame_capacity(data.out_mode, blocks);
+    let length = match data.out_mode {
+        OutputMode::Color => data.len as usize,
+        OutputMode::Binary => data.len as usize * 8,
+        OutputMode::Gray4 | OutputMode::Gray8 | OutputMode::Palette4 | OutputMode::Palette8 => {
+            (data.len as usize * 8).div_ceil(data.out_mode.bits_per_block())
+        }
+    };
+    if frame_data_size == 0 {
+        return Err(anyhow!(
+            "Blocks of {} pixels leave no room for data in a {}x{} frame",
+            settings.size,
+            settings.width,
+            settings.height
+        ));
+    }
+
+    // The final frame is always at least partially filled, so a payload that exactly
+    // fills its last frame reports a full final frame rather than an empty one
+    let final_frame = length.div_ceil(frame_data_size);
+    let final_byte = length - (final_frame - 1) * frame_data_size;
+
+    let mut header = Header::new(
+        data.out_mode,
+        settings.size as u32,
+        final_frame as u32,
+        final_byte as u32,
+    );
+    header.palette = match data.out_mode {
+        OutputMode::Palette4 | OutputMode::Palette8 => symbol_colors(data.out_mode),
+        _ => Vec::new(),
+    };
+    header.frame_size = Some((settings.width as u32, settings.height as u32));
+    header.calibration_frames = calibration_references(data.out_mode, &header.palette)
+        .len()
+        .div_ceil(blocks) as u16;
+    header.payload = data.payload.clone();
+
+    // Convert the header into binary format for embedding, signing it if requested
+    let header_bytes = match &data.signing_key {
+        Some(key) => header.encode_signed(key)?,
+        None => header.encode(),
+    };
+    let mut instruction_data = BitReader::new(&header_bytes);
+
+    // Etch the header bits across as many instruction frames as needed
+    let mut frames = Vec::new();
+    loop {
+        let mut source = blank_frame(INSTRUCTION_SIZE, settings.width, settings.height)?;
+        let blocks = 0..blocks_per_frame(INSTRUCTION_SIZE, settings.width, settings.height);
+        match etch_bw(&mut source, &mut instruction_data, blocks) {
+            Ok(_) => frames.push(source),
+            Err(_) => {
+                frames.push(source);
+                println!("Instructions written");
+                break;
+            }
+        }
+    }
+
+    Ok(frames)
+}
+
+/// Reads and verifies the container header from the instruction frame(s) at the start of the video.
 ///
 /// This function extracts metadata necessary for decoding the embedded data,
 /// such as output mode, final frame, and byte positions, and constructs appropriate settings.
+/// Videos without a valid signature or with a corrupted header are rejected.
 ///
 /// # Arguments
-/// * `source` - The source from which instructions are read.
+/// * `video` - The opened video, positioned at its first frame.
 /// * `threads` - The number of threads for parallel processing.
 ///
 /// # Returns
 /// * A tuple containing:
-///   - `OutputMode` indicating the type of output (Color or Binary)
-///   - `final_frame` indicating the last frame with embedded data
-///   - `final_byte` indicating the last byte position in the final frame
+///   - The decoded `Header`
 ///   - `Settings` instance with configuration for the decoding process
 fn read_instructions(
-    source: &EmbedSource,
+    video: &mut dyn FrameSource,
     threads: usize,
-) -> anyhow::Result<(OutputMode, i32, i32, Settings)> {
-    // Read binary data from the first frame of the source
-    // This retrieves the raw binary encoding of the instructions
-    let binary_data = read_bw(source, 0, 1, 0)?;
-
-    // Convert the binary data into a vector of 32-bit unsigned integers
-    let u32_data = translate_u32(binary_data)?;
-
-    // Extract and interpret the output mode from the first instruction value
-    let out_mode = match u32_data[0] {
-        u32::MAX => OutputMode::Color, // Color mode marker
-        _ => OutputMode::Binary,       // Default to Binary mode
+) -> anyhow::Result<(Header, Settings)> {
+    let mut binary_data = Bits::with_capacity(header::PREFIX_LEN * 8);
+    let mut instruction_data: Vec<u8>;
+
+    let Some(mut frame) = video.read_frame()? else {
+        return Err(anyhow!(
+            "Video ended before the instruction frames could be read"
+        ));
     };
+    let (mut width, mut height) = instruction_resolution(&frame)?;
+
+    // The header may span several instruction frames, so keep reading until
+    // the length announced in its prefix has been collected
+    let mut header_len = header::PREFIX_LEN;
+    let mut alignment = None;
+    loop {
+        let source = frame_source(
+            frame.clone(),
+            INSTRUCTION_SIZE,
+            width,
+            height,
+            &mut alignment,
+        )?;
+
+        // Bits are concatenated before translating, as frames don't necessarily hold whole bytes
+        let blocks = 0..blocks_per_frame(INSTRUCTION_SIZE, width, height);
+        binary_data.extend(&read_bw(&source, 0, 1, 0, DEFAULT_THRESHOLD, blocks)?);
+        instruction_data = binary_data.bytes().to_vec();
+
+        if instruction_data.len() >= header::PREFIX_LEN {
+            header_len = Header::peek_len(&instruction_data)?;
+        }
+        if instruction_data.len() >= header_len {
+            break;
+        }
 
-    // Extract the final frame index for the embedded data
-    let final_frame = u32_data[1] as i32;
+        frame = video
+            .read_frame()?
+            .ok_or_else(|| anyhow!("Video ended before the instruction frames could be read"))?;
+    }
 
-    // Extract the byte position within the final frame
-    let final_byte = u32_data[2] as i32;
+    let header = Header::decode(&instruction_data)?;
 
-    // Extract the pixel block size for encoding
-    let size = u32_data[3] as i32;
+    // The header records the exact frame size, which is authoritative
+    if let Some((header_width, header_height)) = header.frame_size {
+        (width, height) = (header_width as i32, header_height as i32);
+    }
+    if (frame.width, frame.height) != (width, height) {
+        println!(
+            "Video was resized from {}x{} to {}x{}, aligning the blocks",
+            width, height, frame.width, frame.height
+        );
+    }
 
-    // Retrieve source dimensions (height and width)
-    let height = source.frame_size.height;
-    let width = source.frame_size.width;
+    // Make sure the final frame can actually hold what the header claims
+    let blocks = blocks_per_frame(header.block_size as i32, width, height);
+    let frame_data_size = frame_capacity(header.out_mode, blocks);
+    if header.final_byte as usize > frame_data_size {
+        return Err(anyhow!(
+            "Corrupted header: final frame holds {} units but a frame only fits {}",
+            header.final_byte,
+            frame_data_size
+        ));
+    }
 
     // Create the settings object for decoding, using the extracted size and provided thread count
-    let settings = Settings::new(size, threads, 1337, width, height);
+    let settings = Settings::new(header.block_size as i32, threads, 1337, width, height);
 
     // Return the parsed instructions and settings
-    Ok((out_mode, final_frame, final_byte, settings))
+    Ok((header, settings))
+}
+
+/// Renders a single data frame and stamps it with its index and the CRC of what it holds.
+///
+/// # Arguments
+/// * `chunk` - The bytes the frame holds, at most `frame_bytes` of them.
+/// * `frame_number` - The index of the frame (data frames are numbered from 1).
+/// * `out_mode` - The output mode of the data frames.
+/// * `colors` - The color of each symbol, as built by `symbol_colors`.
+/// * `geometry` - The block size, width and height of the frames.
+///
+/// # Returns
+/// * The rendered frame.
+fn etch_frame(
+    chunk: Vec<u8>,
+    frame_number: u32,
+    out_mode: OutputMode,
+    colors: &[[u8; 3]],
+    geometry: (i32, i32, i32),
+) -> anyhow::Result<EmbedSource> {
+    let (size, width, height) = geometry;
+    let mut source = blank_frame(size, width, height)?;
+    let blocks = data_blocks(out_mode, blocks_per_frame(size, width, height));
+    let mut index = 0;
+
+    // The etch functions report running out of data as an error, which is expected
+    // once the chunk is used up
+    let crc = match out_mode {
+        OutputMode::Color => {
+            // Pad to whole RGB triplets; the header records the exact length
+            let real_length = chunk.len();
+            let mut bytes = chunk;
+            bytes.resize(real_length.div_ceil(3) * 3, 0);
+            let _ = etch_color(&mut source, &bytes, &mut index, blocks);
+            units_crc(&bytes[..real_length])
+        }
+        OutputMode::Binary => {
+            let _ = etch_bw(&mut source, &mut BitReader::new(&chunk), blocks);
+            bits_crc(BitReader::new(&chunk))
+        }
+        OutputMode::Gray4 | OutputMode::Gray8 | OutputMode::Palette4 | OutputMode::Palette8 => {
+            let symbols = rip_symbols(&chunk, out_mode.bits_per_block());
+            let _ = etch_symbols(&mut source, &symbols, &mut index, colors, blocks);
+            units_crc(&symbols)
+        }
+    };
+    etch_strip(&mut source, frame_number, crc)?;
+
+    Ok(source)
 }
 
 /// Embeds data into a video file using multi-threaded frame generation.
 ///
+/// The data is streamed through a pipeline: a reader thread cuts it into one chunk per
+/// frame and deals the chunks out to the worker threads in turn, the workers render
+/// their frames, and the frames are written in order as they come back. Every queue
+/// is bounded, so memory use depends on the thread count, not on the size of the data.
+///
 /// # Arguments
 /// * `path` - Path to the output video file.
+/// * `encoder` - How the video is encoded.
 /// * `data` - Data to embed in the video.
 /// * `settings` - Configuration for the embedding process.
 ///
 /// # Returns
 /// * `anyhow::Result<()>` - Ok on success or an error on failure.
-pub fn etch(path: &str, data: Data, settings: Settings) -> anyhow::Result<()> {
+pub fn etch(path: &str, encoder: &Encoder, data: Data, settings: Settings) -> anyhow::Result<()> {
     let _timer = Timer::new("Etching video");
 
-    let mut spool = Vec::new();
-
-    match data.out_mode {
-        OutputMode::Color => {
-            let length = data.bytes.len();
-
-            // Compute sizes for frame data and chunk data for threads
-            let frame_size = (settings.width * settings.height) as usize;
-            let frame_data_size = frame_size / settings.size.pow(2) as usize * 3;
-            let frame_length = length / frame_data_size;
-            let chunk_frame_size = (frame_length / settings.threads) + 1;
-            let chunk_data_size = chunk_frame_size * frame_data_size;
-
-            // Divide data into chunks and spawn threads for parallel processing
-            for chunk in data.bytes.chunks(chunk_data_size) {
-                let chunk_copy = chunk.to_vec();
-
-                let thread = thread::spawn(move || {
-                    let mut frames = Vec::new();
-                    let mut index: usize = 0;
-
-                    // Generate frames and push to the frame list
-                    loop {
-                        let mut source =
-                            EmbedSource::new(settings.size, settings.width, settings.height);
-                        match etch_color(&mut source, &chunk_copy, &mut index) {
-                            Ok(_) => frames.push(source),
-                            Err(_) => {
-                                frames.push(source);
-                                println!("Embedding thread complete!");
-                                break;
-                            }
-                        }
-                    }
+    // Generate the instructional frames, which also checks the frames have room for data
+    let instructional_frames = etch_instructions(&settings, &data)?;
+
+    // Every frame has the size of the first instruction frame
+    let frame_size = instructional_frames[0].frame_size;
+    let mut video = video::open_sink(
+        path,
+        encoder,
+        settings.fps,
+        frame_size.width,
+        frame_size.height,
+    )?;
+
+    for frame in instructional_frames {
+        video.write_frame(&frame.image)?;
+    }
 
-                    frames
-                });
+    // Follow them with the calibration frames, so the decoder can learn what every symbol looks like
+    let colors = symbol_colors(data.out_mode);
+    let references = calibration_references(data.out_mode, &colors);
+    for frame in etch_calibration(&settings, &references)? {
+        video.write_frame(&frame.image)?;
+    }
 
-                spool.push(thread);
+    let out_mode = data.out_mode;
+    let frame_bytes = frame_bytes(out_mode, &settings);
+    let frame_count = data.len.div_ceil(frame_bytes as u64);
+    let geometry = (settings.size, settings.width, settings.height);
+    let workers = settings.threads.max(1);
+    let mut reader = data.reader;
+
+    thread::scope(|scope| {
+        let (chunk_senders, chunk_receivers): (Vec<_>, Vec<_>) = (0..workers)
+            .map(|_| sync_channel::<(u32, Vec<u8>)>(QUEUED_FRAMES))
+            .unzip();
+        let (frame_senders, frame_receivers): (Vec<_>, Vec<_>) = (0..workers)
+            .map(|_| sync_channel::<anyhow::Result<Frame>>(QUEUED_FRAMES))
+            .unzip();
+
+        // Cut the data into frames, dealing them out to the workers in turn
+        let feeder = scope.spawn(move || -> anyhow::Result<()> {
+            for index in 0..frame_count {
+                let chunk = spool::read_chunk(&mut reader, frame_bytes)?;
+                if chunk.is_empty() {
+                    return Err(anyhow!("Data ended before every frame was etched"));
+                }
+                let worker = &chunk_senders[index as usize % workers];
+                if worker.send((index as u32 + 1, chunk)).is_err() {
+                    // The writer gave up, it reports why
+                    break;
+                }
             }
-        }
-        OutputMode::Binary => {
-            let length = data.binary.len();
-
-            let frame_size = (settings.width * settings.height) as usize;
-            let frame_data_size = frame_size / settings.size.pow(2) as usize;
-            let frame_length = length / frame_data_size;
-            let chunk_frame_size = (frame_length / settings.threads) + 1;
-            let chunk_data_size = chunk_frame_size * frame_data_size;
-
-            for chunk in data.binary.chunks(chunk_data_size) {
-                let chunk_copy = chunk.to_vec();
-
-                let thread = thread::spawn(move || {
-                    let mut frames = Vec::new();
-                    let mut index: usize = 0;
-
-                    loop {
-                        let mut source =
-                            EmbedSource::new(settings.size, settings.width, settings.height);
-                        match etch_bw(&mut source, &chunk_copy, &mut index) {
-                            Ok(_) => frames.push(source),
-                            Err(_) => {
-                                frames.push(source);
-                                println!("Embedding thread complete!");
-                                break;
-                            }
-                        }
+            Ok(())
+        });
+
+        // Render the frames
+        for (chunks, frames) in chunk_receivers.into_iter().zip(frame_senders) {
+            let colors = colors.clone();
+            scope.spawn(move || {
+                for (frame_number, chunk) in chunks {
+                    let frame = etch_frame(chunk, frame_number, out_mode, &colors, geometry)
+                        .map(|source| source.image);
+                    if frames.send(frame).is_err() {
+                        break;
                     }
+                }
+                println!("Embedding thread complete!");
+            });
+        }
 
-                    frames
-                });
-
-                spool.push(thread);
+        // Write the frames in order, taking them from the workers in the order they were dealt
+        for index in 0..frame_count {
+            match frame_receivers[index as usize % workers].recv() {
+                Ok(frame) => video.write_frame(&frame?)?,
+                // The feeder stopped early, it reports why
+                Err(_) => break,
             }
         }
-    }
+        drop(frame_receivers);
 
-    let mut complete_frames = Vec::new();
+        feeder
+            .join()
+            .map_err(|_| anyhow!("The thread reading the data panicked"))?
+    })?;
+    video.finish()?;
 
-    // Generate the instructional frame and add it to the frame list
-    let instructional_frame = etch_instructions(&settings, &data)?;
-    complete_frames.push(instructional_frame);
+    println!("Video embedded successfully at {}", path);
+
+    Ok(())
+}
 
-    // Collect all frames from the threads
-    for thread in spool {
-        let frame_chunk = thread.join().unwrap();
-        complete_frames.extend(frame_chunk);
+/// A data frame decoded by `FrameDecoder`: its index, the bytes it holds and whether
+/// they matched the CRC in its header strip.
+type DecodedFrame = (u32, Vec<u8>, bool);
+
+/// Everything needed to decode a data frame on its own, shared by the decoding threads.
+struct FrameDecoder {
+    out_mode: OutputMode,
+    /// The block size, width and height of the frames as etched.
+    geometry: (i32, i32, i32),
+    final_frame: i32,
+    final_byte: i32,
+    /// Luma threshold for black-and-white blocks and the header strip.
+    threshold: u8,
+    /// Luma thresholds between gray levels, for gray modes.
+    thresholds: Vec<u8>,
+    /// Per-channel corrections, for colored mode.
+    lut: [[u8; 256]; 3],
+    /// Observed palette colors, for palette modes.
+    palette: Vec<[u8; 3]>,
+    blocks: Range<usize>,
+}
+
+impl FrameDecoder {
+    /// Prepares the decoding of the data frames from the header and what the
+    /// calibration frames showed.
+    fn new(header: &Header, settings: &Settings, calibration: &Calibration) -> FrameDecoder {
+        FrameDecoder {
+            out_mode: header.out_mode,
+            geometry: (settings.size, settings.width, settings.height),
+            final_frame: header.final_frame as i32,
+            final_byte: header.final_byte as i32,
+            threshold: calibration.bw_threshold(),
+            thresholds: match header.out_mode {
+                OutputMode::Gray4 | OutputMode::Gray8 => calibration.gray_thresholds(),
+                _ => Vec::new(),
+            },
+            lut: match header.out_mode {
+                OutputMode::Color => calibration.color_lut(),
+                _ => [[0; 256]; 3],
+            },
+            palette: calibration.observed.clone(),
+            blocks: data_blocks(
+                header.out_mode,
+                blocks_per_frame(settings.size, settings.width, settings.height),
+            ),
+        }
     }
 
-    // Attempt to use a lossless codec (PNG)
-    let fourcc = VideoWriter::fourcc('p', 'n', 'g', ' ')?;
+    /// Decodes a data frame read from the video.
+    ///
+    /// # Arguments
+    /// * `frame` - The frame as read.
+    /// * `alignment` - The alignment of the previous frame, updated with this frame's.
+    ///
+    /// # Returns
+    /// * The decoded frame, or `None` if its header strip doesn't hold a data frame index.
+    fn decode(
+        &self,
+        frame: Frame,
+        alignment: &mut Option<Transform>,
+    ) -> anyhow::Result<Option<DecodedFrame>> {
+        let (size, width, height) = self.geometry;
+        let source = frame_source(frame, size, width, height, alignment)?;
+
+        let (current_frame, crc) = read_strip(&source, self.threshold)?;
+        if current_frame == 0 || current_frame > self.final_frame as u32 {
+            return Ok(None);
+        }
+        let index = current_frame as i32;
+        let blocks = self.blocks.clone();
+        let bits = self.out_mode.bits_per_block();
+
+        // Read and decode frame data based on the output mode, checking it against the strip's CRC
+        let (frame_data, valid) = match self.out_mode {
+            OutputMode::Color => {
+                let byte_data = read_color(
+                    &source,
+                    index,
+                    self.final_frame,
+                    self.final_byte,
+                    &self.lut,
+                    blocks,
+                )?;
+                let valid = units_crc(&byte_data) == crc;
+                (byte_data, valid)
+            }
+            OutputMode::Binary => {
+                let binary_data = read_bw(
+                    &source,
+                    index,
+                    self.final_frame,
+                    self.final_byte,
+                    self.threshold,
+                    blocks,
+                )?;
+                let valid = bits_crc(binary_data.reader()) == crc;
+                (binary_data.into_bytes(), valid)
+            }
+            OutputMode::Gray4 | OutputMode::Gray8 => {
+                let symbols = read_gray(
+                    &source,
+                    index,
+                    self.final_frame,
+                    self.final_byte,
+                    &self.thresholds,
+                    blocks,
+                )?;
+                let valid = units_crc(&symbols) == crc;
+                (translate_symbols(symbols, bits)?, valid)
+            }
+            OutputMode::Palette4 | OutputMode::Palette8 => {
+                let symbols = read_palette(
+                    &source,
+                    index,
+                    self.final_frame,
+                    self.final_byte,
+                    &self.palette,
+                    blocks,
+                )?;
+                let valid = units_crc(&symbols) == crc;
+                (translate_symbols(symbols, bits)?, valid)
+            }
+        };
+
+        Ok(Some((current_frame, frame_data, valid)))
+    }
+}
 
-    // Determine frame size based on the first frame in the list
-    let frame_size = complete_frames[1].frame_size;
-    let video = VideoWriter::new(path, fourcc, settings.fps, frame_size, true);
+/// Number of missing frames listed by index when reporting them.
+const MISSING_LISTED: usize = 20;
+
+/// Where the data frames read from a video were staged, and what turned up on the way.
+/// Frames are placed by the index in their header strip rather than by read order, and
+/// the table only grows with the frames actually read, whatever the header claims.
+#[derive(Default)]
+struct FrameTable {
+    /// Offset in the spool, length and CRC match of every frame placed, by index from 0.
+    placed: HashMap<usize, (u64, usize, bool)>,
+    /// Frames placed with a matching CRC.
+    intact: usize,
+    /// Frames read after an intact copy of them was already placed.
+    duplicates: usize,
+    /// Frame reads whose data didn't match the CRC in their header strip.
+    damaged: usize,
+    /// Frames whose header strip doesn't hold a data frame index.
+    unplaceable: usize,
+}
 
-    // Fallback to an alternative codec if PNG fails
-    let mut video = match video {
-        Ok(v) => v,
-        Err(_) => {
-            let fourcc = VideoWriter::fourcc('a', 'v', 'c', '1')?;
-            VideoWriter::new(path, fourcc, settings.fps, frame_size, true)
-                .expect("Both PNG and AVC1 codecs failed. Please raise an issue on GitHub.")
+impl FrameTable {
+    /// Places a decoded frame, appending its data to `spool`. A damaged copy is kept
+    /// until an intact one turns up, error correction may still fix it.
+    fn place(&mut self, decoded: Option<DecodedFrame>, spool: &mut Spool) -> anyhow::Result<()> {
+        let Some((current_frame, frame_data, valid)) = decoded else {
+            self.unplaceable += 1;
+            return Ok(());
+        };
+        let index = current_frame as usize - 1;
+        if matches!(self.placed.get(&index), Some((_, _, true))) {
+            // Repeated frame (e.g. from a frame rate conversion), already have it intact
+            self.duplicates += 1;
+            return Ok(());
         }
-    };
 
-    // Write all frames to the video
-    for frame in complete_frames {
-        let image = frame.image;
-        video.write(&image)?;
+        if valid {
+            self.intact += 1;
+        } else {
+            self.damaged += 1;
+        }
+        self.placed
+            .insert(index, (spool.size(), frame_data.len(), valid));
+        spool.write_all(&frame_data)?;
+        Ok(())
     }
-    video.release()?;
 
-    println!("Video embedded successfully at {}", path);
+    /// Number of data frames that never turned up.
+    fn missing(&self, final_frame: usize) -> usize {
+        final_frame - self.placed.len()
+    }
 
-    Ok(())
+    /// Describes the frames that never turned up, listing the first few by index (from 1).
+    fn describe_missing(&self, final_frame: usize) -> String {
+        let listed: Vec<String> = (0..final_frame)
+            .filter(|index| !self.placed.contains_key(index))
+            .take(MISSING_LISTED)
+            .map(|index| (index + 1).to_string())
+            .collect();
+        let missing = self.missing(final_frame);
+        let more = if missing > listed.len() { ", ..." } else { "" };
+        format!(
+            "{} data frame(s) missing from the video: [{}{}]",
+            missing,
+            listed.join(", "),
+            more
+        )
+    }
 }
 
-/// Reads embedded data from a video file.
+/// Reads embedded data from a video file using multi-threaded frame decoding.
+///
+/// A reader thread deals the frames out to the worker threads in turn, the workers
+/// decode them, and the results are taken back in read order. Frames are staged in a
+/// temporary file as they come back, and the payload is decoded from there and streamed
+/// into `out`, so memory use doesn't grow with the video.
 ///
 /// # Arguments
 /// * `path` - Path to the input video file.
+/// * `backend` - The backend decoding the video, or `None` to pick it from the path.
 /// * `threads` - Number of threads to use for decoding.
+/// * `out` - Where the embedded bytes are written.
 ///
 /// # Returns
-/// * `anyhow::Result<Vec<u8>>` - Returns the embedded byte data or an error.
-pub fn read(path: &str, threads: usize) -> anyhow::Result<Vec<u8>> {
+/// * `anyhow::Result<Header>` - Returns the header describing the embedded bytes, or an error.
+pub fn read(
+    path: &str,
+    backend: Option<Backend>,
+    threads: usize,
+    out: &mut impl Write,
+) -> anyhow::Result<Header> {
     let _timer = Timer::new("Dislodging frame");
-    const INSTRUCTION_SIZE: i32 = 5;
 
     // Open the video file
-    let mut video = VideoCapture::from_file(path, CAP_ANY).expect("Could not open video path");
-    let mut frame = Mat::default();
+    let video = video::open_source(path, backend)?;
+    let (header, _) = read_video(video, threads, out)?;
 
-    // Read the first frame for instructions
-    video.read(&mut frame)?;
-    let instruction_source = EmbedSource::from(frame.clone(), INSTRUCTION_SIZE, true)
-        .expect("Couldn't create instructions");
+    Ok(header)
+}
 
-    let (out_mode, final_frame, final_byte, settings) =
-        read_instructions(&instruction_source, threads)?;
+/// Reads embedded data from an opened video, as `read` does.
+///
+/// # Arguments
+/// * `video` - The opened video, positioned at its first frame.
+/// * `threads` - Number of threads to use for decoding.
+/// * `out` - Where the embedded bytes are written.
+///
+/// # Returns
+/// * The header describing the embedded bytes and where the data frames were placed.
+fn read_video(
+    mut video: Box<dyn FrameSource>,
+    threads: usize,
+    out: &mut impl Write,
+) -> anyhow::Result<(Header, FrameTable)> {
+    // Read the header from the instruction frames
+    let (header, settings) = read_instructions(video.as_mut(), threads)?;
+    let final_frame = header.final_frame as usize;
+    let final_byte = header.final_byte as i32;
+
+    // Learn the decision thresholds and colors from the calibration frames
+    let mut alignment = None;
+    let calibration = read_calibration(video.as_mut(), &settings, &header, &mut alignment)?;
+    let decoder = FrameDecoder::new(&header, &settings, &calibration);
+    let bits = header.out_mode.bits_per_block();
+
+    // Every frame is appended to the spool as it comes back, the table remembers where
+    let mut spool = Spool::new()?;
+    let mut table = FrameTable::default();
+    let mut read_count = 0;
+    let workers = settings.threads.max(1);
+
+    thread::scope(|scope| -> anyhow::Result<()> {
+        let (frame_senders, frame_receivers): (Vec<_>, Vec<_>) = (0..workers)
+            .map(|_| sync_channel::<Frame>(QUEUED_FRAMES))
+            .unzip();
+        let (result_senders, result_receivers): (Vec<_>, Vec<_>) = (0..workers)
+            .map(|_| sync_channel::<anyhow::Result<Option<DecodedFrame>>>(QUEUED_FRAMES))
+            .unzip();
+
+        // Read the frames, dealing them out to the workers in turn
+        let video = &mut video;
+        let feeder = scope.spawn(move || -> anyhow::Result<()> {
+            for index in 0.. {
+                let Some(frame) = video.read_frame()? else {
+                    break;
+                };
+                if frame_senders[index % workers].send(frame).is_err() {
+                    // Every data frame was read intact, or decoding failed
+                    break;
+                }
+            }
+            Ok(())
+        });
+
+        // Decode the frames, every worker aligning them on the last frame it saw
+        for (frames, results) in frame_receivers.into_iter().zip(result_senders) {
+            let decoder = &decoder;
+            let mut alignment = alignment;
+            scope.spawn(move || {
+                for frame in frames {
+                    if results.send(decoder.decode(frame, &mut alignment)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
 
-    let mut byte_data = Vec::new();
-    let mut current_frame = 1;
+        // Take the frames back in read order until every data frame was read intact, ignoring anything else
+        for index in 0.. {
+            if table.intact >= final_frame {
+                break;
+            }
+            let Ok(decoded) = result_receivers[index % workers].recv() else {
+                // The video ended
+                break;
+            };
+            read_count += 1;
+            if read_count % 20 == 0 {
+                println!("On frame: {}", read_count);
+            }
 
-    // Loop through video frames and extract embedded data
-    while video.read(&mut frame)? && frame.cols() > 0 {
-        if current_frame % 20 == 0 {
-            println!("On frame: {}", current_frame);
+            table.place(decoded?, &mut spool)?;
         }
+        drop(result_receivers);
 
-        let source =
-            EmbedSource::from(frame.clone(), settings.size, false).expect("Reading frame failed");
+        feeder
+            .join()
+            .map_err(|_| anyhow!("The thread reading the video panicked"))?
+    })?;
 
-        // Read and decode frame data based on the output mode
-        let frame_data = match out_mode {
-            OutputMode::Color => read_color(&source, current_frame, i32::MAX, final_byte)
-                .expect("Failed to read color frame"),
-            OutputMode::Binary => {
-                let binary_data = read_bw(&source, current_frame, final_frame, final_byte)
-                    .expect("Failed to read binary frame");
-                translate_u8(binary_data).expect("Failed to translate binary data")
+    if table.duplicates > 0 {
+        println!("Skipped {} duplicated frame(s)", table.duplicates);
+    }
+    if table.unplaceable > 0 {
+        println!(
+            "Skipped {} frame(s) with an unreadable header strip",
+            table.unplaceable
+        );
+    }
+    if table.damaged > 0 {
+        println!("{} frame read(s) failed their CRC check", table.damaged);
+    }
+
+    let missing = table.missing(final_frame);
+    if missing > 0 {
+        let description = table.describe_missing(final_frame);
+        // Without the fountain code, missing frames are made up from zeros. Nothing would
+        // notice the holes without error correction, which reports the codewords they leave
+        // beyond repair and passes them on as partial output
+        if header.payload.fountain.is_none() {
+            match header.payload.ecc {
+                None => bail!("{}, the data can't be restored without them", description),
+                Some(_) if missing > table.placed.len() => bail!(
+                    "{}, too many for error correction to restore the data",
+                    description
+                ),
+                Some(_) => (),
             }
+        }
+        println!("{}", description);
+    }
+
+    println!("Video read successfully");
+
+    // Fetches a frame back from the spool
+    let mut stored = spool.reader()?;
+    let mut load_frame = |index: usize| -> anyhow::Result<Option<Vec<u8>>> {
+        let Some((offset, len, _)) = table.placed.get(&index) else {
+            return Ok(None);
         };
+        stored.seek(SeekFrom::Start(*offset))?;
+        let mut frame_data = vec![0u8; *len];
+        stored.read_exact(&mut frame_data)?;
+        Ok(Some(frame_data))
+    };
 
-        byte_data.extend(frame_data);
-        current_frame += 1;
+    // With a fountain code, correct each frame on its own and decode the payload from
+    // whichever frames turned up
+    if let Some(fountain) = &header.payload.fountain {
+        let mut corrections = ecc::Corrections::default();
+        let frame = |index: usize| -> anyhow::Result<Option<Vec<u8>>> {
+            let frame_data = load_frame(index)?;
+            Ok(match &header.payload.ecc {
+                Some(ecc) => {
+                    frame_data.map(|encoded| ecc::decode_frame(&encoded, ecc, &mut corrections))
+                }
+                None => frame_data,
+            })
+        };
+        fountain::decode(fountain, frame, out)?;
+        corrections.report();
+        return Ok((header, table));
     }
 
-    println!("Video read successfully");
-    Ok(byte_data)
+    // Reassemble the payload in frame order, leaving the gaps to error correction
+    let full_frame_bytes = frame_bytes(header.out_mode, &settings);
+    let final_frame_bytes = match header.out_mode {
+        OutputMode::Color => final_byte as usize,
+        _ => final_byte as usize * bits / 8,
+    };
+    let mut assembled = Spool::new()?;
+    for index in 0..final_frame {
+        let frame_data = load_frame(index)?.unwrap_or_else(|| {
+            let len = if index + 1 == final_frame {
+                final_frame_bytes
+            } else {
+                full_frame_bytes
+            };
+            vec![0; len]
+        });
+
+        // Without error correction, the frames are the payload
+        match header.payload.ecc {
+            Some(_) => assembled.write_all(&frame_data)?,
+            None => out.write_all(&frame_data)?,
+        }
+    }
+
+    // Undo the error correction layer, repairing damaged bytes on the way. Codewords
+    // beyond repair are passed on, for dislodge to keep or delete as partial output
+    if let Some(ecc) = &header.payload.ecc {
+        let mut corrections = ecc::Corrections::default();
+        ecc::decode(&mut assembled.reader()?, ecc, out, &mut corrections)?;
+        corrections.report();
+    }
+
+    Ok((header, table))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::{fs, io};
+
+    use super::*;
+    use crate::test_support::sample_bytes;
+
+    /// Block size, width and height of the frames the tests etch.
+    const GEOMETRY: (i32, i32, i32) = (4, 320, 180);
+
+    const MODES: [OutputMode; 6] = [
+        OutputMode::Binary,
+        OutputMode::Color,
+        OutputMode::Gray4,
+        OutputMode::Gray8,
+        OutputMode::Palette4,
+        OutputMode::Palette8,
+    ];
+
+    /// A decoder for `final_frame` frames of `out_mode`, the last one holding `final_byte`
+    /// units, that learned `observed` from the calibration frames.
+    fn decoder(
+        out_mode: OutputMode,
+        geometry: (i32, i32, i32),
+        final_frame: u32,
+        final_byte: u32,
+        observed: Vec<[u8; 3]>,
+    ) -> FrameDecoder {
+        let (size, width, height) = geometry;
+        let header = Header::new(out_mode, size as u32, final_frame, final_byte);
+        let settings = Settings::new(size, 1, 10, width, height);
+        FrameDecoder::new(&header, &settings, &Calibration { observed })
+    }
+
+    /// A decoder for two frames of `out_mode` that learned the nominal colors.
+    fn nominal_decoder(out_mode: OutputMode, geometry: (i32, i32, i32)) -> FrameDecoder {
+        let (size, width, height) = geometry;
+        let blocks = blocks_per_frame(size, width, height);
+        let references = calibration_references(out_mode, &symbol_colors(out_mode));
+        decoder(
+            out_mode,
+            geometry,
+            2,
+            frame_capacity(out_mode, blocks) as u32,
+            references,
+        )
+    }
+
+    /// Etches a full data frame of `out_mode` holding `data`.
+    fn etch_full(data: &[u8], out_mode: OutputMode, geometry: (i32, i32, i32)) -> EmbedSource {
+        etch_frame(
+            data.to_vec(),
+            1,
+            out_mode,
+            &symbol_colors(out_mode),
+            geometry,
+        )
+        .unwrap()
+    }
+
+    /// Refills every block of a range with a new color, computed from its number and the
+    /// color it had.
+    fn recolor_blocks(
+        source: &mut EmbedSource,
+        blocks: Range<usize>,
+        recolor: impl Fn(usize, [u8; 3]) -> [u8; 3],
+    ) {
+        let pixels = source.pixels();
+        let colors: Vec<(i32, i32, [u8; 3])> = block_positions(source, blocks.clone())
+            .zip(blocks)
+            .map(|((x, y), block)| (x, y, recolor(block, pixels.rgb(x, y))))
+            .collect();
+        let mut pixels = source.pixels_mut();
+        for (x, y, rgb) in colors {
+            pixels.fill_block(x, y, rgb);
+        }
+    }
+
+    /// Serves frames from memory, as if read from a video.
+    struct Frames(vec::IntoIter<Frame>);
+
+    impl FrameSource for Frames {
+        fn read_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+            Ok(self.0.next())
+        }
+    }
+
+    /// Etches `data` into a Y4M file in `dir` on `threads` threads, returning its path.
+    fn etch_y4m(dir: &Path, data: Data, threads: usize) -> String {
+        let (size, width, height) = GEOMETRY;
+        let path = dir.join("video.y4m").to_str().unwrap().to_string();
+        let settings = Settings::new(size, threads, 10, width, height);
+        etch(&path, &Encoder::Y4m, data, settings).unwrap();
+        path
+    }
+
+    /// Every frame of the video at `path`, in order.
+    fn video_frames(path: &str) -> Vec<Frame> {
+        let mut video = video::open_source(path, None).unwrap();
+        let mut frames = Vec::new();
+        while let Some(frame) = video.read_frame().unwrap() {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    /// `bytes` to etch as they are, in `mode`.
+    fn plain_data(bytes: &[u8], mode: OutputMode) -> Data {
+        let reader = io::Cursor::new(bytes.to_vec());
+        Data::new(Box::new(reader), bytes.len() as u64, mode)
+    }
+
+    /// Squeezes every channel of a frame into `64..=191`, as a transcode shifting the
+    /// brightness and lowering the contrast would.
+    fn squeeze_levels(mut frame: Frame) -> Frame {
+        for value in frame.data.iter_mut() {
+            *value = 64 + *value / 2;
+        }
+        frame
+    }
+
+    /// Rescales a frame to `width` x `height` with nearest-neighbour sampling.
+    fn scale_nearest(frame: &Frame, width: i32, height: i32) -> Frame {
+        let pixels = Pixels::of(frame);
+        let mut scaled = Frame::new(width, height);
+        for (index, pixel) in scaled.data.chunks_exact_mut(3).enumerate() {
+            let (u, v) = (index as i32 % width, index as i32 / width);
+            let rgb = pixels.rgb(u * frame.width / width, v * frame.height / height);
+            pixel.copy_from_slice(&[rgb[2], rgb[1], rgb[0]]);
+        }
+        scaled
+    }
+
+    #[test]
+    fn gray_levels_map_back_to_their_symbols() {
+        for bits in [2, 3] {
+            let count = 1 << bits;
+            let mut levels: Vec<usize> = (0..count as u8).map(symbol_to_level).collect();
+            for symbol in 0..count as u8 {
+                assert_eq!(level_to_symbol(symbol_to_level(symbol)), symbol);
+            }
+
+            // Every level is used once, and neighbouring levels differ by a single bit
+            levels.sort_unstable();
+            assert_eq!(levels, (0..count).collect::<Vec<_>>());
+            for level in 0..count - 1 {
+                let flipped = level_to_symbol(level) ^ level_to_symbol(level + 1);
+                assert_eq!(
+                    flipped.count_ones(),
+                    1,
+                    "levels {} and {}",
+                    level,
+                    level + 1
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn gray_levels_and_thresholds_are_evenly_spread() {
+        assert_eq!(gray_levels(2), [0, 85, 170, 255]);
+        assert_eq!(gray_levels(3), [0, 36, 72, 109, 145, 182, 218, 255]);
+        assert_eq!(gray_thresholds(&gray_levels(2)), [43, 128, 213]);
+    }
+
+    #[test]
+    fn symbols_round_trip_through_bytes() {
+        let data = sample_bytes(100);
+        for bits in [1, 2, 3] {
+            let symbols = rip_symbols(&data, bits);
+            assert_eq!(symbols.len(), (data.len() * 8).div_ceil(bits));
+            assert!(symbols.iter().all(|&symbol| symbol < 1 << bits));
+            assert_eq!(translate_symbols(symbols, bits).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn frame_capacity_counts_the_units_of_every_mode() {
+        // Short of the header strip, there is no room for data
+        for mode in MODES {
+            assert_eq!(frame_capacity(mode, STRIP_BLOCKS), 0);
+        }
+
+        // Modes other than color only use whole bytes' worth of blocks
+        let blocks = STRIP_BLOCKS + 1003;
+        assert_eq!(
+            data_blocks(OutputMode::Gray8, blocks),
+            STRIP_BLOCKS..STRIP_BLOCKS + 1000
+        );
+        assert_eq!(data_blocks(OutputMode::Color, blocks), STRIP_BLOCKS..blocks);
+        assert_eq!(frame_capacity(OutputMode::Color, blocks), 3009);
+        for mode in [OutputMode::Binary, OutputMode::Gray4, OutputMode::Gray8] {
+            assert_eq!(frame_capacity(mode, blocks), 1000);
+        }
+
+        let (size, width, height) = GEOMETRY;
+        let settings = Settings::new(size, 1, 10, width, height);
+        let blocks = blocks_per_frame(size, width, height);
+        for mode in MODES {
+            let units = frame_capacity(mode, blocks);
+            let bits = match mode {
+                OutputMode::Color => units * 8,
+                _ => units * mode.bits_per_block(),
+            };
+            assert_eq!(frame_bytes(mode, &settings) * 8, bits, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn frames_round_trip_in_every_mode() {
+        let (size, width, height) = GEOMETRY;
+        let settings = Settings::new(size, 1, 10, width, height);
+        for mode in MODES {
+            let data = sample_bytes(frame_bytes(mode, &settings));
+            let etched = etch_full(&data, mode, GEOMETRY);
+
+            let decoded = nominal_decoder(mode, GEOMETRY)
+                .decode(etched.image, &mut None)
+                .unwrap();
+            assert_eq!(decoded, Some((1, data, true)), "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn palettes_are_well_separated() {
+        for bits in [2, 3] {
+            let palette = palette_colors(bits);
+            assert_eq!(palette.len(), 1 << bits);
+            for (i, first) in palette.iter().enumerate() {
+                for second in &palette[i + 1..] {
+                    assert_ne!(luma(first), luma(second));
+                    assert!((0..3).any(|c| first[c].abs_diff(second[c]) == 255));
+                }
+            }
+        }
+
+        // In the eight color palette, a symbol's bits are its red, green and blue channels
+        for (symbol, color) in palette_colors(3).iter().enumerate() {
+            let channels = (color[0] / 255) << 2 | (color[1] / 255) << 1 | (color[2] / 255);
+            assert_eq!(channels as usize, symbol);
+        }
+    }
+
+    #[test]
+    fn palette_frames_decode_the_nearest_color_under_noise() {
+        let (size, width, height) = GEOMETRY;
+        let settings = Settings::new(size, 1, 10, width, height);
+        let blocks = blocks_per_frame(size, width, height);
+        for mode in [OutputMode::Palette4, OutputMode::Palette8] {
+            let data = sample_bytes(frame_bytes(mode, &settings));
+            for noise in [0, 40, 100] {
+                // Shift every channel of every data block by up to `noise` either way
+                let mut etched = etch_full(&data, mode, GEOMETRY);
+                recolor_blocks(&mut etched, data_blocks(mode, blocks), |block, rgb| {
+                    let mut noisy = rgb;
+                    for (channel, value) in noisy.iter_mut().enumerate() {
+                        let hash = (block * 3 + channel).wrapping_mul(0x9e37_79b9) >> 7;
+                        let offset = (hash % (2 * noise + 1)) as i32 - noise as i32;
+                        *value = (*value as i32 + offset).clamp(0, 255) as u8;
+                    }
+                    noisy
+                });
+
+                let decoded = nominal_decoder(mode, GEOMETRY)
+                    .decode(etched.image, &mut None)
+                    .unwrap();
+                assert_eq!(
+                    decoded,
+                    Some((1, data.clone(), true)),
+                    "{:?} ±{}",
+                    mode,
+                    noise
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn calibration_frames_correct_shifted_levels() {
+        let (size, width, height) = GEOMETRY;
+        let settings = Settings::new(size, 1, 10, width, height);
+        let blocks = blocks_per_frame(size, width, height);
+        for mode in [OutputMode::Binary, OutputMode::Gray4, OutputMode::Gray8] {
+            let references = calibration_references(mode, &symbol_colors(mode));
+            let calibration_frames = etch_calibration(&settings, &references).unwrap();
+            let mut header = Header::new(mode, size as u32, 2, frame_capacity(mode, blocks) as u32);
+            header.calibration_frames = calibration_frames.len() as u16;
+
+            let shifted: Vec<Frame> = calibration_frames
+                .into_iter()
+                .map(|frame| squeeze_levels(frame.image))
+                .collect();
+            let mut alignment = None;
+            let calibration = read_calibration(
+                &mut Frames(shifted.into_iter()),
+                &settings,
+                &header,
+                &mut alignment,
+            )
+            .unwrap();
+            assert_eq!(calibration.bw_threshold(), 128);
+
+            let data = sample_bytes(frame_bytes(mode, &settings));
+            let frame = squeeze_levels(etch_full(&data, mode, GEOMETRY).image);
+            let decoded = FrameDecoder::new(&header, &settings, &calibration)
+                .decode(frame.clone(), &mut alignment)
+                .unwrap();
+            assert_eq!(decoded, Some((1, data.clone(), true)), "{:?}", mode);
+
+            // The nominal levels misread the darkest and brightest gray levels
+            if mode != OutputMode::Binary {
+                let (_, nominal, _) = nominal_decoder(mode, GEOMETRY)
+                    .decode(frame, &mut None)
+                    .unwrap()
+                    .unwrap();
+                assert_ne!(nominal, data, "{:?}", mode);
+            }
+        }
+    }
+
+    #[test]
+    fn bits_crc_hashes_every_bit_as_a_byte() {
+        // Longer than the buffer `bits_crc` hashes at a time
+        let data = sample_bytes(1500);
+        let units: Vec<u8> = BitReader::new(&data).map(|bit| bit as u8).collect();
+        assert_eq!(bits_crc(BitReader::new(&data)), units_crc(&units));
+    }
+
+    #[test]
+    fn strip_survives_one_damaged_copy() {
+        let (size, width, height) = GEOMETRY;
+        for copy in 0..STRIP_COPIES {
+            let mut source = blank_frame(size, width, height).unwrap();
+            etch_strip(&mut source, 5, 0xdead_beef).unwrap();
+            let copy_blocks = copy * STRIP_BITS..(copy + 1) * STRIP_BITS;
+            recolor_blocks(&mut source, copy_blocks, |_, rgb| {
+                rgb.map(|value| 255 - value)
+            });
+
+            assert_eq!(
+                read_strip(&source, DEFAULT_THRESHOLD).unwrap(),
+                (5, 0xdead_beef)
+            );
+        }
+    }
+
+    #[test]
+    fn strip_with_two_damaged_copies_is_rejected() {
+        let (size, width, height) = GEOMETRY;
+        let settings = Settings::new(size, 1, 10, width, height);
+        let data = sample_bytes(frame_bytes(OutputMode::Binary, &settings));
+        let decoder = nominal_decoder(OutputMode::Binary, GEOMETRY);
+        let invert = |_: usize, rgb: [u8; 3]| rgb.map(|value| 255 - value);
+
+        // A single damaged copy is outvoted, the frame is still placed and intact
+        let mut etched = etch_full(&data, OutputMode::Binary, GEOMETRY);
+        recolor_blocks(&mut etched, 0..STRIP_BITS, invert);
+        let decoded = decoder.decode(etched.image.clone(), &mut None).unwrap();
+        assert_eq!(decoded, Some((1, data.clone(), true)));
+
+        // A second one wins the vote, and the frame index it spells isn't one of the video's
+        recolor_blocks(&mut etched, 2 * STRIP_BITS..STRIP_BLOCKS, invert);
+        assert_eq!(
+            read_strip(&etched, DEFAULT_THRESHOLD).unwrap(),
+            (!1, !bits_crc(BitReader::new(&data)))
+        );
+        assert_eq!(decoder.decode(etched.image, &mut None).unwrap(), None);
+    }
+
+    #[test]
+    fn frames_upscaled_after_embedding_still_decode() {
+        // Etched at 720p, read back at 1080p
+        let geometry = (4, 1280, 720);
+        let settings = Settings::new(4, 1, 10, 1280, 720);
+        for mode in [OutputMode::Binary, OutputMode::Gray4] {
+            let len = frame_bytes(mode, &settings) * 2;
+            let data = Data::new(Box::new(io::empty()), len as u64, mode);
+            let instructions: Vec<Frame> = etch_instructions(&settings, &data)
+                .unwrap()
+                .into_iter()
+                .map(|frame| scale_nearest(&frame.image, 1920, 1080))
+                .collect();
+            assert_eq!(
+                instruction_resolution(&instructions[0]).unwrap(),
+                (1280, 720)
+            );
+
+            let (header, read_settings) =
+                read_instructions(&mut Frames(instructions.into_iter()), 1).unwrap();
+            assert_eq!(header.final_frame, 2);
+            assert_eq!((read_settings.width, read_settings.height), (1280, 720));
+
+            let chunk = sample_bytes(frame_bytes(mode, &settings));
+            let frame = scale_nearest(&etch_full(&chunk, mode, geometry).image, 1920, 1080);
+            let references = calibration_references(mode, &symbol_colors(mode));
+            let decoded = FrameDecoder::new(
+                &header,
+                &read_settings,
+                &Calibration {
+                    observed: references,
+                },
+            )
+            .decode(frame, &mut None)
+            .unwrap();
+            assert_eq!(decoded, Some((1, chunk, true)), "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn shuffled_duplicated_and_dropped_frames_are_accounted_for() {
+        let dir = tempfile::tempdir().unwrap();
+        let (size, width, height) = GEOMETRY;
+        let frame_bytes = frame_bytes(
+            OutputMode::Binary,
+            &Settings::new(size, 1, 10, width, height),
+        );
+        let bytes = sample_bytes(5 * frame_bytes - 100);
+        let mut spool = Spool::new().unwrap();
+        spool.write_all(&bytes).unwrap();
+        let (encoded, fountain) = fountain::encode(&mut spool, frame_bytes, 50).unwrap();
+        let len = encoded.size();
+        let mut data = Data::new(
+            Box::new(encoded.into_reader().unwrap()),
+            len,
+            OutputMode::Binary,
+        );
+        data.payload.fountain = Some(fountain);
+        let path = etch_y4m(dir.path(), data, 1);
+
+        // Swap the first two data frames, repeat the third and drop the fourth
+        let mut frames = video_frames(&path);
+        let first = frames.len() - len.div_ceil(frame_bytes as u64) as usize;
+        frames.swap(first, first + 1);
+        frames.insert(first + 3, frames[first + 2].clone());
+        frames.remove(first + 4);
+
+        let mut out = Vec::new();
+        let (header, table) =
+            read_video(Box::new(Frames(frames.into_iter())), 2, &mut out).unwrap();
+        assert_eq!(out, bytes);
+        assert_eq!(table.duplicates, 1);
+        assert_eq!(table.damaged, 0);
+        assert_eq!(table.unplaceable, 0);
+        let final_frame = header.final_frame as usize;
+        assert_eq!(table.missing(final_frame), 1);
+        assert!(table.describe_missing(final_frame).ends_with("[4]"));
+    }
+
+    #[test]
+    fn videos_round_trip_through_y4m_files_and_png_sequences() {
+        let (size, width, height) = GEOMETRY;
+        for (name, encoder) in [("video.y4m", Encoder::Y4m), ("frames", Encoder::Images)] {
+            for mode in MODES {
+                let settings = Settings::new(size, 2, 10, width, height);
+                let bytes = sample_bytes(3 * frame_bytes(mode, &settings) / 2);
+                let dir = tempfile::tempdir().unwrap();
+                let path = dir.path().join(name);
+                let path = path.to_str().unwrap();
+
+                etch(path, &encoder, plain_data(&bytes, mode), settings).unwrap();
+                let mut out = Vec::new();
+                read(path, None, 2, &mut out).unwrap();
+                assert!(out == bytes, "{} {:?}", name, mode);
+            }
+        }
+    }
+
+    #[test]
+    fn reads_write_exactly_the_embedded_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (size, width, height) = GEOMETRY;
+        let settings = Settings::new(size, 1, 10, width, height);
+        for mode in [OutputMode::Binary, OutputMode::Color, OutputMode::Gray8] {
+            let frame_bytes = frame_bytes(mode, &settings);
+            // A full final frame, then final frames cut short
+            for len in [2 * frame_bytes, 2 * frame_bytes + 1, 3 * frame_bytes - 1] {
+                let bytes = sample_bytes(len);
+                let path = etch_y4m(dir.path(), plain_data(&bytes, mode), 1);
+                let mut out = Vec::new();
+                read(&path, None, 1, &mut out).unwrap();
+                assert_eq!(out.len(), len, "{:?}", mode);
+                assert!(out == bytes, "{:?} {}", mode, len);
+            }
+        }
+    }
+
+    #[test]
+    fn etching_more_frames_than_the_queues_hold_round_trips() {
+        let (size, width, height) = GEOMETRY;
+        let settings = Settings::new(size, 1, 10, width, height);
+        let workers = 4;
+        let frames = 5 * QUEUED_FRAMES * workers;
+        let bytes = sample_bytes(frames * frame_bytes(OutputMode::Binary, &settings) - 1);
+
+        // The frames come back in order whatever the number of workers rendering them
+        let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+        let paths: Vec<String> = [1, workers]
+            .into_iter()
+            .zip(&dirs)
+            .map(|(threads, dir)| {
+                etch_y4m(dir.path(), plain_data(&bytes, OutputMode::Binary), threads)
+            })
+            .collect();
+        assert!(fs::read(&paths[0]).unwrap() == fs::read(&paths[1]).unwrap());
+
+        let mut out = Vec::new();
+        read(&paths[1], None, workers, &mut out).unwrap();
+        assert!(out == bytes);
+    }
+
+    #[test]
+    fn reads_on_one_or_several_threads_agree() {
+        let dir = tempfile::tempdir().unwrap();
+        let (size, width, height) = GEOMETRY;
+        let settings = Settings::new(size, 1, 10, width, height);
+        let bytes = sample_bytes(6 * frame_bytes(OutputMode::Gray4, &settings) - 10);
+        let path = etch_y4m(dir.path(), plain_data(&bytes, OutputMode::Gray4), 1);
+
+        let outputs: Vec<Vec<u8>> = [1, 4]
+            .into_iter()
+            .map(|threads| {
+                let mut out = Vec::new();
+                read(&path, None, threads, &mut out).unwrap();
+                out
+            })
+            .collect();
+        assert!(outputs[0] == outputs[1]);
+        assert!(outputs[0] == bytes);
+    }
+
+    #[test]
+    fn frames_missing_without_error_correction_are_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let (size, width, height) = GEOMETRY;
+        let frame_bytes = frame_bytes(
+            OutputMode::Binary,
+            &Settings::new(size, 1, 10, width, height),
+        );
+        let bytes = sample_bytes(3 * frame_bytes);
+        let path = etch_y4m(dir.path(), plain_data(&bytes, OutputMode::Binary), 1);
+
+        let mut frames = video_frames(&path);
+        frames.remove(frames.len() - 2);
+        let error = read_video(Box::new(Frames(frames.into_iter())), 1, &mut Vec::new())
+            .err()
+            .unwrap();
+        assert!(
+            error.to_string().contains("missing from the video: [2]"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn missing_frames_are_listed_without_walking_the_claimed_count() {
+        let description = FrameTable::default().describe_missing(u32::MAX as usize);
+        assert!(description.starts_with("4294967295 data frame(s) missing from the video: [1, 2,"));
+        assert!(description.ends_with("19, 20, ...]"));
+    }
 }
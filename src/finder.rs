@@ -0,0 +1,610 @@
+use std::ops::Range;
+
+use crate::source::{EmbedSource, Pixels};
+use crate::video::Frame;
+
+/// Size of a finder pattern module, in pixels of the frame as etched.
+const MODULE: i32 = 4;
+
+/// Modules per side of a finder pattern: a dark ring, a light ring and a 3x3 dark core.
+const PATTERN_MODULES: i32 = 7;
+
+/// Light modules between the pattern and the data blocks, so it stays detectable when
+/// its neighbours are dark.
+const INNER_QUIET_MODULES: i32 = 1;
+
+/// Modules left free of data along every edge of the frame, so the patterns stay
+/// detectable when the frame is padded with black and slight cropping loses no data.
+/// Together with the pattern they keep data blocks on the 8 pixel grid codecs compress in.
+const MARGIN_MODULES: i32 = 2;
+
+/// Side of the square reserved in every corner of the frame, in pixels.
+const RESERVED: i32 = (MARGIN_MODULES + PATTERN_MODULES + INNER_QUIET_MODULES) * MODULE;
+
+/// Luma below which a pixel belongs to a dark module.
+const DARK_THRESHOLD: u8 = 128;
+
+/// Minimum number of scan lines that must agree on a pattern before it is trusted,
+/// so chance arrangements of data blocks aren't mistaken for it.
+const MIN_CONFIRMATIONS: usize = 2;
+
+/// A projective transformation mapping positions on the frame as etched to positions
+/// on the frame as read, stored as a row-major 3x3 matrix.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    matrix: [f64; 9],
+}
+
+impl Transform {
+    /// The transformation leaving every position untouched.
+    pub fn identity() -> Transform {
+        Transform::scale(1.0, 1.0)
+    }
+
+    /// A transformation stretching the frame horizontally by `x` and vertically by `y`.
+    pub fn scale(x: f64, y: f64) -> Transform {
+        Transform {
+            matrix: [x, 0.0, 0.0, 0.0, y, 0.0, 0.0, 0.0, 1.0],
+        }
+    }
+
+    /// Fits a transformation to pairs of matching positions: an affine one from three
+    /// pairs, a perspective one from four.
+    ///
+    /// # Arguments
+    /// * `from` - Positions on the frame as etched.
+    /// * `to` - The matching positions on the frame as read.
+    ///
+    /// # Returns
+    /// The transformation, or `None` if the positions are degenerate (e.g. collinear).
+    fn fit(from: &[(f64, f64)], to: &[(f64, f64)]) -> Option<Transform> {
+        match from.len() {
+            3 => {
+                // x' = a x + b y + c and y' = d x + e y + f, solved one output at a time
+                let rows: Vec<Vec<f64>> = from.iter().map(|&(x, y)| vec![x, y, 1.0]).collect();
+                let first = solve(rows.clone(), to.iter().map(|p| p.0).collect())?;
+                let second = solve(rows, to.iter().map(|p| p.1).collect())?;
+                Some(Transform {
+                    matrix: [
+                        first[0], first[1], first[2], second[0], second[1], second[2], 0.0, 0.0,
+                        1.0,
+                    ],
+                })
+            }
+            4 => {
+                // Homography with its last coefficient fixed to 1
+                let mut rows = Vec::new();
+                let mut values = Vec::new();
+                for (&(x, y), &(u, v)) in from.iter().zip(to) {
+                    rows.push(vec![x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y]);
+                    values.push(u);
+                    rows.push(vec![0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y]);
+                    values.push(v);
+                }
+                let h = solve(rows, values)?;
+                Some(Transform {
+                    matrix: [h[0], h[1], h[2], h[3], h[4], h[5], h[6], h[7], 1.0],
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// How far the transformation is from a uniform scale plus translation: the relative
+    /// difference between its horizontal and vertical scales plus the skew between its
+    /// axes, measured at the origin.
+    fn irregularity(&self) -> f64 {
+        let m = &self.matrix;
+        let (horizontal, vertical) = (m[0].hypot(m[3]), m[1].hypot(m[4]));
+        let skew = (m[0] * m[1] + m[3] * m[4]) / (horizontal * vertical);
+        (horizontal - vertical).abs() / horizontal.max(vertical) + skew.abs()
+    }
+
+    /// Whether the transformation could come from resizing, cropping or slightly
+    /// distorting a frame, rather than from a misplaced pattern: it must keep the frame
+    /// upright, with axes of comparable scale that stay close to perpendicular.
+    fn is_plausible(&self) -> bool {
+        let m = &self.matrix;
+        let determinant = m[0] * m[4] - m[1] * m[3];
+        determinant > 0.0 && m[0] > 0.0 && m[4] > 0.0 && self.irregularity() < 0.5
+    }
+
+    /// Maps a position on the frame as etched to the frame as read.
+    pub fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        let m = &self.matrix;
+        let w = m[6] * x + m[7] * y + m[8];
+        (
+            (m[0] * x + m[1] * y + m[2]) / w,
+            (m[3] * x + m[4] * y + m[5]) / w,
+        )
+    }
+
+    /// Whether the transformation moves no corner of a `width` x `height` frame by more
+    /// than `tolerance` pixels, so blocks can be sampled where they were etched.
+    pub fn is_identity(&self, width: i32, height: i32, tolerance: f64) -> bool {
+        let (width, height) = (width as f64, height as f64);
+        [(0.0, 0.0), (width, 0.0), (0.0, height), (width, height)]
+            .iter()
+            .all(|&(x, y)| {
+                let (u, v) = self.apply(x, y);
+                (u - x).abs() <= tolerance && (v - y).abs() <= tolerance
+            })
+    }
+}
+
+/// Solves a square linear system by Gaussian elimination with partial pivoting.
+fn solve(mut rows: Vec<Vec<f64>>, mut values: Vec<f64>) -> Option<Vec<f64>> {
+    let n = values.len();
+    for column in 0..n {
+        let pivot =
+            (column..n).max_by(|&a, &b| rows[a][column].abs().total_cmp(&rows[b][column].abs()))?;
+        if rows[pivot][column].abs() < 1e-9 {
+            return None;
+        }
+        rows.swap(column, pivot);
+        values.swap(column, pivot);
+
+        for row in column + 1..n {
+            let factor = rows[row][column] / rows[column][column];
+            let pivot_row = rows[column].clone();
+            for (value, pivot) in rows[row].iter_mut().zip(pivot_row).skip(column) {
+                *value -= factor * pivot;
+            }
+            values[row] -= factor * values[column];
+        }
+    }
+
+    let mut solution = vec![0.0; n];
+    for row in (0..n).rev() {
+        let known: f64 = (row + 1..n).map(|k| rows[row][k] * solution[k]).sum();
+        solution[row] = (values[row] - known) / rows[row][row];
+    }
+    Some(solution)
+}
+
+/// Whether a block overlaps the part of the frame reserved for the finder patterns: the
+/// squares in its corners, and a margin as wide as their quiet zone along its edges that
+/// keeps data clear of slight cropping. Such blocks carry no data.
+///
+/// # Arguments
+/// * `x` - The x-coordinate of the block.
+/// * `y` - The y-coordinate of the block.
+/// * `size` - The block size in pixels per side.
+/// * `width` - The frame width in pixels.
+/// * `height` - The frame height in pixels.
+pub fn is_reserved(x: i32, y: i32, size: i32, width: i32, height: i32) -> bool {
+    let margin = MARGIN_MODULES * MODULE;
+    if x < margin || y < margin || x + size > width - margin || y + size > height - margin {
+        return true;
+    }
+
+    let near_side = x < RESERVED || x + size > width - RESERVED;
+    let near_edge = y < RESERVED || y + size > height - RESERVED;
+    near_side && near_edge
+}
+
+/// Centers of the four finder patterns on a `width` x `height` frame as etched: top-left,
+/// top-right, bottom-left and bottom-right.
+fn expected_centers(width: i32, height: i32) -> [(f64, f64); 4] {
+    let offset = (MARGIN_MODULES * MODULE) as f64 + (PATTERN_MODULES * MODULE) as f64 / 2.0;
+    let (right, bottom) = (width as f64 - offset, height as f64 - offset);
+    [
+        (offset, offset),
+        (right, offset),
+        (offset, bottom),
+        (right, bottom),
+    ]
+}
+
+/// Etches a finder pattern, surrounded by its quiet zone, into every corner of a frame.
+///
+/// # Arguments
+/// * `source` - The frame, laid out on its `frame_size`.
+pub fn etch_finders(source: &mut EmbedSource) -> anyhow::Result<()> {
+    let width = source.frame_size.width;
+    let height = source.frame_size.height;
+    let corners = [
+        (0, 0),
+        (width - RESERVED, 0),
+        (0, height - RESERVED),
+        (width - RESERVED, height - RESERVED),
+    ];
+
+    for ((x, y), (center_x, center_y)) in corners.into_iter().zip(expected_centers(width, height)) {
+        // Light quiet zone, then the dark ring, the light ring and the core, each drawn
+        // as a square over the previous one. A pixel belongs to a square when its center
+        // is within the square's reach of the pattern's center.
+        fill(&mut source.image, x..x + RESERVED, y..y + RESERVED, 255);
+        for (modules, value) in [(3.5, 0), (2.5, 255), (1.5, 0)] {
+            let reach = modules * MODULE as f64;
+            let span = |center: f64| {
+                (center - reach - 0.5).floor() as i32 + 1..(center + reach - 0.5).ceil() as i32
+            };
+            fill(&mut source.image, span(center_x), span(center_y), value);
+        }
+    }
+
+    Ok(())
+}
+
+/// Fills a rectangle of an image with a gray level, a row slice at a time.
+///
+/// # Arguments
+/// * `image` - The image to draw on.
+/// * `columns` - The columns of the rectangle.
+/// * `rows` - The rows of the rectangle.
+/// * `value` - The brightness to fill it with.
+fn fill(image: &mut Frame, columns: Range<i32>, rows: Range<i32>, value: u8) {
+    let width = image.width as usize;
+    for row in rows {
+        let start = (row as usize * width + columns.start as usize) * 3;
+        let end = start + columns.len() * 3;
+        image.data[start..end].fill(value);
+    }
+}
+
+/// Whether the run lengths along a line match the 1:1:3:1:1 proportions of a finder pattern.
+fn is_pattern(runs: &[i32; 5]) -> bool {
+    let total: i32 = runs.iter().sum();
+    if total < PATTERN_MODULES {
+        return false;
+    }
+
+    let module = total as f64 / PATTERN_MODULES as f64;
+    let tolerance = module / 2.0;
+    runs.iter().enumerate().all(|(index, &run)| {
+        let expected = if index == 2 { 3.0 } else { 1.0 };
+        (run as f64 - expected * module).abs() < expected * tolerance
+    })
+}
+
+/// Measures the finder pattern runs along a line through a dark pixel, walking both ways.
+///
+/// # Arguments
+/// * `dark` - Whether the pixel at a position along the line is dark.
+/// * `start` - The position of a pixel of the pattern's core.
+/// * `limit` - The number of pixels along the line.
+///
+/// # Returns
+/// The center of the core along the line and the total length of the pattern, if the
+/// runs have the proportions of a finder pattern.
+fn cross_check(dark: impl Fn(i32) -> bool, start: i32, limit: i32) -> Option<(f64, i32)> {
+    let mut runs = [0; 5];
+
+    // Walk back through the core, the light ring and the dark ring
+    let mut position = start;
+    for (run, expect_dark) in [(2, true), (1, false), (0, true)] {
+        while position >= 0 && dark(position) == expect_dark {
+            runs[run] += 1;
+            position -= 1;
+        }
+    }
+
+    // Then forward, the core pixel at `start` was already counted
+    let mut position = start + 1;
+    for (run, expect_dark) in [(2, true), (3, false), (4, true)] {
+        while position < limit && dark(position) == expect_dark {
+            runs[run] += 1;
+            position += 1;
+        }
+    }
+
+    if !is_pattern(&runs) {
+        return None;
+    }
+    let end = position as f64;
+    let center = end - runs[4] as f64 - runs[3] as f64 - runs[2] as f64 / 2.0;
+    Some((center, runs.iter().sum()))
+}
+
+/// Locates the finder patterns of a frame as read, searching for each in its own corner.
+///
+/// Rows are scanned for runs with the 1:1:3:1:1 proportions of the pattern, and every
+/// hit is confirmed by measuring the pattern vertically and horizontally through it.
+/// Hits within a module of each other are grouped, and the group closest to the corner
+/// among those confirmed by several scan lines is taken as the pattern.
+///
+/// # Arguments
+/// * `image` - The frame as read.
+///
+/// # Returns
+/// The center of each pattern, in the order of `expected_centers`, if it was found.
+pub fn locate(image: &Frame) -> [Option<(f64, f64)>; 4] {
+    let (columns, rows) = (image.width, image.height);
+    let pixels = Pixels::of(image);
+    let dark = |x: i32, y: i32| -> bool {
+        if x < 0 || y < 0 || x >= columns || y >= rows {
+            return false;
+        }
+        let rgb = pixels.rgb(x, y);
+        let luma = (299 * rgb[0] as u32 + 587 * rgb[1] as u32 + 114 * rgb[2] as u32) / 1000;
+        luma < DARK_THRESHOLD as u32
+    };
+
+    // Each pattern is searched for in the outer third of the frame around its corner
+    let (window_width, window_height) = (columns / 3, rows / 3);
+    let windows = [
+        (0, 0, 0, 0),
+        (columns - window_width, 0, columns, 0),
+        (0, rows - window_height, 0, rows),
+        (columns - window_width, rows - window_height, columns, rows),
+    ];
+
+    windows.map(|(left, top, corner_x, corner_y)| {
+        // Groups of hits: summed centers, summed module sizes and number of hits
+        let mut groups: Vec<(f64, f64, f64, usize)> = Vec::new();
+
+        for y in top..top + window_height {
+            // Run lengths along the row, as (is dark, start, length)
+            let mut line: Vec<(bool, i32, i32)> = Vec::new();
+            for x in left..left + window_width {
+                let is_dark = dark(x, y);
+                match line.last_mut() {
+                    Some(run) if run.0 == is_dark => run.2 += 1,
+                    _ => line.push((is_dark, x, 1)),
+                }
+            }
+
+            for window in line.windows(5).filter(|window| window[0].0) {
+                let runs = [0, 1, 2, 3, 4].map(|index| window[index].2);
+                if !is_pattern(&runs) {
+                    continue;
+                }
+
+                // Confirm vertically through the middle of the core, then horizontally again
+                let core = window[2].1 + window[2].2 / 2;
+                let Some((center_y, vertical)) = cross_check(|y| dark(core, y), y, rows) else {
+                    continue;
+                };
+                let Some((center_x, horizontal)) =
+                    cross_check(|x| dark(x, center_y as i32), core, columns)
+                else {
+                    continue;
+                };
+                if (vertical - horizontal).abs() * 5 > horizontal * 2 {
+                    continue;
+                }
+
+                let module = (vertical + horizontal) as f64 / 2.0 / PATTERN_MODULES as f64;
+                match groups.iter_mut().find(|group| {
+                    let count = group.3 as f64;
+                    (group.0 / count - center_x).abs() < module
+                        && (group.1 / count - center_y).abs() < module
+                }) {
+                    Some(group) => {
+                        group.0 += center_x;
+                        group.1 += center_y;
+                        group.2 += module;
+                        group.3 += 1;
+                    }
+                    None => groups.push((center_x, center_y, module, 1)),
+                }
+            }
+        }
+
+        // Nothing lies between a pattern and its corner but the quiet zone and padding,
+        // so the confirmed hit closest to the corner is the pattern
+        let distance =
+            |(x, y, _): (f64, f64, f64)| (x - corner_x as f64).hypot(y - corner_y as f64);
+        let (x, y, module) = groups
+            .into_iter()
+            .filter(|group| group.3 >= MIN_CONFIRMATIONS)
+            .map(|(x, y, module, count)| {
+                let count = count as f64;
+                (x / count, y / count, module / count)
+            })
+            .min_by(|&a, &b| distance(a).total_cmp(&distance(b)))?;
+
+        // Re-measure the center through every line crossing the core and keep the median,
+        // so a partly damaged pattern doesn't pull it off
+        let reach = module.round() as i32;
+        let (core_x, core_y) = (x as i32, y as i32);
+        let centers_x = (core_y - reach..=core_y + reach)
+            .filter_map(|row| cross_check(|x| dark(x, row), core_x, columns))
+            .map(|(center, _)| center);
+        let centers_y = (core_x - reach..=core_x + reach)
+            .filter_map(|column| cross_check(|y| dark(column, y), core_y, rows))
+            .map(|(center, _)| center);
+        Some((
+            median(centers_x).unwrap_or(x),
+            median(centers_y).unwrap_or(y),
+        ))
+    })
+}
+
+/// The median of a series of measurements, if there are any.
+fn median(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let mut values: Vec<f64> = values.collect();
+    values.sort_by(f64::total_cmp);
+    values.get(values.len() / 2).copied()
+}
+
+/// Computes the transformation from a `width` x `height` frame as etched to the frame
+/// as read, from the finder patterns located on the latter.
+///
+/// A damaged pattern or a chance match among the data could skew the whole frame, so
+/// patterns are checked against each other. Given the alignment of a previous frame,
+/// patterns more than a couple of modules from where it predicts them are left out.
+/// Otherwise, four patterns are only trusted together if each of them sits where the
+/// other three predict, and the corner that disagrees is left out.
+///
+/// # Arguments
+/// * `found` - The located pattern centers, as returned by `locate`.
+/// * `width` - The width of the frame as etched.
+/// * `height` - The height of the frame as etched.
+/// * `previous` - The alignment of a previous frame of the same video, if any.
+///
+/// # Returns
+/// A perspective transformation if all four patterns were found and agree, an affine
+/// one from three of them, or `None` if too few plausible patterns were found.
+pub fn transform(
+    found: &[Option<(f64, f64)>; 4],
+    width: i32,
+    height: i32,
+    previous: Option<&Transform>,
+) -> Option<Transform> {
+    let expected = expected_centers(width, height);
+    let tolerance = (width as f64).hypot(height as f64) / 50.0;
+    let fit = |corners: &[usize]| -> Option<Transform> {
+        let from: Vec<(f64, f64)> = corners.iter().map(|&corner| expected[corner]).collect();
+        let to: Vec<(f64, f64)> = corners
+            .iter()
+            .map(|&corner| found[corner])
+            .collect::<Option<_>>()?;
+        Transform::fit(&from, &to).filter(Transform::is_plausible)
+    };
+
+    if let Some(previous) = previous {
+        // Frames of a video share their geometry, so patterns may only move by a couple
+        // of modules between frames
+        let predicted = expected.map(|(x, y)| previous.apply(x, y));
+        let scale = (predicted[3].0 - predicted[0].0).hypot(predicted[3].1 - predicted[0].1)
+            / (expected[3].0 - expected[0].0).hypot(expected[3].1 - expected[0].1);
+        let tolerance = 2.0 * MODULE as f64 * scale;
+
+        let agreeing: Vec<usize> = (0..4)
+            .filter(|&corner| {
+                found[corner].is_some_and(|(x, y)| {
+                    (predicted[corner].0 - x).hypot(predicted[corner].1 - y) <= tolerance
+                })
+            })
+            .collect();
+        return match agreeing.len() {
+            3 | 4 => fit(&agreeing),
+            _ => None,
+        };
+    }
+
+    // The affine fit of three corners must predict the fourth within a few modules
+    if let (Some(affine), Some((x, y))) = (fit(&[0, 1, 2]), found[3]) {
+        let (predicted_x, predicted_y) = affine.apply(expected[3].0, expected[3].1);
+        if (predicted_x - x).hypot(predicted_y - y) <= tolerance {
+            return fit(&[0, 1, 2, 3]);
+        }
+    }
+
+    // Otherwise align on the three corners fitting the most regular frame
+    [[0, 1, 2], [0, 1, 3], [0, 2, 3], [1, 2, 3]]
+        .iter()
+        .filter_map(|corners| fit(corners))
+        .min_by(|a, b| a.irregularity().total_cmp(&b.irregularity()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: i32 = 640;
+    const HEIGHT: i32 = 360;
+
+    /// A frame with its finder patterns and a pseudo-random pattern of 8 pixel data blocks.
+    fn etched() -> Frame {
+        let mut source = EmbedSource::new(8, WIDTH, HEIGHT);
+        let mut pixels = source.pixels_mut();
+        for y in (0..HEIGHT).step_by(8) {
+            for x in (0..WIDTH).step_by(8) {
+                if !is_reserved(x, y, 8, WIDTH, HEIGHT) && (x * 7 + y * 13) % 11 < 5 {
+                    pixels.fill_block(x, y, [255, 255, 255]);
+                }
+            }
+        }
+        etch_finders(&mut source).unwrap();
+        source.image
+    }
+
+    /// Resamples `image` as if it was scaled by `scale` and shifted by `(dx, dy)` onto a
+    /// black `width` x `height` frame.
+    fn resample(image: &Frame, scale: f64, dx: f64, dy: f64, width: i32, height: i32) -> Frame {
+        let pixels = Pixels::of(image);
+        let mut resampled = Frame::new(width, height);
+        for v in 0..height {
+            for u in 0..width {
+                let x = ((u as f64 + 0.5 - dx) / scale).floor() as i32;
+                let y = ((v as f64 + 0.5 - dy) / scale).floor() as i32;
+                if x < 0 || y < 0 || x >= image.width || y >= image.height {
+                    continue;
+                }
+                let rgb = pixels.rgb(x, y);
+                let start = (v as usize * width as usize + u as usize) * 3;
+                resampled.data[start..start + 3].copy_from_slice(&[rgb[2], rgb[1], rgb[0]]);
+            }
+        }
+        resampled
+    }
+
+    fn assert_near(actual: (f64, f64), expected: (f64, f64), tolerance: f64) {
+        assert!(
+            (actual.0 - expected.0).abs() <= tolerance
+                && (actual.1 - expected.1).abs() <= tolerance,
+            "{:?} is not within {} of {:?}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn locates_patterns_where_they_were_etched() {
+        let found = locate(&etched());
+
+        for (found, expected) in found.iter().zip(expected_centers(WIDTH, HEIGHT)) {
+            assert_near(found.expect("pattern not found"), expected, 0.5);
+        }
+        let transform = transform(&found, WIDTH, HEIGHT, None).unwrap();
+        assert!(transform.is_identity(WIDTH, HEIGHT, 0.5));
+    }
+
+    #[test]
+    fn locates_patterns_in_a_shifted_and_scaled_frame() {
+        let (scale, dx, dy) = (1.5, 24.0, 10.0);
+        let image = resample(&etched(), scale, dx, dy, 1000, 560);
+        let found = locate(&image);
+
+        for (found, (x, y)) in found.iter().zip(expected_centers(WIDTH, HEIGHT)) {
+            let expected = (x * scale + dx, y * scale + dy);
+            assert_near(found.expect("pattern not found"), expected, 1.0);
+        }
+
+        // Every position of the frame as etched maps onto the resampled frame
+        let transform = transform(&found, WIDTH, HEIGHT, None).unwrap();
+        assert!(!transform.is_identity(WIDTH, HEIGHT, 1.0));
+        for (x, y) in [
+            (0.0, 0.0),
+            (640.0, 0.0),
+            (320.0, 180.0),
+            (0.0, 360.0),
+            (640.0, 360.0),
+        ] {
+            assert_near(transform.apply(x, y), (x * scale + dx, y * scale + dy), 1.5);
+        }
+    }
+
+    #[test]
+    fn aligns_on_three_patterns_when_one_is_covered() {
+        let (scale, dx, dy) = (0.75, -6.0, 4.0);
+        let mut image = resample(&etched(), scale, dx, dy, 480, 280);
+        // Paint over the bottom-right pattern
+        fill(&mut image, 400..480, 200..280, 0);
+        let found = locate(&image);
+        assert!(found[3].is_none());
+
+        let transform = transform(&found, WIDTH, HEIGHT, None).unwrap();
+        for (x, y) in [(0.0, 0.0), (640.0, 360.0)] {
+            assert_near(transform.apply(x, y), (x * scale + dx, y * scale + dy), 1.5);
+        }
+    }
+
+    #[test]
+    fn rejects_patterns_away_from_the_previous_alignment() {
+        let mut found = locate(&etched());
+        let previous = Transform::identity();
+        found[1] = found[1].map(|(x, y)| (x - 40.0, y + 30.0));
+
+        // The misplaced pattern is left out and the other three still agree
+        let aligned = transform(&found, WIDTH, HEIGHT, Some(&previous)).unwrap();
+        assert!(aligned.is_identity(WIDTH, HEIGHT, 0.5));
+
+        found[2] = None;
+        assert!(transform(&found, WIDTH, HEIGHT, Some(&previous)).is_none());
+    }
+}
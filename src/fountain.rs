@@ -0,0 +1,404 @@
+use std::collections::btree_map::{BTreeMap, Entry};
+use std::io::Write;
+
+use anyhow::bail;
+use raptorq::{
+    extended_source_block_symbols, EncodingPacket, ObjectTransmissionInformation, PayloadId,
+    SourceBlockDecoder, SourceBlockEncoder,
+};
+
+use crate::spool::{self, Spool};
+
+/// Default repair overhead, in percent of the source symbols of every block.
+/// 10% lets a video lose roughly one frame in eleven and still be restored exactly.
+pub const DEFAULT_OVERHEAD: u8 = 10;
+
+/// Largest symbol RaptorQ supports, rounded down to the symbol alignment.
+const MAX_SYMBOL_SIZE: usize = 0xFFF8;
+
+/// Symbols are kept a multiple of this many bytes, as RFC 6330 recommends.
+const SYMBOL_ALIGNMENT: u8 = 8;
+
+/// Source symbols per block the encoder aims for. Larger files are split into more
+/// blocks, which keeps decoding time in check.
+const TARGET_BLOCK_SYMBOLS: u64 = 8192;
+
+/// Upper bound on the bytes of one block. Blocks are encoded one at a time, so this
+/// bounds the memory the encoder needs whatever the size of the data.
+const MAX_BLOCK_LEN: u64 = 16 * 1024 * 1024;
+
+/// Upper bound on the source symbols of one block (K'max in RFC 6330).
+const MAX_BLOCK_SYMBOLS: u16 = 56403;
+
+/// Every packet ends with the CRC32 of its symbol, so damaged packets aren't fed to the decoder.
+const CRC_LEN: usize = 4;
+
+/// RaptorQ parameters recorded in the header so the decoder can rebuild the packet layout.
+///
+/// The payload is split into symbols of `symbol_size` bytes, grouped into blocks of
+/// `block_symbols` symbols (the last block holds whatever is left), and each block gets
+/// `overhead` percent of repair symbols on top of its source symbols. Blocks are laid
+/// out one after the other, every frame carrying `packets_per_frame` packets, so the
+/// position of a packet in the video tells which symbol it holds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fountain {
+    /// Length of the data before it was split into symbols.
+    pub data_len: u64,
+    /// Bytes per symbol, a multiple of 8.
+    pub symbol_size: u16,
+    /// Source symbols in every block but the last.
+    pub block_symbols: u16,
+    /// Repair symbols per block, in percent of its source symbols.
+    pub overhead: u8,
+    /// Packets carried by every data frame.
+    pub packets_per_frame: u16,
+}
+
+impl Fountain {
+    /// RaptorQ transmission parameters for a block of `source` symbols. Every block is
+    /// coded as an object of its own.
+    fn config(&self, source: u32) -> ObjectTransmissionInformation {
+        ObjectTransmissionInformation::new(
+            source as u64 * self.symbol_size as u64,
+            self.symbol_size,
+            1,
+            1,
+            SYMBOL_ALIGNMENT,
+        )
+    }
+
+    /// Number of source symbols in each block.
+    fn blocks(&self) -> impl Iterator<Item = u32> {
+        let total = self.data_len.div_ceil(self.symbol_size as u64).max(1);
+        let full = self.block_symbols as u64;
+        (0..total.div_ceil(full)).map(move |block| (total - block * full).min(full) as u32)
+    }
+
+    /// Number of repair symbols generated for a block of `source` symbols.
+    fn repair_symbols(&self, source: u32) -> u32 {
+        (source * self.overhead as u32).div_ceil(100)
+    }
+
+    /// Length of a packet: the symbol and its CRC32.
+    fn packet_len(&self) -> usize {
+        self.symbol_size as usize + CRC_LEN
+    }
+
+    /// Checks the parameters read from a header, so a corrupted header can't make the
+    /// decoder panic.
+    pub fn is_valid(&self) -> bool {
+        self.symbol_size > 0
+            && self.symbol_size.is_multiple_of(SYMBOL_ALIGNMENT as u16)
+            && (1..=MAX_BLOCK_SYMBOLS).contains(&self.block_symbols)
+            && self.packets_per_frame > 0
+    }
+}
+
+/// Fountain-codes `data` across frames with RaptorQ.
+///
+/// The data is split into symbols sized so a whole number of packets fills a frame,
+/// and repair symbols are added so any large enough subset of a block's frames restores
+/// it. Blocks are read and encoded one at a time.
+///
+/// # Arguments
+/// * `data` - The data to encode.
+/// * `frame_bytes` - Bytes carried by each data frame.
+/// * `overhead` - Repair symbols to add, in percent of the source symbols.
+///
+/// # Returns
+/// The packets laid out frame by frame, with every frame but the last padded to
+/// `frame_bytes`, and the parameters to record in the header.
+pub fn encode(
+    data: &mut Spool,
+    frame_bytes: usize,
+    overhead: u8,
+) -> anyhow::Result<(Spool, Fountain)> {
+    // Fill each frame with as few packets as possible, as larger symbols decode faster
+    let packets_per_frame = frame_bytes.div_ceil(MAX_SYMBOL_SIZE + CRC_LEN).max(1);
+    let symbol_size = (frame_bytes / packets_per_frame).saturating_sub(CRC_LEN)
+        / SYMBOL_ALIGNMENT as usize
+        * SYMBOL_ALIGNMENT as usize;
+    if symbol_size == 0 {
+        bail!(
+            "Frames hold only {} bytes of data, too few for fountain coding; use a larger resolution, smaller blocks or --overhead 0",
+            frame_bytes
+        );
+    }
+    if packets_per_frame > u16::MAX as usize {
+        bail!("Frames are too large for fountain coding");
+    }
+
+    let total_symbols = data.size().div_ceil(symbol_size as u64).max(1);
+    let block_symbols = TARGET_BLOCK_SYMBOLS
+        .min((MAX_BLOCK_LEN / symbol_size as u64).max(1))
+        .min(total_symbols);
+
+    let fountain = Fountain {
+        data_len: data.size(),
+        symbol_size: symbol_size as u16,
+        block_symbols: block_symbols as u16,
+        overhead,
+        packets_per_frame: packets_per_frame as u16,
+    };
+    let total_packets: u64 = fountain
+        .blocks()
+        .map(|source| (source + fountain.repair_symbols(source)) as u64)
+        .sum();
+
+    let mut input = data.reader()?;
+    let mut encoded = Spool::new()?;
+    let padding = vec![0u8; frame_bytes - packets_per_frame * fountain.packet_len()];
+    let mut written = 0u64;
+    let mut block_count = 0;
+    for source in fountain.blocks() {
+        // Generate the block's source and repair symbols
+        let block_len = source as usize * symbol_size;
+        let mut block_data = spool::read_chunk(&mut input, block_len)?;
+        block_data.resize(block_len, 0);
+
+        let encoder = SourceBlockEncoder::new2(0, &fountain.config(source), &block_data);
+        let mut packets = encoder.source_packets();
+        packets.extend(encoder.repair_packets(0, fountain.repair_symbols(source)));
+
+        // Write them out, padding every frame but the last
+        for packet in packets {
+            encoded.write_all(packet.data())?;
+            encoded.write_all(&crc32fast::hash(packet.data()).to_be_bytes())?;
+            written += 1;
+            if written.is_multiple_of(packets_per_frame as u64) && written < total_packets {
+                encoded.write_all(&padding)?;
+            }
+        }
+        block_count += 1;
+    }
+
+    println!(
+        "Fountain code applied: {} bytes -> {} packets of {} bytes ({} source blocks, {}% overhead)",
+        data.size(),
+        total_packets,
+        fountain.packet_len(),
+        block_count,
+        overhead
+    );
+
+    Ok((encoded, fountain))
+}
+
+/// Reverses `encode` from whatever frames were read, one block at a time.
+///
+/// Blocks with enough intact packets are decoded exactly. The others fall back to their
+/// source symbols as read, damaged or zeroed where missing, for the integrity check
+/// to catch. Only the frames of the block being decoded are held in memory.
+///
+/// # Arguments
+/// * `fountain` - The parameters recorded in the header.
+/// * `frame` - Fetches the data of a frame by index, or `None` if it is missing.
+/// * `out` - Where the decoded data is written.
+pub fn decode(
+    fountain: &Fountain,
+    mut frame: impl FnMut(usize) -> anyhow::Result<Option<Vec<u8>>>,
+    out: &mut impl Write,
+) -> anyhow::Result<()> {
+    let symbol_size = fountain.symbol_size as usize;
+    let packet_len = fountain.packet_len();
+    let packets_per_frame = fountain.packets_per_frame as u64;
+
+    // Frames of the current block, by index. A frame shared by two blocks is kept for the next one
+    let mut frames: BTreeMap<usize, Option<Vec<u8>>> = BTreeMap::new();
+    let mut remaining = fountain.data_len;
+    let mut position = 0u64;
+    let mut blocks = 0;
+    let mut unrecovered = 0;
+    let mut missing = 0;
+    for source in fountain.blocks() {
+        blocks += 1;
+
+        // Sort the block's packets, keeping damaged source symbols aside
+        let mut intact = Vec::new();
+        let mut damaged: Vec<Option<Vec<u8>>> = vec![None; source as usize];
+        for symbol in 0..source + fountain.repair_symbols(source) {
+            let index = (position / packets_per_frame) as usize;
+            let start = (position % packets_per_frame) as usize * packet_len;
+            position += 1;
+            let frame_data = match frames.entry(index) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(frame(index)?),
+            };
+            let Some(frame_data) = frame_data else {
+                continue;
+            };
+            let Some(packet) = frame_data.get(start..start + packet_len) else {
+                continue;
+            };
+
+            let (data, crc) = packet.split_at(symbol_size);
+            if crc32fast::hash(data).to_be_bytes() == crc {
+                // Repair symbol ids start after the padding symbols of the extended block
+                let id = if symbol < source {
+                    symbol
+                } else {
+                    extended_source_block_symbols(source) + symbol - source
+                };
+                intact.push(EncodingPacket::new(PayloadId::new(0, id), data.to_vec()));
+            } else if symbol < source {
+                damaged[symbol as usize] = Some(data.to_vec());
+            }
+        }
+        let next_frame = (position / packets_per_frame) as usize;
+        frames.retain(|&index, _| index >= next_frame);
+
+        let restored = if intact.len() >= source as usize {
+            let block_len = source as u64 * symbol_size as u64;
+            SourceBlockDecoder::new2(0, &fountain.config(source), block_len).decode(intact.clone())
+        } else {
+            None
+        };
+
+        let decoded = match restored {
+            Some(data) => data,
+            None => {
+                // Not enough intact packets: piece the source symbols together as best we can
+                unrecovered += 1;
+                for packet in intact {
+                    let symbol = packet.payload_id().encoding_symbol_id() as usize;
+                    if symbol < source as usize {
+                        damaged[symbol] = Some(packet.data().to_vec());
+                    }
+                }
+                let mut decoded = Vec::with_capacity(source as usize * symbol_size);
+                for symbol in damaged {
+                    match symbol {
+                        Some(data) => decoded.extend(data),
+                        None => {
+                            missing += 1;
+                            decoded.resize(decoded.len() + symbol_size, 0);
+                        }
+                    }
+                }
+                decoded
+            }
+        };
+
+        let len = remaining.min(decoded.len() as u64);
+        out.write_all(&decoded[..len as usize])?;
+        remaining -= len;
+    }
+
+    if unrecovered > 0 {
+        println!(
+            "Fountain code could not restore {} of {} block(s), {} symbol(s) missing",
+            unrecovered, blocks, missing
+        );
+    } else {
+        println!("Fountain code restored all {} block(s)", blocks);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::test_support::sample_bytes;
+
+    /// Encodes `data` and splits the result into frames.
+    fn encode_frames(data: &[u8], frame_bytes: usize, overhead: u8) -> (Vec<Vec<u8>>, Fountain) {
+        let mut spool = Spool::new().unwrap();
+        spool.write_all(data).unwrap();
+        let (mut encoded, fountain) = encode(&mut spool, frame_bytes, overhead).unwrap();
+
+        let mut bytes = Vec::new();
+        encoded.reader().unwrap().read_to_end(&mut bytes).unwrap();
+        let frames = bytes.chunks(frame_bytes).map(<[u8]>::to_vec).collect();
+        (frames, fountain)
+    }
+
+    /// Decodes from the frames that are `Some`.
+    fn decode_frames(frames: &[Option<Vec<u8>>], fountain: &Fountain) -> Vec<u8> {
+        let mut out = Vec::new();
+        decode(
+            fountain,
+            |index| Ok(frames.get(index).cloned().flatten()),
+            &mut out,
+        )
+        .unwrap();
+        out
+    }
+
+    #[test]
+    fn decodes_every_frame() {
+        let data = sample_bytes(50_000);
+        let (frames, fountain) = encode_frames(&data, 1000, 20);
+        assert!(fountain.is_valid());
+
+        let frames: Vec<_> = frames.into_iter().map(Some).collect();
+        assert_eq!(decode_frames(&frames, &fountain), data);
+    }
+
+    #[test]
+    fn decodes_after_dropping_frames() {
+        let data = sample_bytes(50_000);
+        let (frames, fountain) = encode_frames(&data, 1000, 20);
+
+        // Lose one frame in six, source and repair frames alike
+        let frames: Vec<_> = frames
+            .into_iter()
+            .enumerate()
+            .map(|(index, frame)| (index % 6 != 2).then_some(frame))
+            .collect();
+        assert_eq!(decode_frames(&frames, &fountain), data);
+    }
+
+    #[test]
+    fn decodes_after_dropping_frames_of_several_packets() {
+        // Frames too large for a single symbol carry several packets each
+        let data = sample_bytes(1_000_000);
+        let (frames, fountain) = encode_frames(&data, 200_000, 40);
+        assert!(fountain.packets_per_frame > 1);
+
+        let mut frames: Vec<_> = frames.into_iter().map(Some).collect();
+        frames[1] = None;
+        assert_eq!(decode_frames(&frames, &fountain), data);
+    }
+
+    #[test]
+    fn damaged_packets_are_treated_as_missing() {
+        let data = sample_bytes(50_000);
+        let (mut frames, fountain) = encode_frames(&data, 1000, 20);
+        frames[0][10] ^= 0xFF;
+        frames[30][500] ^= 0x01;
+
+        let frames: Vec<_> = frames.into_iter().map(Some).collect();
+        assert_eq!(decode_frames(&frames, &fountain), data);
+    }
+
+    #[test]
+    fn too_few_frames_leave_the_gaps_zeroed() {
+        let data = sample_bytes(50_000);
+        let (frames, fountain) = encode_frames(&data, 1000, 10);
+
+        // Dropping half the frames is far beyond the overhead
+        let frames: Vec<_> = frames
+            .into_iter()
+            .enumerate()
+            .map(|(index, frame)| (index % 2 == 0).then_some(frame))
+            .collect();
+        let out = decode_frames(&frames, &fountain);
+
+        let symbol_size = fountain.symbol_size as usize;
+        assert_eq!(out.len(), data.len());
+        assert_eq!(out[..symbol_size], data[..symbol_size]);
+        assert!(out[symbol_size..2 * symbol_size]
+            .iter()
+            .all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn frames_too_small_for_a_symbol_are_refused() {
+        let mut spool = Spool::new().unwrap();
+        spool.write_all(b"data").unwrap();
+        assert!(encode(&mut spool, 8, 10).is_err());
+    }
+}
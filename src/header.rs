@@ -0,0 +1,824 @@
+use std::fmt;
+
+use anyhow::bail;
+use ed25519_dalek::SigningKey;
+
+use crate::compression::{Algorithm, Compression};
+use crate::crypto::{self, Cipher, Encryption, Kdf, KeySource, Stanza};
+use crate::ecc::Ecc;
+use crate::fountain::Fountain;
+use crate::metadata::FileMeta;
+use crate::settings::OutputMode;
+use crate::signing::{self, Signature};
+
+/// Magic signature written at the very start of every instruction frame.
+/// Videos whose first bytes don't match this are rejected as "not encoded by us".
+pub const MAGIC: [u8; 4] = *b"ISRS";
+
+/// Version of the container format written by the encoder.
+/// Decoders refuse headers with a version they don't understand.
+pub const FORMAT_VERSION: u16 = 1;
+
+/// Number of bytes needed to learn the total header length:
+/// magic (4) + version (2) + header length (4).
+pub const PREFIX_LEN: usize = 10;
+
+/// Upper bound on the header length, so a corrupted length field can't make
+/// the decoder swallow the whole video as instruction frames.
+const MAX_HEADER_LEN: u32 = 1 << 20;
+
+/// Extension tags with this bit set are critical: a decoder that doesn't understand
+/// one must refuse the video instead of silently ignoring it.
+pub const CRITICAL_TAG: u16 = 0x8000;
+
+/// Extension carrying the Reed-Solomon parameters. Critical, as the payload
+/// can't be read back without undoing the error correction.
+pub const TAG_ECC: u16 = CRITICAL_TAG | 0x0001;
+
+/// Extension carrying the SHA-256 of the original bytes, checked after dislodging.
+pub const TAG_SHA256: u16 = 0x0002;
+
+/// Extension carrying the original file name, size, modification time and permissions.
+pub const TAG_METADATA: u16 = 0x0003;
+
+/// Extension marking the payload as an archive of several entries. Critical, as an
+/// older decoder would otherwise write the raw archive out as a single file.
+pub const TAG_ARCHIVE: u16 = CRITICAL_TAG | 0x0004;
+
+/// Extension carrying the compression algorithm, level and original length. Critical,
+/// as the payload can't be used without decompressing it.
+pub const TAG_COMPRESSION: u16 = CRITICAL_TAG | 0x0005;
+
+/// Extension carrying the cipher, nonce and key source: Argon2id parameters and salt
+/// for passphrases, or the wrapped file keys for recipients. Critical, as the payload
+/// is unreadable without decrypting it.
+pub const TAG_ENCRYPTION: u16 = CRITICAL_TAG | 0x0006;
+
+/// Extension carrying the Ed25519 signature over the header and payload hash.
+/// Always the last extension, as it signs every header byte before it.
+pub const TAG_SIGNATURE: u16 = 0x0007;
+
+/// Extension carrying the palette colors of the palette modes, so decoders know what
+/// colors to expect. Critical, as palette frames can't be decoded without it.
+pub const TAG_PALETTE: u16 = CRITICAL_TAG | 0x0008;
+
+/// Extension carrying the number of calibration frames between the instruction frames
+/// and the data frames. Critical, as an older decoder would read them as data.
+pub const TAG_CALIBRATION: u16 = CRITICAL_TAG | 0x0009;
+
+/// Extension carrying the width and height of the frames as etched, so videos rescaled
+/// after embedding can be sampled on their original block grid.
+pub const TAG_FRAME_SIZE: u16 = 0x000A;
+
+/// Extension carrying the fountain code layout of the data frames. Critical, as the
+/// frames hold coded packets rather than the payload itself.
+pub const TAG_FOUNTAIN: u16 = CRITICAL_TAG | 0x000B;
+
+/// A tagged extension record carried in the header.
+/// Unknown non-critical extensions are preserved but otherwise ignored.
+#[derive(Clone, Debug)]
+pub struct Extension {
+    /// Identifies the extension. See `CRITICAL_TAG`.
+    pub tag: u16,
+    /// Raw extension payload.
+    pub data: Vec<u8>,
+}
+
+/// Describes the transformations applied to the payload before it was etched,
+/// so the decoder can reverse them. Each field is stored as a header extension.
+#[derive(Clone, Debug, Default)]
+pub struct PayloadInfo {
+    /// Reed-Solomon error correction, if enabled.
+    pub ecc: Option<Ecc>,
+    /// Fountain code spread across the frames before error correction, if enabled.
+    pub fountain: Option<Fountain>,
+    /// Compression applied before error correction, if any.
+    pub compression: Option<Compression>,
+    /// Passphrase or recipient encryption applied after compression, if any.
+    pub encryption: Option<Encryption>,
+    /// SHA-256 of the original bytes, used to verify the restored output is bit-exact.
+    pub sha256: Option<[u8; 32]>,
+    /// Metadata of the original file, used to restore it under its own name.
+    pub metadata: Option<FileMeta>,
+    /// Whether the payload is an archive of files, directories and links to extract.
+    pub archive: bool,
+    /// Signature over the header and payload hash, if the video was signed.
+    pub signature: Option<Signature>,
+}
+
+/// The versioned container header stored in the instruction frame(s).
+///
+/// Layout (all integers big-endian):
+/// ```text
+/// magic        [u8; 4]   "ISRS"
+/// version      u16
+/// header_len   u32       total length in bytes, including the CRC
+/// out_mode     u8        0 = Binary, 1 = Color, 2 = Gray4, 3 = Gray8, 4 = Palette4, 5 = Palette8
+/// reserved     [u8; 3]   must be zero
+/// block_size   u32
+/// final_frame  u32
+/// final_byte   u32
+/// ext_count    u16
+/// extensions   ext_count * (tag u16, len u32, data [u8; len])
+/// crc32        u32       CRC32 over every preceding header byte
+/// ```
+#[derive(Clone, Debug)]
+pub struct Header {
+    /// How the payload is represented in the data frames.
+    pub out_mode: OutputMode,
+    /// Size of the embedding blocks in the data frames, in pixels per side.
+    pub block_size: u32,
+    /// Index of the last data frame (data frames are numbered from 1).
+    pub final_frame: u32,
+    /// Number of units (bits, bytes or symbols, depending on the mode) held by the final frame.
+    pub final_byte: u32,
+    /// RGB colors of the palette modes, indexed by symbol. Empty for the other modes.
+    pub palette: Vec<[u8; 3]>,
+    /// Number of calibration frames following the instruction frames.
+    pub calibration_frames: u16,
+    /// Width and height of the frames as etched, if recorded.
+    pub frame_size: Option<(u32, u32)>,
+    /// Transformations applied to the payload, stored as known extensions.
+    pub payload: PayloadInfo,
+    /// Unknown extension records, reserved for future format additions.
+    pub extensions: Vec<Extension>,
+}
+
+impl Header {
+    /// Creates a header with no extensions.
+    pub fn new(out_mode: OutputMode, block_size: u32, final_frame: u32, final_byte: u32) -> Self {
+        Header {
+            out_mode,
+            block_size,
+            final_frame,
+            final_byte,
+            palette: Vec::new(),
+            calibration_frames: 0,
+            frame_size: None,
+            payload: PayloadInfo::default(),
+            extensions: Vec::new(),
+        }
+    }
+
+    /// Serializes the header, appending its CRC32.
+    ///
+    /// # Returns
+    /// The encoded header bytes, ready to be etched into the instruction frame(s).
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body: Vec<u8> = Vec::new();
+
+        // Fixed fields
+        body.push(match self.out_mode {
+            OutputMode::Binary => 0,
+            OutputMode::Color => 1,
+            OutputMode::Gray4 => 2,
+            OutputMode::Gray8 => 3,
+            OutputMode::Palette4 => 4,
+            OutputMode::Palette8 => 5,
+        });
+        body.extend_from_slice(&[0u8; 3]); // Reserved
+        body.extend_from_slice(&self.block_size.to_be_bytes());
+        body.extend_from_slice(&self.final_frame.to_be_bytes());
+        body.extend_from_slice(&self.final_byte.to_be_bytes());
+
+        // Extension records: known payload fields first, then anything carried over
+        let mut extensions: Vec<Extension> = Vec::new();
+        if !self.palette.is_empty() {
+            let mut data = vec![self.palette.len() as u8];
+            data.extend(self.palette.iter().flatten());
+            extensions.push(Extension {
+                tag: TAG_PALETTE,
+                data,
+            });
+        }
+        if let Some((width, height)) = self.frame_size {
+            let mut data = width.to_be_bytes().to_vec();
+            data.extend_from_slice(&height.to_be_bytes());
+            extensions.push(Extension {
+                tag: TAG_FRAME_SIZE,
+                data,
+            });
+        }
+        if self.calibration_frames > 0 {
+            extensions.push(Extension {
+                tag: TAG_CALIBRATION,
+                data: self.calibration_frames.to_be_bytes().to_vec(),
+            });
+        }
+        if let Some(ecc) = &self.payload.ecc {
+            let mut data = vec![ecc.parity];
+            data.extend_from_slice(&ecc.interleave.to_be_bytes());
+            data.extend_from_slice(&ecc.data_len.to_be_bytes());
+            extensions.push(Extension { tag: TAG_ECC, data });
+        }
+        if let Some(fountain) = &self.payload.fountain {
+            let mut data = fountain.data_len.to_be_bytes().to_vec();
+            data.extend_from_slice(&fountain.symbol_size.to_be_bytes());
+            data.extend_from_slice(&fountain.block_symbols.to_be_bytes());
+            data.push(fountain.overhead);
+            data.extend_from_slice(&fountain.packets_per_frame.to_be_bytes());
+            extensions.push(Extension {
+                tag: TAG_FOUNTAIN,
+                data,
+            });
+        }
+        if let Some(compression) = &self.payload.compression {
+            let mut data = vec![match compression.algorithm {
+                Algorithm::None => 0,
+                Algorithm::Zstd => 1,
+                Algorithm::Xz => 2,
+            }];
+            data.extend_from_slice(&compression.level.to_be_bytes());
+            data.extend_from_slice(&compression.original_len.to_be_bytes());
+            extensions.push(Extension {
+                tag: TAG_COMPRESSION,
+                data,
+            });
+        }
+        if let Some(encryption) = &self.payload.encryption {
+            let mut data = vec![match encryption.cipher {
+                Cipher::ChaCha20Poly1305 => 0,
+                Cipher::Aes256Gcm => 1,
+            }];
+            data.extend_from_slice(&encryption.nonce);
+            match &encryption.key_source {
+                KeySource::Passphrase(kdf) => {
+                    data.push(0);
+                    data.extend_from_slice(&kdf.memory_kib.to_be_bytes());
+                    data.extend_from_slice(&kdf.iterations.to_be_bytes());
+                    data.extend_from_slice(&kdf.parallelism.to_be_bytes());
+                    data.extend_from_slice(&kdf.salt);
+                }
+                KeySource::Recipients(stanzas) => {
+                    data.push(1);
+                    data.extend_from_slice(&(stanzas.len() as u16).to_be_bytes());
+                    for stanza in stanzas {
+                        data.extend_from_slice(&stanza.ephemeral);
+                        data.extend_from_slice(&stanza.wrapped_key);
+                    }
+                }
+            }
+            data.extend_from_slice(&encryption.segment_len.to_be_bytes());
+            extensions.push(Extension {
+                tag: TAG_ENCRYPTION,
+                data,
+            });
+        }
+        if let Some(sha256) = &self.payload.sha256 {
+            extensions.push(Extension {
+                tag: TAG_SHA256,
+                data: sha256.to_vec(),
+            });
+        }
+        if let Some(metadata) = &self.payload.metadata {
+            extensions.push(Extension {
+                tag: TAG_METADATA,
+                data: metadata.encode(),
+            });
+        }
+        if self.payload.archive {
+            extensions.push(Extension {
+                tag: TAG_ARCHIVE,
+                data: Vec::new(),
+            });
+        }
+        extensions.extend(self.extensions.iter().cloned());
+        if let Some(signature) = &self.payload.signature {
+            let mut data = signature.signer.to_vec();
+            data.extend_from_slice(&signature.payload_sha256);
+            data.extend_from_slice(&signature.signature);
+            extensions.push(Extension {
+                tag: TAG_SIGNATURE,
+                data,
+            });
+        }
+
+        body.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        for extension in &extensions {
+            body.extend_from_slice(&extension.tag.to_be_bytes());
+            body.extend_from_slice(&(extension.data.len() as u32).to_be_bytes());
+            body.extend_from_slice(&extension.data);
+        }
+
+        let header_len = (PREFIX_LEN + body.len() + 4) as u32;
+
+        let mut bytes: Vec<u8> = Vec::with_capacity(header_len as usize);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+        bytes.extend_from_slice(&header_len.to_be_bytes());
+        bytes.extend_from_slice(&body);
+
+        let crc = crc32fast::hash(&bytes);
+        bytes.extend_from_slice(&crc.to_be_bytes());
+
+        bytes
+    }
+
+    /// Serializes the header and signs it with `key`.
+    /// `payload.signature` must have been prepared with `Signature::new`.
+    ///
+    /// # Arguments
+    /// * `key` - The Ed25519 key to sign with.
+    ///
+    /// # Returns
+    /// The encoded header bytes, signature and CRC32 included.
+    pub fn encode_signed(&self, key: &SigningKey) -> anyhow::Result<Vec<u8>> {
+        if self.payload.signature.is_none() {
+            bail!("The header has no signature record to complete");
+        }
+
+        // The signature is the last field before the CRC, and signs everything before it
+        let mut bytes = self.encode();
+        let crc_start = bytes.len() - 4;
+        let signature_start = crc_start - signing::SIGNATURE_LEN;
+        let signature = signing::sign(&bytes[..signature_start], key);
+        bytes[signature_start..crc_start].copy_from_slice(&signature);
+
+        let crc = crc32fast::hash(&bytes[..crc_start]);
+        bytes[crc_start..].copy_from_slice(&crc.to_be_bytes());
+
+        Ok(bytes)
+    }
+
+    /// Validates the fixed prefix of a header and returns its total length.
+    /// This is used to find out how many instruction bytes must be read before decoding.
+    ///
+    /// # Arguments
+    /// * `bytes` - At least the first `PREFIX_LEN` bytes of the instruction data.
+    ///
+    /// # Returns
+    /// The total header length in bytes, or an error if the video wasn't produced by this tool.
+    pub fn peek_len(bytes: &[u8]) -> anyhow::Result<usize> {
+        if bytes.len() < PREFIX_LEN {
+            bail!("Instruction frame is too small to hold a header");
+        }
+
+        if bytes[0..4] != MAGIC {
+            bail!("Not an encoded video: magic signature missing from the instruction frame");
+        }
+
+        let version = u16::from_be_bytes([bytes[4], bytes[5]]);
+        if version != FORMAT_VERSION {
+            bail!(
+                "Unsupported format version {} (this build understands {})",
+                version,
+                FORMAT_VERSION
+            );
+        }
+
+        let header_len = u32::from_be_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]);
+        if header_len < PREFIX_LEN as u32 + 4 || header_len > MAX_HEADER_LEN {
+            bail!("Corrupted header: implausible header length {}", header_len);
+        }
+
+        Ok(header_len as usize)
+    }
+
+    /// Parses and verifies a header.
+    ///
+    /// # Arguments
+    /// * `bytes` - The instruction data, starting at the magic signature. Trailing bytes are ignored.
+    ///
+    /// # Returns
+    /// The decoded `Header`, or an error if the signature, version or checksum don't match.
+    pub fn decode(bytes: &[u8]) -> anyhow::Result<Header> {
+        let header_len = Header::peek_len(bytes)?;
+        if bytes.len() < header_len {
+            bail!(
+                "Header is truncated: expected {} bytes, got {}",
+                header_len,
+                bytes.len()
+            );
+        }
+
+        // Verify the checksum before trusting any field
+        let (content, crc) = bytes[..header_len].split_at(header_len - 4);
+        let expected = u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]);
+        if crc32fast::hash(content) != expected {
+            bail!("Corrupted header: CRC32 mismatch");
+        }
+
+        let mut reader = ByteReader::new(&content[PREFIX_LEN..]);
+
+        let out_mode = match reader.u8()? {
+            0 => OutputMode::Binary,
+            1 => OutputMode::Color,
+            2 => OutputMode::Gray4,
+            3 => OutputMode::Gray8,
+            4 => OutputMode::Palette4,
+            5 => OutputMode::Palette8,
+            other => bail!("Unknown output mode {} in header", other),
+        };
+        reader.take(3)?; // Reserved
+        let block_size = reader.u32()?;
+        let final_frame = reader.u32()?;
+        let final_byte = reader.u32()?;
+
+        let ext_count = reader.u16()?;
+        let mut palette = Vec::new();
+        let mut calibration_frames = 0;
+        let mut frame_size = None;
+        let mut payload = PayloadInfo::default();
+        let mut extensions = Vec::new();
+        for index in 0..ext_count {
+            let tag = reader.u16()?;
+            let len = reader.u32()? as usize;
+            let data = reader.take(len)?;
+            let mut fields = ByteReader::new(data);
+
+            match tag {
+                TAG_PALETTE => {
+                    let count = fields.u8()?;
+                    for _ in 0..count {
+                        palette.push(fields.take(3)?.try_into()?);
+                    }
+                }
+                TAG_CALIBRATION => calibration_frames = fields.u16()?,
+                TAG_FRAME_SIZE => frame_size = Some((fields.u32()?, fields.u32()?)),
+                TAG_ECC => {
+                    let ecc = Ecc {
+                        parity: fields.u8()?,
+                        interleave: fields.u16()?,
+                        data_len: fields.u64()?,
+                    };
+                    if ecc.parity as usize >= crate::ecc::CODEWORD_LEN || ecc.interleave == 0 {
+                        bail!("Corrupted header: invalid Reed-Solomon parameters");
+                    }
+                    payload.ecc = Some(ecc);
+                }
+                TAG_FOUNTAIN => {
+                    let fountain = Fountain {
+                        data_len: fields.u64()?,
+                        symbol_size: fields.u16()?,
+                        block_symbols: fields.u16()?,
+                        overhead: fields.u8()?,
+                        packets_per_frame: fields.u16()?,
+                    };
+                    if !fountain.is_valid() {
+                        bail!("Corrupted header: invalid fountain code parameters");
+                    }
+                    payload.fountain = Some(fountain);
+                }
+                TAG_COMPRESSION => {
+                    let algorithm = match fields.u8()? {
+                        0 => Algorithm::None,
+                        1 => Algorithm::Zstd,
+                        2 => Algorithm::Xz,
+                        other => bail!("Unknown compression algorithm {} in header", other),
+                    };
+                    payload.compression = Some(Compression {
+                        algorithm,
+                        level: fields.u32()? as i32,
+                        original_len: fields.u64()?,
+                    });
+                }
+                TAG_ENCRYPTION => {
+                    let cipher = match fields.u8()? {
+                        0 => Cipher::ChaCha20Poly1305,
+                        1 => Cipher::Aes256Gcm,
+                        other => bail!("Unknown cipher {} in header", other),
+                    };
+                    let nonce = fields.take(crypto::NONCE_LEN)?.try_into()?;
+                    let key_source = match fields.u8()? {
+                        0 => {
+                            let kdf = Kdf {
+                                memory_kib: fields.u32()?,
+                                iterations: fields.u32()?,
+                                parallelism: fields.u32()?,
+                                salt: fields.take(crypto::SALT_LEN)?.try_into()?,
+                            };
+                            if kdf.memory_kib > crypto::MAX_MEMORY_KIB
+                                || kdf.iterations > crypto::MAX_ITERATIONS
+                            {
+                                bail!("Corrupted header: implausible key derivation parameters");
+                            }
+                            KeySource::Passphrase(kdf)
+                        }
+                        1 => {
+                            let count = fields.u16()?;
+                            let mut stanzas = Vec::new();
+                            for _ in 0..count {
+                                stanzas.push(Stanza {
+                                    ephemeral: fields.take(32)?.try_into()?,
+                                    wrapped_key: fields
+                                        .take(crypto::WRAPPED_KEY_LEN)?
+                                        .try_into()?,
+                                });
+                            }
+                            KeySource::Recipients(stanzas)
+                        }
+                        other => bail!("Unknown key source {} in header", other),
+                    };
+                    let segment_len = fields.u32()?;
+                    if segment_len == 0 {
+                        bail!("Corrupted header: encryption segment length is zero");
+                    }
+                    payload.encryption = Some(Encryption {
+                        cipher,
+                        key_source,
+                        nonce,
+                        segment_len,
+                    });
+                }
+                TAG_SHA256 => {
+                    payload.sha256 = Some(fields.take(32)?.try_into()?);
+                }
+                TAG_METADATA => {
+                    payload.metadata = Some(FileMeta::decode(&mut fields)?);
+                }
+                TAG_ARCHIVE => payload.archive = true,
+                TAG_SIGNATURE => {
+                    if index + 1 != ext_count {
+                        bail!("Corrupted header: the signature must be the last extension");
+                    }
+                    let signer = fields.take(32)?.try_into()?;
+                    let payload_sha256 = fields.take(32)?.try_into()?;
+                    // Everything up to the signature bytes is what was signed
+                    let signature_start = PREFIX_LEN + reader.position() - len + 64;
+                    payload.signature = Some(Signature {
+                        signer,
+                        payload_sha256,
+                        signature: fields.take(signing::SIGNATURE_LEN)?.try_into()?,
+                        signed_header: content[..signature_start].to_vec(),
+                    });
+                }
+                // Refuse critical extensions this build doesn't know about
+                _ if tag & CRITICAL_TAG != 0 => {
+                    bail!("Video requires unsupported header extension {:#06x}", tag);
+                }
+                _ => extensions.push(Extension {
+                    tag,
+                    data: data.to_vec(),
+                }),
+            }
+        }
+
+        if block_size == 0 {
+            bail!("Corrupted header: block size is zero");
+        }
+        if let Some((width, height)) = frame_size {
+            if width < block_size || height < block_size || width > 1 << 15 || height > 1 << 15 {
+                bail!(
+                    "Corrupted header: implausible frame size {}x{}",
+                    width,
+                    height
+                );
+            }
+        }
+
+        // Palette modes need exactly one color per symbol
+        let colors = match out_mode {
+            OutputMode::Palette4 | OutputMode::Palette8 => 1 << out_mode.bits_per_block(),
+            _ => 0,
+        };
+        if palette.len() != colors {
+            bail!(
+                "Corrupted header: {:?} needs {} palette colors, found {}",
+                out_mode,
+                colors,
+                palette.len()
+            );
+        }
+
+        Ok(Header {
+            out_mode,
+            block_size,
+            final_frame,
+            final_byte,
+            palette,
+            calibration_frames,
+            frame_size,
+            payload,
+            extensions,
+        })
+    }
+}
+
+/// Error reported by `ByteReader` when the bytes end before the field being read.
+#[derive(Debug)]
+pub struct UnexpectedEnd;
+
+impl fmt::Display for UnexpectedEnd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unexpected end of header data")
+    }
+}
+
+impl std::error::Error for UnexpectedEnd {}
+
+/// A minimal cursor over a byte slice that reports truncation as an `UnexpectedEnd` error.
+pub struct ByteReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    /// Creates a reader positioned at the start of `bytes`.
+    pub fn new(bytes: &'a [u8]) -> Self {
+        ByteReader { bytes, position: 0 }
+    }
+
+    /// Number of bytes consumed so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Takes the next `len` bytes.
+    pub fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(UnexpectedEnd)?;
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    /// Reads a single byte.
+    pub fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Reads a big-endian `u16`.
+    pub fn u16(&mut self) -> anyhow::Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    /// Reads a big-endian `u32`.
+    pub fn u32(&mut self) -> anyhow::Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// Reads a big-endian `u64`.
+    pub fn u64(&mut self) -> anyhow::Result<u64> {
+        let b = self.take(8)?;
+        Ok(u64::from_be_bytes(b.try_into()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A header with every known extension and an unknown optional one.
+    fn sample() -> Header {
+        let mut header = Header::new(OutputMode::Palette4, 4, 17, 1234);
+        header.palette = vec![[0, 0, 0], [255, 0, 0], [0, 255, 0], [0, 0, 255]];
+        header.calibration_frames = 2;
+        header.frame_size = Some((1280, 720));
+        header.payload = PayloadInfo {
+            ecc: Some(Ecc {
+                parity: 32,
+                interleave: 64,
+                data_len: 100_000,
+            }),
+            fountain: Some(Fountain {
+                data_len: 110_000,
+                symbol_size: 1024,
+                block_symbols: 500,
+                overhead: 10,
+                packets_per_frame: 3,
+            }),
+            compression: Some(Compression {
+                algorithm: Algorithm::Zstd,
+                level: 19,
+                original_len: 250_000,
+            }),
+            encryption: Some(Encryption {
+                cipher: Cipher::Aes256Gcm,
+                key_source: KeySource::Passphrase(Kdf {
+                    memory_kib: 19 * 1024,
+                    iterations: 2,
+                    parallelism: 1,
+                    salt: [9; crypto::SALT_LEN],
+                }),
+                nonce: [3; crypto::NONCE_LEN],
+                segment_len: crypto::SEGMENT_LEN,
+            }),
+            sha256: Some([0xAB; 32]),
+            metadata: Some(FileMeta {
+                name: "notes.txt".to_string(),
+                size: 250_000,
+                mtime: 1_700_000_000,
+                mtime_nanos: 42,
+                mode: 0o644,
+            }),
+            archive: true,
+            signature: None,
+        };
+        header.extensions = vec![Extension {
+            tag: 0x0100,
+            data: vec![1, 2, 3],
+        }];
+        header
+    }
+
+    #[test]
+    fn round_trip_keeps_every_field() {
+        let header = sample();
+        let bytes = header.encode();
+        assert_eq!(Header::peek_len(&bytes).unwrap(), bytes.len());
+
+        let decoded = Header::decode(&bytes).unwrap();
+        assert_eq!(decoded.out_mode, header.out_mode);
+        assert_eq!(decoded.block_size, header.block_size);
+        assert_eq!(decoded.final_frame, header.final_frame);
+        assert_eq!(decoded.final_byte, header.final_byte);
+        assert_eq!(decoded.palette, header.palette);
+        assert_eq!(decoded.calibration_frames, header.calibration_frames);
+        assert_eq!(decoded.frame_size, header.frame_size);
+        assert_eq!(decoded.payload.ecc, header.payload.ecc);
+        assert_eq!(decoded.payload.fountain, header.payload.fountain);
+        assert_eq!(decoded.payload.compression, header.payload.compression);
+        assert_eq!(decoded.payload.encryption, header.payload.encryption);
+        assert_eq!(decoded.payload.sha256, header.payload.sha256);
+        assert_eq!(decoded.payload.metadata, header.payload.metadata);
+        assert!(decoded.payload.archive);
+        assert!(decoded.payload.signature.is_none());
+    }
+
+    #[test]
+    fn minimal_header_round_trips() {
+        let header = Header::new(OutputMode::Binary, 2, 1, 8);
+        let decoded = Header::decode(&header.encode()).unwrap();
+        assert_eq!(decoded.out_mode, OutputMode::Binary);
+        assert_eq!(decoded.block_size, 2);
+        assert!(decoded.palette.is_empty());
+        assert!(decoded.extensions.is_empty());
+        assert!(decoded.payload.ecc.is_none());
+    }
+
+    #[test]
+    fn trailing_bytes_are_ignored() {
+        let mut bytes = sample().encode();
+        bytes.extend_from_slice(&[0xFF; 100]);
+        assert!(Header::decode(&bytes).is_ok());
+    }
+
+    #[test]
+    fn wrong_magic_is_rejected() {
+        let mut bytes = sample().encode();
+        bytes[0] ^= 0xFF;
+        let error = Header::decode(&bytes).unwrap_err();
+        assert!(error.to_string().contains("magic"));
+    }
+
+    #[test]
+    fn wrong_crc_is_rejected() {
+        let mut bytes = sample().encode();
+        bytes[PREFIX_LEN + 5] ^= 0x01;
+        let error = Header::decode(&bytes).unwrap_err();
+        assert!(error.to_string().contains("CRC32"));
+
+        let mut bytes = sample().encode();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x01;
+        assert!(Header::decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn other_format_versions_are_rejected() {
+        let mut bytes = sample().encode();
+        bytes[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_be_bytes());
+        let error = Header::decode(&bytes).unwrap_err();
+        assert!(error.to_string().contains("format version"));
+    }
+
+    #[test]
+    fn truncated_header_is_rejected() {
+        let bytes = sample().encode();
+        assert!(Header::decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Header::peek_len(&bytes[..PREFIX_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn unknown_optional_extension_is_kept() {
+        let decoded = Header::decode(&sample().encode()).unwrap();
+        assert_eq!(decoded.extensions.len(), 1);
+        assert_eq!(decoded.extensions[0].tag, 0x0100);
+        assert_eq!(decoded.extensions[0].data, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn unknown_critical_extension_is_refused() {
+        let mut header = sample();
+        header.extensions.push(Extension {
+            tag: CRITICAL_TAG | 0x0100,
+            data: vec![4, 5],
+        });
+        let error = Header::decode(&header.encode()).unwrap_err();
+        assert!(error.to_string().contains("0x8100"));
+    }
+
+    #[test]
+    fn byte_reader_reports_the_end_of_its_bytes() {
+        let mut reader = ByteReader::new(&[1, 2, 3]);
+        assert_eq!(reader.u16().unwrap(), 0x0102);
+        assert!(reader.u16().unwrap_err().is::<UnexpectedEnd>());
+        assert_eq!(reader.u8().unwrap(), 3);
+        assert_eq!(reader.position(), 3);
+    }
+}
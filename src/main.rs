@@ -1,10 +1,24 @@
+mod archive;
 mod args;
+mod bits;
+mod compression;
+mod crypto;
+mod ecc;
 mod etcher;
+mod finder;
+mod fountain;
+mod header;
+mod metadata;
 mod settings;
+mod signing;
 mod source;
+mod spool;
 mod tasks;
+#[cfg(test)]
+mod test_support;
 mod timer;
 mod ui;
+mod video;
 
 use clap::Parser;
 
@@ -14,8 +28,8 @@ use clap::Parser;
 /// videos for storage on YouTube, which are later retrievable without data loss.
 ///
 /// The user workflow includes:
-/// 1. Zipping files to prepare for encoding.
-/// 2. Using the embed option to convert the archive into a video.
+/// 1. Selecting the files or folders to encode.
+/// 2. Using the embed option to convert them into a video.
 /// 3. Transmit or store the generated video securely
 /// 4. Downloading the video from YouTube when needed.
 /// 5. Using the dislodge option to extract the original files from the video.
@@ -30,10 +44,8 @@ async fn main() -> anyhow::Result<()> {
 
     // Providing a structured guide for users
     println!("\nUsage Instructions:");
-    println!(
-        "1. Prepare your files by archiving them into a single compressed format (e.g., ZIP)."
-    );
-    println!("2. Use the 'Embed' option to encode the archive into a video file.");
+    println!("1. Pick the files or folders to store; several paths and whole folders are packed automatically.");
+    println!("2. Use the 'Embed' option to encode them into a video file.");
     println!("3. Transmit or store the generated video securely.");
     println!("4. Use the 'Download' option to retrieve the video file.");
     println!("5. Use the 'Dislodge' option to extract the original files from the encoded video.");
@@ -0,0 +1,270 @@
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail};
+
+use crate::header::ByteReader;
+
+/// Metadata of an embedded file, recorded at embed time and restored when dislodging.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileMeta {
+    /// Original file name, without any directory components.
+    pub name: String,
+    /// Original length of the file in bytes.
+    pub size: u64,
+    /// Modification time, in seconds since the Unix epoch.
+    pub mtime: i64,
+    /// Sub-second part of the modification time.
+    pub mtime_nanos: u32,
+    /// Unix permission bits, or 0 when unknown (e.g. embedded on Windows).
+    pub mode: u32,
+}
+
+impl FileMeta {
+    /// Collects the metadata of the file at `path`.
+    ///
+    /// # Arguments
+    /// * `path` - The file being embedded.
+    ///
+    /// # Returns
+    /// The file's name, size, modification time and permissions.
+    pub fn from_path(path: &Path) -> anyhow::Result<FileMeta> {
+        let name = path
+            .file_name()
+            .ok_or_else(|| anyhow!("{} has no file name", path.display()))?
+            .to_string_lossy()
+            .into_owned();
+
+        FileMeta::from_metadata(name, &fs::metadata(path)?)
+    }
+
+    /// Builds the metadata from already queried filesystem metadata.
+    ///
+    /// # Arguments
+    /// * `name` - The name to record.
+    /// * `metadata` - The filesystem metadata, e.g. from `fs::symlink_metadata` for links.
+    pub fn from_metadata(name: String, metadata: &fs::Metadata) -> anyhow::Result<FileMeta> {
+        let (mtime, mtime_nanos) = split_time(metadata.modified()?);
+
+        Ok(FileMeta {
+            name,
+            size: metadata.len(),
+            mtime,
+            mtime_nanos,
+            mode: file_mode(metadata),
+        })
+    }
+
+    /// Returns the name to restore the file under, refusing anything that could
+    /// escape the output directory (separators, `..`, empty names).
+    pub fn file_name(&self) -> anyhow::Result<&str> {
+        let valid = Path::new(&self.name)
+            .file_name()
+            .is_some_and(|name| name == self.name.as_str());
+        if !valid || self.name.contains(['/', '\\']) {
+            bail!("Refusing to restore unsafe file name {:?}", self.name);
+        }
+        Ok(&self.name)
+    }
+
+    /// Applies the recorded modification time and permissions to a restored file or directory.
+    ///
+    /// # Arguments
+    /// * `path` - The restored file or directory.
+    pub fn apply(&self, path: &Path) -> anyhow::Result<()> {
+        let mtime = if self.mtime >= 0 {
+            UNIX_EPOCH + Duration::new(self.mtime as u64, self.mtime_nanos)
+        } else {
+            UNIX_EPOCH - Duration::from_secs(self.mtime.unsigned_abs())
+        };
+
+        // Directories can't be opened for writing
+        let handle = if path.is_dir() {
+            fs::File::open(path)?
+        } else {
+            fs::File::options().write(true).open(path)?
+        };
+        handle.set_modified(mtime)?;
+
+        set_file_mode(path, self.mode)?;
+
+        Ok(())
+    }
+
+    /// Serializes the metadata.
+    ///
+    /// Layout (big-endian): name length `u16`, UTF-8 name, size `u64`,
+    /// mtime `i64`, mtime nanoseconds `u32`, mode `u32`.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(self.name.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(self.name.as_bytes());
+        bytes.extend_from_slice(&self.size.to_be_bytes());
+        bytes.extend_from_slice(&self.mtime.to_be_bytes());
+        bytes.extend_from_slice(&self.mtime_nanos.to_be_bytes());
+        bytes.extend_from_slice(&self.mode.to_be_bytes());
+        bytes
+    }
+
+    /// Parses metadata written by `encode`.
+    pub fn decode(reader: &mut ByteReader) -> anyhow::Result<FileMeta> {
+        let name_len = reader.u16()? as usize;
+        let name = String::from_utf8(reader.take(name_len)?.to_vec())?;
+
+        Ok(FileMeta {
+            name,
+            size: reader.u64()?,
+            mtime: reader.u64()? as i64,
+            mtime_nanos: reader.u32()?,
+            mode: reader.u32()?,
+        })
+    }
+}
+
+/// Reads the Unix permission bits of a file.
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+/// Permission bits aren't available on this platform.
+#[cfg(not(unix))]
+fn file_mode(_metadata: &fs::Metadata) -> u32 {
+    0
+}
+
+/// Restores the Unix permission bits of a file. A mode of 0 means unknown and is skipped.
+#[cfg(unix)]
+fn set_file_mode(path: &Path, mode: u32) -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    if mode != 0 {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+/// Permission bits can't be restored on this platform.
+#[cfg(not(unix))]
+fn set_file_mode(_path: &Path, _mode: u32) -> anyhow::Result<()> {
+    Ok(())
+}
+
+/// Converts a `SystemTime` into the (seconds, nanoseconds) pair stored in `FileMeta`.
+/// Times before the epoch are stored as negative seconds.
+fn split_time(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => (since.as_secs() as i64, since.subsec_nanos()),
+        Err(before) => (-(before.duration().as_secs() as i64), 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(name: &str) -> FileMeta {
+        FileMeta {
+            name: name.to_string(),
+            size: 123_456_789_012,
+            mtime: 1_700_000_000,
+            mtime_nanos: 987_654_321,
+            mode: 0o640,
+        }
+    }
+
+    #[test]
+    fn encode_and_decode_round_trip() {
+        for meta in [
+            sample("report.pdf"),
+            sample("\u{e9}t\u{e9} \u{1f4c1}.txt"),
+            FileMeta {
+                mtime: -86_400,
+                mtime_nanos: 0,
+                mode: 0,
+                ..sample("old")
+            },
+        ] {
+            let bytes = meta.encode();
+            let mut reader = ByteReader::new(&bytes);
+            assert_eq!(FileMeta::decode(&mut reader).unwrap(), meta);
+            assert_eq!(reader.position(), bytes.len());
+        }
+    }
+
+    #[test]
+    fn truncated_or_corrupted_blobs_are_rejected() {
+        let bytes = sample("report.pdf").encode();
+        for len in 0..bytes.len() {
+            assert!(FileMeta::decode(&mut ByteReader::new(&bytes[..len])).is_err());
+        }
+
+        // A name length running past the end of the blob
+        let mut long = bytes.clone();
+        long[..2].copy_from_slice(&u16::MAX.to_be_bytes());
+        assert!(FileMeta::decode(&mut ByteReader::new(&long)).is_err());
+
+        // A name that isn't UTF-8
+        let mut garbled = bytes;
+        garbled[2] = 0xff;
+        assert!(FileMeta::decode(&mut ByteReader::new(&garbled)).is_err());
+    }
+
+    #[test]
+    fn file_name_accepts_plain_names() {
+        for name in ["report.pdf", ".hidden", "a..b", "with space"] {
+            assert_eq!(sample(name).file_name().unwrap(), name);
+        }
+    }
+
+    #[test]
+    fn file_name_refuses_names_that_escape_the_output_directory() {
+        for name in [
+            "",
+            ".",
+            "..",
+            "a/b",
+            "../a",
+            "/etc/passwd",
+            "a\\b",
+            "..\\a",
+            "a/",
+        ] {
+            assert!(sample(name).file_name().is_err(), "{:?}", name);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn apply_restores_modification_time_and_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, b"data").unwrap();
+        let meta = sample("file");
+        meta.apply(&file).unwrap();
+
+        let restored = fs::metadata(&file).unwrap();
+        assert_eq!(
+            split_time(restored.modified().unwrap()),
+            (meta.mtime, meta.mtime_nanos)
+        );
+        assert_eq!(restored.permissions().mode() & 0o7777, 0o640);
+
+        // Directories too, and an unknown mode leaves the permissions alone
+        let nested = dir.path().join("nested");
+        fs::create_dir(&nested).unwrap();
+        let before = fs::metadata(&nested).unwrap().permissions().mode();
+        let meta = FileMeta {
+            mode: 0,
+            ..sample("nested")
+        };
+        meta.apply(&nested).unwrap();
+
+        let restored = fs::metadata(&nested).unwrap();
+        assert_eq!(split_time(restored.modified().unwrap()).0, meta.mtime);
+        assert_eq!(restored.permissions().mode(), before);
+    }
+}
@@ -1,91 +1,84 @@
+use std::io::Read;
+
 use serde::Deserialize;
 
+use ed25519_dalek::SigningKey;
+
+use crate::header::PayloadInfo;
+
+/// Resolutions videos can be embedded at, as (name, width, height).
+pub const RESOLUTIONS: [(&str, i32, i32); 7] = [
+    ("144p", 256, 144),
+    ("240p", 426, 240),
+    ("360p", 640, 360),
+    ("480p", 854, 480),
+    ("720p", 1280, 720),
+    ("1080p", 1920, 1080),
+    ("2160p", 3840, 2160),
+];
+
 /// Represents the output mode of the data.
-/// `Binary` for binary output, `Color` for color data.
+/// `Binary` for binary output, `Color` for color data, `Gray4`/`Gray8` for multi-level luma
+/// and `Palette4`/`Palette8` for a small set of well separated colors.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum OutputMode {
-    Binary, // Binary mode for representing data as bits (e.g., `Vec<bool>`).
-    Color,  // Color mode for representing data as bytes (e.g., `Vec<u8>`).
+    Binary,   // Binary mode for representing data as bits (packed eight to a byte).
+    Color,    // Color mode for representing data as bytes (e.g., `Vec<u8>`).
+    Gray4,    // Four gray levels, 2 bits per block, stored as bytes.
+    Gray8,    // Eight gray levels, 3 bits per block, stored as bytes.
+    Palette4, // Four palette colors, 2 bits per block, stored as bytes.
+    Palette8, // Eight palette colors, 3 bits per block, stored as bytes.
+}
+
+impl OutputMode {
+    /// Number of bits carried by a single block in this mode.
+    pub fn bits_per_block(&self) -> usize {
+        match self {
+            OutputMode::Binary => 1,
+            OutputMode::Gray4 | OutputMode::Palette4 => 2,
+            OutputMode::Gray8 | OutputMode::Palette8 => 3,
+            OutputMode::Color => 24,
+        }
+    }
 }
 
 /// A struct to hold data and its corresponding output mode.
-/// - `bytes`: A vector of bytes (`u8`) used to store color or other non-binary data.
-/// - `binary`: A vector of booleans (`bool`) used to represent binary data.
-/// - `out_mode`: Specifies the mode (`Binary` or `Color`) to indicate the type of data stored.
+/// - `reader`: The payload to etch, read a frame at a time so it never sits in memory whole.
+/// - `len`: Length of the payload in bytes.
+/// - `out_mode`: Specifies the mode (`Binary`, `Color`, gray or palette) the data is etched in.
+/// - `payload`: Describes the transformations (e.g. error correction) applied to the data.
+/// - `signing_key`: Signs the header when set, completing `payload.signature`.
 pub struct Data {
-    pub bytes: Vec<u8>,    // Stores data in byte format (e.g., for color or raw data).
-    pub binary: Vec<bool>, // Stores data in binary format (a series of true/false values).
+    pub reader: Box<dyn Read + Send>, // Streams the payload bytes to the etcher.
+    pub len: u64,                     // Number of bytes the reader yields.
     pub out_mode: OutputMode, // Indicates the output mode of the data (`Binary` or `Color`).
+    pub payload: PayloadInfo, // Recorded in the header so the decoder can undo the transformations.
+    pub signing_key: Option<SigningKey>, // Key the header is signed with, if any.
 }
 
 impl Data {
-    /// Creates a new instance of `Data` with an empty state and a specified `OutputMode`.
-    ///
-    /// # Arguments
-    /// - `out_mode`: The desired output mode of the `Data` (`Binary` or `Color`).
-    ///
-    /// # Returns
-    /// A new `Data` instance with:
-    /// - An empty `bytes` vector.
-    /// - An empty `binary` vector.
-    /// - The specified `out_mode`.
-    ///
-    /// # Example
-    /// ```
-    /// let data = Data::new_out_mode(OutputMode::Binary);
-    /// ```
-    pub fn new_out_mode(out_mode: OutputMode) -> Data {
-        Data {
-            bytes: Vec::new(),  // Initializes an empty vector for `bytes`.
-            binary: Vec::new(), // Initializes an empty vector for `binary`.
-            out_mode,           // Sets the `out_mode` to the provided value.
-        }
-    }
-
-    /// Creates a new `Data` instance from a given binary vector.
-    ///
-    /// # Arguments
-    /// - `binary`: A vector of booleans representing binary data.
-    ///
-    /// # Returns
-    /// A new `Data` instance with:
-    /// - An empty `bytes` vector.
-    /// - The provided `binary` vector.
-    /// - The `OutputMode` set to `Binary`.
-    ///
-    /// # Example
-    /// ```
-    /// let binary_data = vec![true, false, true];
-    /// let data = Data::from_binary(binary_data);
-    /// ```
-    pub fn from_binary(binary: Vec<bool>) -> Data {
-        Data {
-            bytes: Vec::new(),            // Initializes an empty vector for `bytes`.
-            binary,                       // Sets `binary` to the provided binary vector.
-            out_mode: OutputMode::Binary, // Sets the output mode to `Binary`.
-        }
-    }
-
-    /// Creates a new `Data` instance from a given byte vector.
+    /// Creates a new `Data` instance streaming `len` bytes from `reader`.
     ///
     /// # Arguments
-    /// - `bytes`: A vector of bytes representing color or non-binary data.
+    /// - `reader`: Yields the payload bytes, in order.
+    /// - `len`: Number of bytes `reader` yields.
+    /// - `out_mode`: The mode the bytes are etched in.
     ///
     /// # Returns
-    /// A new `Data` instance with:
-    /// - The provided `bytes` vector.
-    /// - An empty `binary` vector.
-    /// - The `OutputMode` set to `Color`.
+    /// A new `Data` instance with no transformations recorded and no signing key.
     ///
     /// # Example
     /// ```
-    /// let color_data = vec![255, 128, 64];
-    /// let data = Data::from_color(color_data);
+    /// let bytes = vec![255, 128, 64];
+    /// let data = Data::new(Box::new(std::io::Cursor::new(bytes)), 3, OutputMode::Color);
     /// ```
-    pub fn from_color(bytes: Vec<u8>) -> Data {
+    pub fn new(reader: Box<dyn Read + Send>, len: u64, out_mode: OutputMode) -> Data {
         Data {
-            bytes,                       // Sets `bytes` to the provided byte vector.
-            binary: Vec::new(),          // Initializes an empty vector for `binary`.
-            out_mode: OutputMode::Color, // Sets the output mode to `Color`.
+            reader,                          // Sets `reader` to the provided stream.
+            len,                             // Sets `len` to the provided length.
+            out_mode,                        // Sets the `out_mode` to the provided value.
+            payload: PayloadInfo::default(), // No transformations recorded yet.
+            signing_key: None,               // Unsigned until a key is provided.
         }
     }
 }
@@ -143,11 +136,11 @@ impl Settings {
     /// - Setting up parameters for multi-threaded data processing.
     pub fn new(size: i32, threads: usize, fps: i32, width: i32, height: i32) -> Self {
         Settings {
-            size,    // Block or data unit size.
-            threads, // Number of threads for parallel processing.
-            fps: fps as f64,     // Frames per second for output.
-            width,   // Width of the frame or resolution.
-            height,  // Height of the frame or resolution.
+            size,            // Block or data unit size.
+            threads,         // Number of threads for parallel processing.
+            fps: fps as f64, // Frames per second for output.
+            width,           // Width of the frame or resolution.
+            height,          // Height of the frame or resolution.
         }
     }
 }
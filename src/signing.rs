@@ -0,0 +1,235 @@
+use anyhow::{anyhow, bail};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use ed25519_dalek::{Signer, SigningKey, Verifier, VerifyingKey};
+
+use crate::crypto::{from_hex, to_hex};
+
+/// Length of an Ed25519 signature.
+pub const SIGNATURE_LEN: usize = 64;
+
+/// Prefix of a textual verifying (public) key.
+const PUBLIC_KEY_PREFIX: &str = "isrs-sig-";
+
+/// Prefix of a textual signing (secret) key.
+const SECRET_KEY_PREFIX: &str = "ISRS-SIGNING-KEY-";
+
+/// Domain separation prepended to the signed header bytes.
+const SIGNATURE_CONTEXT: &[u8] = b"infinite-storage signature v1\0";
+
+/// An Ed25519 signature over the header, recorded as the header's last extension.
+///
+/// The signed message is every header byte before the signature itself, which covers
+/// the layout, every payload transformation and `payload_sha256`, so neither the header
+/// nor the payload can be altered without breaking the signature.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Signature {
+    /// Public key of the signer.
+    pub signer: [u8; 32],
+    /// SHA-256 of the payload as etched, before error correction was added.
+    pub payload_sha256: [u8; 32],
+    /// The Ed25519 signature, all zeroes until the header is signed.
+    pub signature: [u8; SIGNATURE_LEN],
+    /// The header bytes covered by the signature, filled in when decoding.
+    pub signed_header: Vec<u8>,
+}
+
+impl Signature {
+    /// Prepares an unsigned signature record for a payload, to be completed by
+    /// `Header::encode_signed`.
+    ///
+    /// # Arguments
+    /// * `key` - The key the header will be signed with.
+    /// * `payload_sha256` - SHA-256 of the payload as etched, before error correction.
+    pub fn new(key: &SigningKey, payload_sha256: [u8; 32]) -> Self {
+        Signature {
+            signer: key.verifying_key().to_bytes(),
+            payload_sha256,
+            signature: [0u8; SIGNATURE_LEN],
+            signed_header: Vec::new(),
+        }
+    }
+}
+
+/// Signs the header bytes preceding the signature.
+pub fn sign(signed_header: &[u8], key: &SigningKey) -> [u8; SIGNATURE_LEN] {
+    key.sign(&[SIGNATURE_CONTEXT, signed_header].concat())
+        .to_bytes()
+}
+
+/// Checks a decoded signature against the header and the payload read back from the video.
+///
+/// # Arguments
+/// * `signature` - The signature record from the header.
+/// * `payload_sha256` - SHA-256 of the payload, after error correction was undone.
+/// * `trusted` - The key the video must be signed by, if any.
+///
+/// # Returns
+/// The signer's public key, or an error if the signature, payload or signer don't match.
+pub fn verify(
+    signature: &Signature,
+    payload_sha256: [u8; 32],
+    trusted: Option<&VerifyingKey>,
+) -> anyhow::Result<String> {
+    let signer = format_verifying_key(&signature.signer);
+
+    if let Some(trusted) = trusted {
+        if trusted.as_bytes() != &signature.signer {
+            bail!(
+                "Signature check failed: signed by {}, not by the trusted key {}",
+                signer,
+                format_verifying_key(trusted.as_bytes())
+            );
+        }
+    }
+
+    let key = VerifyingKey::from_bytes(&signature.signer)
+        .map_err(|_| anyhow!("Signature check failed: invalid signer key"))?;
+    key.verify(
+        &[SIGNATURE_CONTEXT, &signature.signed_header].concat(),
+        &ed25519_dalek::Signature::from_bytes(&signature.signature),
+    )
+    .map_err(|_| {
+        anyhow!(
+            "Signature check failed: the header was altered or not signed by {}",
+            signer
+        )
+    })?;
+
+    if payload_sha256 != signature.payload_sha256 {
+        bail!("Signature check failed: the payload doesn't match the signed hash");
+    }
+
+    Ok(signer)
+}
+
+/// Generates a new Ed25519 signing key.
+///
+/// # Returns
+/// The signing key, ready to be written to a key file, and its public key to share.
+pub fn generate_signing_key() -> (String, String) {
+    let mut seed = [0u8; 32];
+    OsRng.fill_bytes(&mut seed);
+    let key = SigningKey::from_bytes(&seed);
+
+    (
+        format!("{}{}", SECRET_KEY_PREFIX, to_hex(&seed).to_uppercase()),
+        format_verifying_key(key.verifying_key().as_bytes()),
+    )
+}
+
+/// Parses a signing key file written by `keygen --signing`. Lines starting with `#` are comments.
+pub fn parse_signing_key(text: &str) -> anyhow::Result<SigningKey> {
+    let hex = text
+        .lines()
+        .map(str::trim)
+        .find_map(|line| line.strip_prefix(SECRET_KEY_PREFIX))
+        .ok_or_else(|| {
+            anyhow!(
+                "No {}... line found in the signing key file",
+                SECRET_KEY_PREFIX
+            )
+        })?;
+    Ok(SigningKey::from_bytes(&from_hex(hex)?))
+}
+
+/// Parses a verifying key as printed by `keygen --signing`.
+pub fn parse_verifying_key(text: &str) -> anyhow::Result<VerifyingKey> {
+    let hex = text
+        .trim()
+        .strip_prefix(PUBLIC_KEY_PREFIX)
+        .ok_or_else(|| anyhow!("{:?} is not a signer key ({}...)", text, PUBLIC_KEY_PREFIX))?;
+    VerifyingKey::from_bytes(&from_hex(hex)?)
+        .map_err(|_| anyhow!("{:?} is not a valid signer key", text))
+}
+
+/// Formats a verifying key for display.
+fn format_verifying_key(key: &[u8; 32]) -> String {
+    format!("{}{}", PUBLIC_KEY_PREFIX, to_hex(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::Header;
+    use crate::settings::OutputMode;
+
+    const PAYLOAD_SHA256: [u8; 32] = [0x5A; 32];
+
+    /// A header signed with a fresh key, decoded back as a reader would see it.
+    fn signed() -> (Signature, VerifyingKey) {
+        let key = parse_signing_key(&generate_signing_key().0).unwrap();
+        let mut header = Header::new(OutputMode::Binary, 2, 3, 100);
+        header.payload.signature = Some(Signature::new(&key, PAYLOAD_SHA256));
+
+        let decoded = Header::decode(&header.encode_signed(&key).unwrap()).unwrap();
+        (decoded.payload.signature.unwrap(), key.verifying_key())
+    }
+
+    #[test]
+    fn signed_header_verifies() {
+        let (signature, key) = signed();
+
+        let signer = verify(&signature, PAYLOAD_SHA256, None).unwrap();
+        assert_eq!(signer, format_verifying_key(key.as_bytes()));
+        assert_eq!(
+            verify(&signature, PAYLOAD_SHA256, Some(&key)).unwrap(),
+            signer
+        );
+    }
+
+    #[test]
+    fn bad_signature_is_rejected() {
+        let (mut signature, _) = signed();
+        signature.signature[0] ^= 1;
+
+        let error =
+            verify(&signature, PAYLOAD_SHA256, None).expect_err("a bad signature was accepted");
+        assert!(error.to_string().contains("altered"), "{}", error);
+    }
+
+    #[test]
+    fn altered_header_is_rejected() {
+        let (mut signature, _) = signed();
+        let last = signature.signed_header.len() - 1;
+        signature.signed_header[last] ^= 1;
+
+        assert!(verify(&signature, PAYLOAD_SHA256, None).is_err());
+    }
+
+    #[test]
+    fn other_payload_is_rejected() {
+        let (signature, _) = signed();
+
+        let error = verify(&signature, [0; 32], None).expect_err("another payload was accepted");
+        assert!(error.to_string().contains("payload"), "{}", error);
+    }
+
+    #[test]
+    fn untrusted_signer_is_rejected() {
+        let (signature, _) = signed();
+        let (_, other) = generate_signing_key();
+
+        let error = verify(
+            &signature,
+            PAYLOAD_SHA256,
+            Some(&parse_verifying_key(&other).unwrap()),
+        )
+        .expect_err("an untrusted signer was accepted");
+        assert!(
+            error.to_string().contains("not by the trusted key"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn keys_round_trip_through_text() {
+        let (secret, public) = generate_signing_key();
+        let key = parse_signing_key(&format!("# signing key\n{}\n", secret)).unwrap();
+
+        assert_eq!(parse_verifying_key(&public).unwrap(), key.verifying_key());
+        assert!(parse_verifying_key("isrs-pk-00").is_err());
+        assert!(parse_signing_key("# nothing").is_err());
+    }
+}
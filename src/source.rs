@@ -1,17 +1,20 @@
-use opencv::core::prelude::*;
-use opencv::core::{Mat, Size, Size_, CV_8UC3};
+use std::ops::Range;
+
+use crate::finder::Transform;
+use crate::video::Frame;
 
 /// A struct representing an embedding source, which includes an image and its associated sizes.
 /// The struct provides methods for creating an embedding source from scratch or from an existing image.
 pub struct EmbedSource {
-    /// The image (matrix) for embedding purposes.
-    pub image: Mat,
+    /// The image for embedding purposes.
+    pub image: Frame,
     /// The size of the embedding block.
     pub size: i32,
     /// The original frame size of the image.
     pub frame_size: Size,
-    /// The adjusted size of the image where both dimensions are multiples of `size`.
-    pub actual_size: Size,
+    /// Maps positions on `frame_size` to positions on the image. It is the identity unless
+    /// the video was rescaled, cropped, padded or distorted after embedding.
+    pub transform: Transform,
 }
 
 impl EmbedSource {
@@ -26,34 +29,15 @@ impl EmbedSource {
     /// # Returns
     ///
     /// A new instance of `EmbedSource` with initialized fields.
-    ///
-    /// # Panics
-    ///
-    /// Panics if the creation of a new OpenCV `Mat` fails.
     pub fn new(size: i32, width: i32, height: i32) -> EmbedSource {
         // Original size of the frame
-        let frame_size: Size_<i32> = Size::new(width, height);
-
-        // Adjust the width to be a multiple of the embedding size
-        let actual_width: i32 = width - (width % size);
-
-        // Adjust the height to be a multiple of the embedding size
-        let actual_height: i32 = height - (height % size);
+        let frame_size = Size::new(width, height);
 
-        // Create the adjusted size using the new width and height
-        let actual_size: Size_<i32> = Size::new(actual_width, actual_height);
-
-        unsafe {
-            // Create a new blank image (matrix) with the specified dimensions and type
-            let image: Mat = Mat::new_rows_cols(frame_size.height, frame_size.width, CV_8UC3)
-                .expect("Failed to create new Mat");
-
-            EmbedSource {
-                image,
-                size,
-                frame_size,
-                actual_size,
-            }
+        EmbedSource {
+            image: Frame::new(width, height),
+            size,
+            frame_size,
+            transform: Transform::identity(),
         }
     }
 
@@ -61,7 +45,7 @@ impl EmbedSource {
     ///
     /// # Arguments
     ///
-    /// * `image` - An OpenCV `Mat` containing the source image.
+    /// * `image` - The source image.
     /// * `size` - The size of the embedding block.
     /// * `instuction` - A boolean that determines whether to allow images where the height
     ///   is not divisible by the embedding size.
@@ -70,13 +54,13 @@ impl EmbedSource {
     ///
     /// * `Ok(EmbedSource)` if the image is successfully processed.
     /// * `Err(String)` if the image's dimensions are incompatible and `instuction` is `false`.
-    pub fn from(image: Mat, size: i32, instuction: bool) -> Result<EmbedSource, String> {
+    pub fn from(image: Frame, size: i32, instuction: bool) -> Result<EmbedSource, String> {
         // Extract the dimensions of the provided image
-        let width: i32 = image.cols();
-        let height: i32 = image.rows();
+        let width: i32 = image.width;
+        let height: i32 = image.height;
 
         // Create the original size of the image (frame size)
-        let frame_size: Size_<i32> = Size::new(width, height);
+        let frame_size = Size::new(width, height);
 
         // If the height is not a multiple of the embedding size and `instuction` is false,
         // return an error message
@@ -84,21 +68,158 @@ impl EmbedSource {
             return Err("Image size is not a multiple of the embedding size".to_string());
         }
 
-        // Adjust the width to be a multiple of the embedding size
-        let adjusted_width: i32 = width - (width % size);
-
-        // Adjust the height to be a multiple of the embedding size
-        let adjusted_height: i32 = height - (height % size);
-
-        // Create the adjusted size using the new width and height
-        let actual_size: Size_<i32> = Size::new(adjusted_width, adjusted_height);
-
         // Return the new `EmbedSource` instance
         Ok(EmbedSource {
             image,
             size,
             frame_size,
-            actual_size,
+            transform: Transform::identity(),
         })
     }
+
+    /// Creates a new `EmbedSource` from an image that was altered after embedding.
+    ///
+    /// Blocks keep the layout of the original `width` x `height` frame; their positions
+    /// are mapped onto the image through `transform` when they are sampled.
+    ///
+    /// # Arguments
+    ///
+    /// * `image` - The altered image.
+    /// * `size` - The size of the embedding block in the original frame.
+    /// * `width` - The width of the original frame.
+    /// * `height` - The height of the original frame.
+    /// * `transform` - Maps positions on the original frame to positions on the image.
+    ///
+    /// # Returns
+    ///
+    /// A new instance of `EmbedSource` laid out on the original frame.
+    pub fn aligned(
+        image: Frame,
+        size: i32,
+        width: i32,
+        height: i32,
+        transform: Transform,
+    ) -> EmbedSource {
+        // Lay the blocks out exactly as they were on the original frame
+        let frame_size = Size::new(width, height);
+
+        EmbedSource {
+            image,
+            size,
+            frame_size,
+            transform,
+        }
+    }
+
+    /// Borrows the image's pixels for reading blocks.
+    pub fn pixels(&self) -> Pixels<'_> {
+        Pixels::of(&self.image)
+    }
+
+    /// Borrows the image's pixels for writing blocks of `size` pixels per side.
+    pub fn pixels_mut(&mut self) -> PixelsMut<'_> {
+        PixelsMut {
+            data: &mut self.image.data,
+            width: self.image.width as usize,
+            size: self.size as usize,
+        }
+    }
+}
+
+/// Width and height of a frame, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Size {
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Size {
+    /// A size of `width` x `height` pixels.
+    pub fn new(width: i32, height: i32) -> Size {
+        Size { width, height }
+    }
+}
+
+/// The pixels of an image as contiguous BGR bytes, row after row. Borrowing them once
+/// per frame lets whole blocks be read straight from memory, a row slice at a time.
+pub struct Pixels<'a> {
+    data: &'a [u8],
+    width: usize,
+    height: usize,
+}
+
+impl<'a> Pixels<'a> {
+    /// Borrows the pixels of a frame.
+    pub fn of(image: &'a Frame) -> Pixels<'a> {
+        Pixels {
+            data: &image.data,
+            width: image.width as usize,
+            height: image.height as usize,
+        }
+    }
+
+    /// Width of the image in pixels.
+    pub fn width(&self) -> i32 {
+        self.width as i32
+    }
+
+    /// Height of the image in pixels.
+    pub fn height(&self) -> i32 {
+        self.height as i32
+    }
+
+    /// The RGB color of the pixel at (`x`, `y`).
+    pub fn rgb(&self, x: i32, y: i32) -> [u8; 3] {
+        let start = (y as usize * self.width + x as usize) * 3;
+        let bgr = &self.data[start..start + 3];
+        [bgr[2], bgr[1], bgr[0]]
+    }
+
+    /// Average RGB color of a rectangle of pixels, summed row slice by row slice.
+    ///
+    /// # Returns
+    /// The average color, or `None` if the rectangle is empty.
+    pub fn average(&self, rows: Range<i32>, columns: Range<i32>) -> Option<[u8; 3]> {
+        let count = rows.len() * columns.len();
+        if count == 0 {
+            return None;
+        }
+
+        let mut sums = [0usize; 3];
+        for row in rows {
+            let start = (row as usize * self.width + columns.start as usize) * 3;
+            let end = start + columns.len() * 3;
+            for bgr in self.data[start..end].chunks_exact(3) {
+                sums[0] += bgr[2] as usize;
+                sums[1] += bgr[1] as usize;
+                sums[2] += bgr[0] as usize;
+            }
+        }
+
+        Some(sums.map(|sum| (sum / count) as u8))
+    }
+}
+
+/// The pixels of an image as contiguous BGR bytes, borrowed for writing whole blocks.
+pub struct PixelsMut<'a> {
+    data: &'a mut [u8],
+    width: usize,
+    size: usize,
+}
+
+impl PixelsMut<'_> {
+    /// Fills the block whose top-left corner is at (`x`, `y`) with an RGB color. The
+    /// first row of the block is written pixel by pixel and copied into the others.
+    pub fn fill_block(&mut self, x: i32, y: i32, rgb: [u8; 3]) {
+        let bgr = [rgb[2], rgb[1], rgb[0]];
+        let row_len = self.size * 3;
+        let first = (y as usize * self.width + x as usize) * 3;
+        for pixel in self.data[first..first + row_len].chunks_exact_mut(3) {
+            pixel.copy_from_slice(&bgr);
+        }
+        for row in 1..self.size {
+            let start = first + row * self.width * 3;
+            self.data.copy_within(first..first + row_len, start);
+        }
+    }
 }
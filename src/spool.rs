@@ -0,0 +1,188 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use sha2::{Digest, Sha256};
+
+/// Size of the buffers used to move data through a spool.
+const BUFFER_LEN: usize = 1 << 20;
+
+/// Distinguishes the spools of a single process.
+static NEXT_SPOOL: AtomicUsize = AtomicUsize::new(0);
+
+/// A payload staged on disk between two steps of the embed pipeline, so files of any
+/// size can be compressed, encrypted and error corrected without holding them in memory.
+///
+/// Spools created with `Spool::new` live in the system's temporary directory and are
+/// removed when dropped. `Spool::open` wraps an existing file, which is left alone.
+pub struct Spool {
+    path: PathBuf,
+    writer: Option<BufWriter<File>>,
+    len: u64,
+    temporary: bool,
+}
+
+impl Spool {
+    /// Creates an empty temporary spool, ready to be written to.
+    pub fn new() -> anyhow::Result<Spool> {
+        Spool::new_in(&std::env::temp_dir())
+    }
+
+    /// Creates an empty temporary spool in `dir`, so it can later be persisted there
+    /// without copying it.
+    pub fn new_in(dir: &Path) -> anyhow::Result<Spool> {
+        let path = dir.join(format!(
+            "infinite-storage-{}-{}.spool",
+            std::process::id(),
+            NEXT_SPOOL.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        // Spools hold the plaintext payload, keep other users of the machine out of them
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let file = options.open(&path)?;
+
+        Ok(Spool {
+            path,
+            writer: Some(BufWriter::with_capacity(BUFFER_LEN, file)),
+            len: 0,
+            temporary: true,
+        })
+    }
+
+    /// Wraps an existing file, so it can be read like any other spool without copying it.
+    pub fn open(path: &Path) -> anyhow::Result<Spool> {
+        Ok(Spool {
+            path: path.to_path_buf(),
+            writer: None,
+            len: fs::metadata(path)?.len(),
+            temporary: false,
+        })
+    }
+
+    /// Number of bytes in the spool.
+    pub fn size(&self) -> u64 {
+        self.len
+    }
+
+    /// Opens the spool for reading from the start. Anything written so far is flushed first.
+    pub fn reader(&mut self) -> anyhow::Result<BufReader<File>> {
+        if let Some(writer) = &mut self.writer {
+            writer.flush()?;
+        }
+        Ok(BufReader::with_capacity(
+            BUFFER_LEN,
+            File::open(&self.path)?,
+        ))
+    }
+
+    /// Turns the spool into a reader that removes it once dropped.
+    pub fn into_reader(mut self) -> anyhow::Result<SpoolReader> {
+        let reader = self.reader()?;
+        Ok(SpoolReader {
+            reader,
+            _spool: self,
+        })
+    }
+
+    /// Moves the spool to `path`, where it is kept for good.
+    pub fn persist(mut self, path: &Path) -> anyhow::Result<()> {
+        self.flush()?;
+        self.writer = None;
+
+        // Spools on another filesystem can't be renamed, copy them instead
+        if fs::rename(&self.path, path).is_err() {
+            fs::copy(&self.path, path)?;
+            return Ok(());
+        }
+        self.temporary = false;
+        Ok(())
+    }
+
+    /// SHA-256 of the spool's contents.
+    pub fn sha256(&mut self) -> anyhow::Result<[u8; 32]> {
+        let mut reader = self.reader()?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; BUFFER_LEN];
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        Ok(hasher.finalize().into())
+    }
+}
+
+impl Write for Spool {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let writer = self
+            .writer
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::PermissionDenied, "spool is read-only"))?;
+        let written = writer.write(buf)?;
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.writer {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        if self.temporary {
+            // Close the file before removing it, some platforms refuse otherwise
+            self.writer = None;
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Reads a spool from the start, removing it once dropped.
+pub struct SpoolReader {
+    reader: BufReader<File>,
+    _spool: Spool,
+}
+
+impl Read for SpoolReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+/// Reads up to `len` bytes, fewer only at the end of the stream.
+pub fn read_chunk(reader: &mut impl Read, len: usize) -> io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(len);
+    reader.take(len as u64).read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn temporary_spools_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = Spool::new_in(dir.path()).unwrap();
+        spool.write_all(b"plaintext").unwrap();
+        spool.flush().unwrap();
+
+        let mode = fs::metadata(&spool.path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
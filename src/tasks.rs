@@ -3,6 +3,7 @@ use crate::args::Arguments;
 pub mod dislodge;
 pub mod download;
 pub mod embed;
+pub mod keygen;
 
 /// Executes the appropriate task based on the user's input arguments.
 ///
@@ -26,5 +27,8 @@ pub async fn run_by_arguments(args: Arguments) -> anyhow::Result<()> {
         
         // Handle the "Dislodge" command by invoking the dislodge module's function.
         crate::args::Commands::Dislodge(args) => dislodge::run_dislodge(args).await,
+
+        // Handle the "Keygen" command by invoking the keygen module's function.
+        crate::args::Commands::Keygen(args) => keygen::run_keygen(args).await,
     }
 }
@@ -1,32 +1,394 @@
-use crate::{args::DislodgeParams, etcher};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use anyhow::bail;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    archive, args::DislodgeParams, compression, crypto, crypto::KeySource, etcher, header::Header,
+    signing, spool::Spool, ui,
+};
 
 /// Handles the "dislodge" operation, which extracts embedded data from a video file
 /// and writes it back to a specified output path.
 ///
+/// When no output path is given, the file is restored under its original name inside
+/// the output directory, and its modification time and permissions are restored too.
+///
+/// The data is decoded and written out a piece at a time, so videos of any size can be
+/// restored with bounded memory. If the video carries a SHA-256 of the original bytes,
+/// the restored data is verified against it. Codewords too damaged for the error
+/// correction to repair are passed through and caught here too. On a mismatch the
+/// data is written with a `.corrupt` suffix instead (or deleted with `--delete-partial`)
+/// and an error is returned, so a damaged restore is never mistaken for a good one.
+///
+/// Signed videos are checked before anything is written; with `--verify` the signer
+/// must also match the trusted key.
+///
+/// Archives of several files or directories are extracted into the output path if
+/// given, otherwise into the output directory.
+///
 /// # Arguments
 /// * `args` - Parameters for the dislodge operation, including the input and output file paths.
 ///
 /// # Returns
 /// * `anyhow::Result<()>` - Indicates success or failure during the dislodge process.
 pub async fn run_dislodge(args: DislodgeParams) -> anyhow::Result<()> {
-    // Extract embedded data from the input video file.
+    // Extract embedded data from the input video file, staging it on disk.
     // The function expects a valid input path to be provided.
-    let out_data = etcher::read(
+    let threads = match args.threads {
+        Some(threads) => threads,
+        None => thread::available_parallelism().map_or(1, |threads| threads.get()),
+    };
+    let mut out_data = Spool::new()?;
+    let header = etcher::read(
         &args
             .in_path
             .expect("Input path not provided for dislodge operation"),
-        1, // Presumably, the frame index or processing flag for extraction
+        args.backend.map(Into::into),
+        threads,
+        &mut out_data,
     )?;
 
-    // Write the extracted data back to the specified output path.
+    // Check the signature before trusting anything else about the payload
+    let trusted = match &args.verify {
+        Some(key) => Some(signing::parse_verifying_key(key)?),
+        None => None,
+    };
+    match (&header.payload.signature, &trusted) {
+        (Some(signature), Some(trusted)) => {
+            let signer = signing::verify(signature, out_data.sha256()?, Some(trusted))?;
+            println!("Signature verified: signed by trusted key {}", signer);
+        }
+        (Some(signature), None) => {
+            let signer = signing::verify(signature, out_data.sha256()?, None)?;
+            println!(
+                "Signed by {} (pass --verify with a trusted key to check the signer)",
+                signer
+            );
+        }
+        (None, Some(_)) => bail!("The video isn't signed, so it can't be verified"),
+        (None, None) => (),
+    }
+
+    // Decrypt the payload, asking for the passphrase or identity only once we know it's needed
+    let mut out_data = match &header.payload.encryption {
+        Some(encryption) => match &encryption.key_source {
+            KeySource::Passphrase(_) => {
+                let passphrase = ui::read_passphrase(args.passphrase_file.as_deref(), false)?;
+                crypto::decrypt_with_passphrase(&mut out_data, encryption, &passphrase)?
+            }
+            KeySource::Recipients(_) => {
+                let identity_path = match &args.identity {
+                    Some(path) => path.clone(),
+                    None => ui::read_identity_path()?,
+                };
+                let identity = crypto::parse_identity(&fs::read_to_string(&identity_path)?)?;
+                crypto::decrypt_with_identity(&mut out_data, encryption, &identity)?
+            }
+        },
+        None => out_data,
+    };
+
+    // An explicit output path wins, otherwise the original file name is restored
+    let out_path = match (args.out_path, &header.payload.metadata) {
+        // Archives are extracted into a directory rather than written out as one file
+        (out_path, _) if header.payload.archive => {
+            PathBuf::from(out_path.or(args.out_dir).unwrap_or_else(|| ".".to_string()))
+        }
+        (Some(out_path), _) => PathBuf::from(out_path),
+        (None, Some(metadata)) => {
+            let out_dir = Path::new(args.out_dir.as_deref().unwrap_or("."));
+            fs::create_dir_all(out_dir)?;
+            out_dir.join(metadata.file_name()?)
+        }
+        (None, None) => {
+            bail!("The video doesn't record the original file name, please provide an output path")
+        }
+    };
+
+    // Undo the compression and verify the result as it is written. Archives are staged
+    // in a temporary file until extracted, single files next to their destination
+    let mut restored = if header.payload.archive {
+        Spool::new()?
+    } else {
+        let out_dir = match out_path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        Spool::new_in(out_dir)?
+    };
+    if let Err(error) = restore(&mut out_data, &header, &mut restored) {
+        if args.delete_partial {
+            bail!("{}. Partial data deleted", error);
+        }
+        let corrupt_path = if header.payload.archive {
+            fs::create_dir_all(&out_path)?;
+            out_path.join("archive.corrupt")
+        } else {
+            PathBuf::from(format!("{}.corrupt", out_path.display()))
+        };
+        restored.persist(&corrupt_path)?;
+        bail!(
+            "{}. Damaged data written to {}",
+            error,
+            corrupt_path.display()
+        );
+    }
+    drop(out_data);
+
+    if header.payload.archive {
+        let count = archive::unpack(&mut restored.into_reader()?, &out_path)?;
+        println!("Extracted {} entries into {}", count, out_path.display());
+        return Ok(());
+    }
+
+    // Move the restored data to the output path.
     // Ensure the output path is valid and accessible.
-    etcher::write_bytes(
-        &args
-            .out_path
-            .expect("Output path not provided for dislodge operation"),
-        out_data,
-    )?;
+    restored.persist(&out_path)?;
+    println!("File written successfully");
+
+    // Restore timestamps and permissions of the original file
+    if let Some(metadata) = &header.payload.metadata {
+        metadata.apply(&out_path)?;
+    }
+    println!("Restored {}", out_path.display());
 
     // Indicate successful completion of the dislodge operation.
     Ok(())
 }
+
+/// Undoes the compression applied at embed time, streaming the original bytes into `out`,
+/// and checks them against what was recorded at embed time on the way.
+///
+/// # Arguments
+/// * `payload` - The payload, decrypted if it was encrypted.
+/// * `header` - The header read from the video.
+/// * `out` - Where the original bytes are written.
+///
+/// # Returns
+/// Nothing, or an error if decompression or the integrity check fails.
+fn restore(payload: &mut Spool, header: &Header, out: &mut impl Write) -> anyhow::Result<()> {
+    let mut hashing = HashingWriter {
+        inner: out,
+        hasher: Sha256::new(),
+        len: 0,
+    };
+    match &header.payload.compression {
+        Some(compression) => compression::decompress(payload, compression, &mut hashing)?,
+        None => {
+            io::copy(&mut payload.reader()?, &mut hashing)?;
+        }
+    }
+    hashing.flush()?;
+
+    if let Some(reason) = integrity_error(hashing.len, hashing.hasher.finalize().into(), header) {
+        bail!("Integrity check failed: {}", reason);
+    }
+
+    Ok(())
+}
+
+/// Passes bytes through to `inner`, hashing and counting them on the way.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    len: u64,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Checks the restored data against the size and SHA-256 recorded in the header.
+///
+/// # Arguments
+/// * `len` - The length of the restored data.
+/// * `actual` - The SHA-256 of the restored data.
+/// * `header` - The header read from the video.
+///
+/// # Returns
+/// A description of the mismatch, or `None` if the data is intact.
+fn integrity_error(len: u64, actual: [u8; 32], header: &Header) -> Option<String> {
+    if let Some(metadata) = &header.payload.metadata {
+        if metadata.size != len {
+            return Some(format!(
+                "restored {} bytes but the original had {}",
+                len, metadata.size
+            ));
+        }
+    }
+
+    match header.payload.sha256 {
+        Some(expected) => {
+            if actual != expected {
+                return Some(format!(
+                    "SHA-256 is {} but {} was expected",
+                    to_hex(&actual),
+                    to_hex(&expected)
+                ));
+            }
+            println!("Integrity verified (SHA-256 {})", to_hex(&actual));
+        }
+        // The cipher's authentication tag already proved the data intact
+        None if header.payload.encryption.is_some() => {
+            println!("Integrity verified by the authentication tag")
+        }
+        None => println!("Video carries no checksum, skipping integrity verification"),
+    }
+
+    None
+}
+
+/// Formats bytes as a lowercase hexadecimal string.
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use super::*;
+    use crate::ecc;
+    use crate::settings::{Data, OutputMode, Settings};
+    use crate::test_support::sample_bytes;
+    use crate::video::Encoder;
+
+    /// `payload` to etch in binary mode, fingerprinted as `original`.
+    fn data(payload: &[u8], original: &[u8]) -> Data {
+        let reader = Cursor::new(payload.to_vec());
+        let mut data = Data::new(Box::new(reader), payload.len() as u64, OutputMode::Binary);
+        data.payload.sha256 = Some(Sha256::digest(original).into());
+        data
+    }
+
+    /// Etches `data` into a Y4M file in `dir`, returning parameters that dislodge it to
+    /// `restored.bin` next to it.
+    fn etch_video(dir: &Path, data: Data) -> DislodgeParams {
+        let video = dir.join("video.y4m").to_str().unwrap().to_string();
+        let settings = Settings::new(4, 1, 10, 320, 180);
+        etcher::etch(&video, &Encoder::Y4m, data, settings).unwrap();
+        DislodgeParams {
+            in_path: Some(video),
+            out_path: Some(dir.join("restored.bin").to_str().unwrap().to_string()),
+            threads: Some(2),
+            ..Default::default()
+        }
+    }
+
+    /// Names of the files in `dir`, sorted.
+    fn files(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn clean_restores_are_written_without_a_suffix() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = sample_bytes(5000);
+        let params = etch_video(dir.path(), data(&original, &original));
+
+        run_dislodge(params).await.unwrap();
+        assert_eq!(files(dir.path()), ["restored.bin", "video.y4m"]);
+        assert_eq!(fs::read(dir.path().join("restored.bin")).unwrap(), original);
+    }
+
+    #[tokio::test]
+    async fn flipped_payload_bytes_are_kept_as_corrupt() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = sample_bytes(5000);
+        let mut flipped = original.clone();
+        flipped[1234] ^= 0x10;
+        let params = etch_video(dir.path(), data(&flipped, &original));
+
+        let error = run_dislodge(params).await.unwrap_err().to_string();
+        assert!(error.starts_with("Integrity check failed"), "{}", error);
+        assert!(error.ends_with("restored.bin.corrupt"), "{}", error);
+        assert_eq!(files(dir.path()), ["restored.bin.corrupt", "video.y4m"]);
+        let corrupt = fs::read(dir.path().join("restored.bin.corrupt")).unwrap();
+        assert_eq!(corrupt, flipped);
+    }
+
+    #[tokio::test]
+    async fn flipped_payload_bytes_are_deleted_when_asked() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = sample_bytes(5000);
+        let mut flipped = original.clone();
+        flipped[1234] ^= 0x10;
+        let params = DislodgeParams {
+            delete_partial: true,
+            ..etch_video(dir.path(), data(&flipped, &original))
+        };
+
+        let error = run_dislodge(params).await.unwrap_err().to_string();
+        assert!(error.ends_with("Partial data deleted"), "{}", error);
+        assert_eq!(files(dir.path()), ["video.y4m"]);
+    }
+
+    /// `original` protected with Reed-Solomon parity, with the first codewords damaged
+    /// beyond repair.
+    fn uncorrectable(original: &[u8]) -> Data {
+        let mut spool = Spool::new().unwrap();
+        spool.write_all(original).unwrap();
+        let (mut encoded, ecc) = ecc::encode(&mut spool, ecc::DEFAULT_PARITY).unwrap();
+        let mut payload = Vec::new();
+        encoded.reader().unwrap().read_to_end(&mut payload).unwrap();
+        // Consecutive bytes belong to different codewords, every one of the first group
+        // gets more errors than its parity can correct
+        let errors = ecc::DEFAULT_PARITY as usize / 2 + 1;
+        for byte in &mut payload[..ecc::DEFAULT_INTERLEAVE as usize * errors] {
+            *byte ^= 0xff;
+        }
+
+        let mut data = data(&payload, original);
+        data.payload.ecc = Some(ecc);
+        data
+    }
+
+    #[tokio::test]
+    async fn uncorrectable_codewords_are_kept_as_corrupt() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = sample_bytes(20_000);
+        let params = etch_video(dir.path(), uncorrectable(&original));
+
+        let error = run_dislodge(params).await.unwrap_err().to_string();
+        assert!(error.starts_with("Integrity check failed"), "{}", error);
+        assert_eq!(files(dir.path()), ["restored.bin.corrupt", "video.y4m"]);
+        // The codewords beyond repair are passed through, the others are intact
+        let corrupt = fs::read(dir.path().join("restored.bin.corrupt")).unwrap();
+        assert_eq!(corrupt.len(), original.len());
+        assert_ne!(corrupt, original);
+        let intact = ecc::DEFAULT_INTERLEAVE as usize * (255 - ecc::DEFAULT_PARITY as usize);
+        assert_eq!(corrupt[intact..], original[intact..]);
+    }
+
+    #[tokio::test]
+    async fn uncorrectable_codewords_are_deleted_when_asked() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = sample_bytes(20_000);
+        let params = DislodgeParams {
+            delete_partial: true,
+            ..etch_video(dir.path(), uncorrectable(&original))
+        };
+
+        let error = run_dislodge(params).await.unwrap_err().to_string();
+        assert!(error.ends_with("Partial data deleted"), "{}", error);
+        assert_eq!(files(dir.path()), ["video.y4m"]);
+    }
+}
@@ -1,7 +1,20 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::bail;
+
 use crate::{
-    args::{EmbedOutputMode, EmbedParams, EmbedPreset},
-    etcher,
-    settings::{Data, OutputMode, Settings},
+    archive,
+    args::{EmbedParams, EmbedPreset},
+    compression::{self, Algorithm},
+    crypto::{self, Cipher},
+    ecc, etcher, fountain,
+    metadata::FileMeta,
+    settings::{Data, OutputMode, Settings, RESOLUTIONS},
+    signing::{self, Signature},
+    spool::Spool,
+    ui,
+    video::{self, Encoder},
 };
 
 /// Handles the embedding operation by configuring settings based on user input or defaults,
@@ -17,12 +30,18 @@ pub async fn run_embed(args: EmbedParams) -> anyhow::Result<()> {
     let mut settings: Settings = Settings::default();
     // Default output mode is set to Binary
     let mut output_mode = OutputMode::Binary;
+    // Default error correction is RS(255,223)
+    let mut parity = ecc::DEFAULT_PARITY;
+    // Default fountain code overhead is 10% of the frames
+    let mut overhead = fountain::DEFAULT_OVERHEAD;
 
     // Configure settings based on the preset, if provided
     match args.preset {
         Some(EmbedPreset::MaxEfficiency) => {
             // MaxEfficiency preset prioritizes speed and lower resource usage
             output_mode = OutputMode::Color;
+            parity = 0;                // No error correction overhead
+            overhead = 0;              // No fountain code either
             settings.size = 1;         // Smaller block size
             settings.threads = 8;      // Use 8 threads
             settings.fps = 10.0;       // Lower FPS
@@ -32,6 +51,8 @@ pub async fn run_embed(args: EmbedParams) -> anyhow::Result<()> {
         Some(EmbedPreset::Optimal) => {
             // Optimal preset balances quality and efficiency
             output_mode = OutputMode::Binary;
+            parity = 32;               // RS(255,223)
+            overhead = 10;             // Survives losing about 1 frame in 11
             settings.size = 2;         // Moderate block size
             settings.threads = 8;      // Use 8 threads
             settings.fps = 10.0;       // Moderate FPS
@@ -41,6 +62,8 @@ pub async fn run_embed(args: EmbedParams) -> anyhow::Result<()> {
         Some(EmbedPreset::Paranoid) => {
             // Paranoid preset prioritizes robustness and maximum data redundancy
             output_mode = OutputMode::Binary;
+            parity = 64;               // RS(255,191)
+            overhead = 25;             // Survives losing about 1 frame in 5
             settings.size = 4;         // Larger block size
             settings.threads = 8;      // Use 8 threads
             settings.fps = 10.0;       // Moderate FPS
@@ -54,22 +77,22 @@ pub async fn run_embed(args: EmbedParams) -> anyhow::Result<()> {
 
     // If resolution is not set by the preset or arguments, fallback to default resolution
     if settings.width == 0 || settings.height == 0 {
-        if args.resolution.is_none() {
-            // Default resolution if none is provided
-            settings.width = 640;      // Default width
-            settings.height = 360;     // Default height
-        } else {
+        match args.resolution.as_deref() {
             // Parse resolution from the provided string argument
-            let (width, height) = match args.resolution.unwrap().as_str() {
-                "144p" => (256, 144),
-                "240p" => (426, 240),
-                "360p" => (640, 360),
-                "480p" => (854, 480),
-                "720p" => (1280, 720),
-                _ => (640, 360), // Default to 360p if resolution is invalid
-            };
-            settings.width = width;
-            settings.height = height;
+            Some(resolution) => {
+                let (width, height) = RESOLUTIONS
+                    .iter()
+                    .find(|(name, _, _)| *name == resolution)
+                    .map(|&(_, width, height)| (width, height))
+                    .unwrap_or((640, 360)); // Default to 360p if resolution is invalid
+                settings.width = width;
+                settings.height = height;
+            }
+            // Default resolution if none is provided
+            None => {
+                settings.width = 640; // Default width
+                settings.height = 360; // Default height
+            }
         }
     }
 
@@ -93,33 +116,178 @@ pub async fn run_embed(args: EmbedParams) -> anyhow::Result<()> {
         settings.fps = fps.into();
     }
 
-    // Match the output mode to perform the embedding operation
-    match output_mode {
-        OutputMode::Color => {
-            // Handle color output mode
-            // Rip the raw bytes from the input file
-            let bytes = etcher::rip_bytes(&args.in_path.expect("No path provided in arguments"))?;
+    // Override error correction strength if explicitly provided
+    if let Some(p) = args.parity {
+        parity = p;
+    }
+
+    // Override fountain code overhead if explicitly provided
+    if let Some(o) = args.overhead {
+        overhead = o;
+    }
+
+    // Blocks must advance across the frame and fit in it at least once
+    if settings.size < 1 {
+        bail!("Block size must be at least 1 pixel, got {}", settings.size);
+    }
+    if settings.size > settings.width || settings.size > settings.height {
+        bail!(
+            "Blocks of {} pixels don't fit in a {}x{} frame",
+            settings.size,
+            settings.width,
+            settings.height
+        );
+    }
+
+    // Containers can't record a frame rate that isn't positive
+    if !settings.fps.is_finite() || settings.fps <= 0.0 {
+        bail!("Frame rate must be positive, got {}", settings.fps);
+    }
+
+    if args.in_path.is_empty() {
+        bail!("No path provided in arguments");
+    }
+
+    // Settle the output video and how it is encoded before any heavy lifting
+    let output = args
+        .output
+        .unwrap_or_else(|| video::DEFAULT_OUTPUT.to_string());
+    let encoder: Encoder = video::select_encoder(
+        &output,
+        args.codec.map(Into::into),
+        args.backend.map(Into::into),
+        args.ffmpeg_args.as_deref(),
+    )?;
+    let in_paths: Vec<PathBuf> = args.in_path.iter().map(PathBuf::from).collect();
 
-            // Create data in color mode
-            let data = Data::from_color(bytes);
+    // Recipients imply encryption, with ChaCha20-Poly1305 unless another cipher was picked
+    let recipients = args
+        .recipient
+        .iter()
+        .map(|recipient| crypto::parse_recipient(recipient))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let cipher: Option<Cipher> = match args.encrypt {
+        Some(cipher) => Some(cipher.into()),
+        None if !recipients.is_empty() => Some(Cipher::ChaCha20Poly1305),
+        None => None,
+    };
 
-            // Perform the etching operation to generate the output video
-            etcher::etch("output.avi", data, settings)?;
+    // Ask for the passphrase up front, before any heavy lifting
+    let passphrase = match cipher {
+        Some(_) if recipients.is_empty() => {
+            Some(ui::read_passphrase(args.passphrase_file.as_deref(), true)?)
         }
-        OutputMode::Binary => {
-            // Handle binary output mode
-            // Rip the raw bytes from the input file
-            let bytes = etcher::rip_bytes(&args.in_path.expect("No path provided in arguments"))?;
-            // Convert raw bytes to binary format
-            let binary = etcher::rip_binary(bytes)?;
-
-            // Create data in binary mode
-            let data = Data::from_binary(binary);
-
-            // Perform the etching operation to generate the output video
-            etcher::etch("output.avi", data, settings)?;
+        _ => None,
+    };
+
+    let signing_key = match &args.sign {
+        Some(path) => Some(signing::parse_signing_key(&fs::read_to_string(path)?)?),
+        None => None,
+    };
+
+    // A single non-empty file is embedded as-is; anything else (several paths,
+    // directories, links or empty files) is packed into an archive. Encrypted
+    // payloads are always archived, so the file name and size stay private too
+    let single_file = match in_paths.as_slice() {
+        [path] => fs::metadata(path).is_ok_and(|m| m.is_file() && m.len() > 0),
+        _ => false,
+    } && cipher.is_none();
+
+    let (mut spool, metadata) = if single_file {
+        // Stream the input file as-is, remembering its name, timestamps
+        // and permissions so dislodge can restore them
+        let spool = Spool::open(&in_paths[0])?;
+        println!("Byte length: {}", spool.size());
+        (spool, Some(FileMeta::from_path(&in_paths[0])?))
+    } else {
+        // The archive's file table carries the metadata of every entry
+        (archive::pack(&in_paths)?, None)
+    };
+
+    // Fingerprint the original bytes so dislodge can prove the restore is bit-exact.
+    // Encrypted payloads are authenticated by the cipher instead, as a public hash
+    // would let anyone confirm a guess of the contents
+    let sha256: Option<[u8; 32]> = match cipher {
+        Some(_) => None,
+        None => Some(spool.sha256()?),
+    };
+
+    // Compress the bytes, unless disabled or they turn out to be incompressible
+    let algorithm: Algorithm = args.compression.map_or(Algorithm::Zstd, Into::into);
+    let level = args
+        .compression_level
+        .unwrap_or_else(|| algorithm.default_level());
+    // Every step stages its output in a temporary file, dropped as soon as the next
+    // step has consumed it
+    let (mut spool, compression) = match compression::compress(&mut spool, algorithm, level)? {
+        Some((compressed, compression)) => {
+            drop(spool);
+            (compressed, Some(compression))
         }
-    }
+        None => (spool, None),
+    };
+
+    // Encrypt the compressed bytes, if requested
+    let (mut spool, encryption) = match cipher {
+        Some(cipher) => {
+            let (sealed, encryption) = match &passphrase {
+                Some(passphrase) => {
+                    crypto::encrypt_with_passphrase(&mut spool, cipher, passphrase)?
+                }
+                None => crypto::encrypt_to_recipients(&mut spool, cipher, &recipients)?,
+            };
+            drop(spool);
+            (sealed, Some(encryption))
+        }
+        None => (spool, None),
+    };
+
+    // Sign the payload exactly as etched, so it can be checked before decrypting anything
+    let signature = match &signing_key {
+        Some(key) => Some(Signature::new(key, spool.sha256()?)),
+        None => None,
+    };
+
+    // Protect the bytes with Reed-Solomon parity, unless disabled. With a fountain code,
+    // the bytes are spread over the frames first and each frame is protected on its own
+    let (spool, ecc, fountain) = if overhead > 0 {
+        let frame_bytes = etcher::frame_bytes(output_mode, &settings);
+        let frame_data = if parity > 0 {
+            ecc::frame_data_len(parity, frame_bytes)
+        } else {
+            frame_bytes
+        };
+        let (mut encoded, fountain) = fountain::encode(&mut spool, frame_data, overhead)?;
+        drop(spool);
+        if parity > 0 {
+            let (encoded, ecc) = ecc::encode_frames(&mut encoded, parity, frame_bytes)?;
+            (encoded, Some(ecc), Some(fountain))
+        } else {
+            (encoded, None, Some(fountain))
+        }
+    } else if parity > 0 {
+        let (encoded, ecc) = ecc::encode(&mut spool, parity)?;
+        drop(spool);
+        (encoded, Some(ecc), None)
+    } else {
+        (spool, None, None)
+    };
+
+    // Stream the staged bytes into the etcher, which splits them into frames as it goes
+    let len = spool.size();
+    let mut data = Data::new(Box::new(spool.into_reader()?), len, output_mode);
+    data.payload.ecc = ecc;
+    data.payload.fountain = fountain;
+    data.payload.compression = compression;
+    data.payload.encryption = encryption;
+    data.payload.sha256 = sha256;
+    data.payload.metadata = metadata;
+    data.payload.archive = !single_file;
+    data.payload.signature = signature;
+    data.signing_key = signing_key;
+
+    // Perform the etching operation to generate the output video
+    etcher::etch(&output, &encoder, data, settings)?;
 
     // Return success
     Ok(())
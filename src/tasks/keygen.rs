@@ -0,0 +1,55 @@
+use std::fs;
+use std::io::Write;
+
+use anyhow::Context;
+
+use crate::{args::KeygenParams, crypto, signing};
+
+/// Generates an X25519 identity for receiving encrypted videos, or with `--signing`
+/// an Ed25519 key for signing them.
+///
+/// The key file holds the secret key and, as a comment, the matching public key.
+/// The public key is printed so it can be shared: with whoever embeds videos for its
+/// owner (`embed --recipient`), or with whoever checks signed videos (`dislodge --verify`).
+/// The file is never overwritten, and is only readable by its owner on Unix.
+///
+/// # Arguments
+/// * `args` - Parameters for the keygen operation, including the identity file path.
+///
+/// # Returns
+/// * `anyhow::Result<()>` - Indicates success or failure during key generation.
+pub async fn run_keygen(args: KeygenParams) -> anyhow::Result<()> {
+    let out_path = args
+        .out_path
+        .expect("Output path not provided for keygen operation");
+
+    let (secret_key, public_key) = if args.signing {
+        signing::generate_signing_key()
+    } else {
+        crypto::generate_identity()
+    };
+    let contents = format!(
+        "# created: {}\n# public key: {}\n{}\n",
+        chrono::Local::now().to_rfc3339(),
+        public_key,
+        secret_key
+    );
+
+    // Refuse to overwrite an existing key, and keep the new one private
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(&out_path)
+        .with_context(|| format!("Couldn't create key file {}", out_path))?
+        .write_all(contents.as_bytes())?;
+
+    println!("Key written to {}", out_path);
+    println!("Public key: {}", public_key);
+
+    Ok(())
+}
@@ -0,0 +1,6 @@
+//! Fixtures shared by the tests of several modules.
+
+/// Deterministic bytes that don't repeat with the codeword length or the frame layout.
+pub(crate) fn sample_bytes(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 + i / 7) as u8).collect()
+}
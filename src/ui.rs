@@ -1,12 +1,11 @@
-use anyhow; // Error handling library for cleaner error propagation
 #[allow(unused_imports)] // Suppresses warnings for unused imports
 use inquire::{min_length, Confirm, CustomType, MultiSelect, Password, Select, Text}; // Interactive command-line prompts from the 'inquire' crate
 
-use crate::args::{Commands, DislodgeParams, DownloadParams, EmbedParams}; // Importing application-specific command parameters
+use crate::args::{Commands, DislodgeParams, DownloadParams, EmbedParams, KeygenParams}; // Importing application-specific command parameters
 
 /// Enriches and completes user-provided arguments by prompting for missing inputs.
 ///
-/// This function handles three main commands: Embed, Download, and Dislodge.
+/// This function handles four main commands: Embed, Download, Dislodge and Keygen.
 /// If no command is provided, it prompts the user to select one.
 ///
 /// # Arguments
@@ -29,12 +28,16 @@ pub async fn enrich_arguments(args: Option<Commands>) -> anyhow::Result<Commands
             // Enrich Dislodge command parameters if provided
             Commands::Dislodge(enrich_dislodge_params(dislodge_args).await?)
         }
+        Some(Commands::Keygen(keygen_args)) => {
+            // Enrich Keygen command parameters if provided
+            Commands::Keygen(enrich_keygen_params(keygen_args).await?)
+        }
         None => {
             // Present user with available command options
-            let options = vec!["Embed", "Download", "Dislodge"];
+            let options = vec!["Embed", "Download", "Dislodge", "Keygen"];
 
             let modes = Select::new("Pick what you want to do with the program", options)
-                .with_help_message("Embed: Create a video from files,\n Download: Download files stored on YouTube,\n Dislodge: Return files from an embedded video")
+                .with_help_message("Embed: Create a video from files,\n Download: Download files stored on YouTube,\n Dislodge: Return files from an embedded video,\n Keygen: Create an identity to receive encrypted videos")
                 .prompt()
                 .unwrap();
 
@@ -42,6 +45,7 @@ pub async fn enrich_arguments(args: Option<Commands>) -> anyhow::Result<Commands
                 "Embed" => Commands::Embed(enrich_embed_params(EmbedParams::default()).await?),
                 "Download" => Commands::Download(enrich_download_params(DownloadParams::default()).await?),
                 "Dislodge" => Commands::Dislodge(enrich_dislodge_params(DislodgeParams::default()).await?),
+                "Keygen" => Commands::Keygen(enrich_keygen_params(KeygenParams::default()).await?),
                 _ => unreachable!(), // Ensures exhaustive matching
             }
         }
@@ -50,13 +54,25 @@ pub async fn enrich_arguments(args: Option<Commands>) -> anyhow::Result<Commands
 
 /// Enriches the parameters for the Embed command by prompting the user for missing values.
 async fn enrich_embed_params(mut args: EmbedParams) -> anyhow::Result<EmbedParams> {
-    if args.in_path.is_none() {
-        // Prompt user for input file path if not provided
-        let path = Text::new("What is the path to your file ?")
-            .with_default("src/tests/test.txt")
-            .prompt()
-            .unwrap();
-        args.in_path = Some(path);
+    // Optional features are only offered when walking the user through the whole setup,
+    // so scripted runs that pass their input paths are never interrupted
+    let interactive = args.in_path.is_empty();
+
+    if args.in_path.is_empty() {
+        // Prompt user for input paths until an empty answer is given
+        loop {
+            let mut prompt = Text::new("What is the path to your file or folder ?")
+                .with_help_message("Add as many files or folders as you like, leave empty when done");
+            if args.in_path.is_empty() {
+                prompt = prompt.with_default("src/tests/test.txt");
+            }
+
+            let path = prompt.prompt().unwrap();
+            if path.is_empty() {
+                break;
+            }
+            args.in_path.push(path);
+        }
     }
 
     // println!("\nI couldn't figure out a weird bug that happens if you set the size to something that isn't a factor of the height");
@@ -67,6 +83,9 @@ async fn enrich_embed_params(mut args: EmbedParams) -> anyhow::Result<EmbedParam
         && args.threads.is_none()
         && args.fps.is_none()
         && args.resolution.is_none()
+        && args.parity.is_none()
+        && args.overhead.is_none()
+        && args.compression.is_none()
     {
         // Offer preset options if no advanced parameters are set
         let presets = vec![
@@ -83,30 +102,71 @@ async fn enrich_embed_params(mut args: EmbedParams) -> anyhow::Result<EmbedParam
         match preset {
             "Maximum efficiency" => {
                 args.preset = Some(crate::args::EmbedPreset::MaxEfficiency);
-                return Ok(args);
             }
             "Optimal compression resistance" => {
                 args.preset = Some(crate::args::EmbedPreset::Optimal);
-                return Ok(args);
             }
             "Paranoid compression resistance" => {
                 args.preset = Some(crate::args::EmbedPreset::Paranoid);
-                return Ok(args);
             }
             _ => (), // Custom settings fall through to advanced prompts
         }
     }
 
-    // Custom or partially set parameters, prompting for each missing value
+    // Presets settle everything else, custom or partially set parameters are prompted for
+    if args.preset.is_none() {
+        enrich_embed_settings(&mut args, interactive)?;
+    }
+
+    if interactive && args.encrypt.is_none() && args.recipient.is_empty() {
+        let encrypt = Confirm::new("Encrypt the data with a passphrase ?")
+            .with_default(false)
+            .with_help_message("Anyone who gets hold of the video can otherwise read the data")
+            .prompt()?;
+        if encrypt {
+            args.encrypt = Some(crate::args::EmbedCipher::ChaCha20Poly1305);
+        }
+    }
+
+    if interactive && args.output.is_none() {
+        // Prompt user for the video to create
+        let output = Text::new("Where should the video be saved ?")
+            .with_default(crate::video::DEFAULT_OUTPUT)
+            .with_help_message("The extension picks the container: .avi, .mkv, .mp4 or .y4m. A directory, e.g. frames/, gets numbered PNG frames")
+            .prompt()?;
+        args.output = Some(output);
+    }
+
+    Ok(args)
+}
+
+/// Prompts for the embedding settings missing from the arguments, when no preset was picked.
+///
+/// # Arguments
+/// * `args` - The Embed parameters to complete.
+/// * `interactive` - Whether the user is being walked through the whole setup, which
+///   offers the optional features too.
+fn enrich_embed_settings(args: &mut EmbedParams, interactive: bool) -> anyhow::Result<()> {
     if args.mode.is_none() {
-        let out_modes = vec!["Colored", "B/W (Binary)"];
+        let out_modes = vec![
+            "Colored",
+            "B/W (Binary)",
+            "Gray, 4 levels",
+            "Gray, 8 levels",
+            "Palette, 4 colors",
+            "Palette, 8 colors",
+        ];
         let out_mode = Select::new("Pick how data will be embedded", out_modes.clone())
-            .with_help_message("Colored mode is useless if the video undergoes compression at any point, B/W survives compression")
+            .with_help_message("Colored mode is useless if the video undergoes compression at any point, B/W survives compression, gray levels and palettes trade some of that for density")
             .prompt()
             .unwrap();
         args.mode = Some(match out_mode {
             "Colored" => crate::args::EmbedOutputMode::Colored,
             "B/W (Binary)" => crate::args::EmbedOutputMode::Binary,
+            "Gray, 4 levels" => crate::args::EmbedOutputMode::Gray4,
+            "Gray, 8 levels" => crate::args::EmbedOutputMode::Gray8,
+            "Palette, 4 colors" => crate::args::EmbedOutputMode::Palette4,
+            "Palette, 8 colors" => crate::args::EmbedOutputMode::Palette8,
             _ => unreachable!(),
         });
     }
@@ -139,7 +199,42 @@ async fn enrich_embed_params(mut args: EmbedParams) -> anyhow::Result<EmbedParam
         args.fps = Some(fps);
     }
 
-    let resolutions = vec!["144p", "240p", "360p", "480p", "720p"];
+    if interactive && args.parity.is_none() {
+        let parity = CustomType::<u8>::new("How many error correction bytes per 255-byte block ?")
+            .with_error_message("Please type a valid number")
+            .with_help_message("Must be even. 32 corrects up to 16 damaged bytes per block, 0 disables error correction")
+            .with_default(crate::ecc::DEFAULT_PARITY)
+            .prompt()?;
+        args.parity = Some(parity);
+    }
+
+    if interactive && args.overhead.is_none() {
+        let overhead = CustomType::<u8>::new("How many extra frames, in percent, should be added to survive lost frames ?")
+            .with_error_message("Please type a valid number")
+            .with_help_message("10 restores the file from any 10 frames in 11, 0 disables the fountain code")
+            .with_default(crate::fountain::DEFAULT_OVERHEAD)
+            .prompt()?;
+        args.overhead = Some(overhead);
+    }
+
+    if interactive && args.compression.is_none() {
+        let compressions = vec!["Zstd", "XZ", "None"];
+        let compression = Select::new("Pick how data will be compressed", compressions)
+            .with_help_message("Zstd is fast, XZ is slower but smaller on text. Incompressible data is stored as-is")
+            .prompt()
+            .unwrap();
+        args.compression = Some(match compression {
+            "Zstd" => crate::args::EmbedCompression::Zstd,
+            "XZ" => crate::args::EmbedCompression::Xz,
+            "None" => crate::args::EmbedCompression::None,
+            _ => unreachable!(),
+        });
+    }
+
+    let resolutions: Vec<&str> = crate::settings::RESOLUTIONS
+        .iter()
+        .map(|(name, _, _)| *name)
+        .collect();
 
     if args.resolution.is_none() {
         // Prompt user for video resolution
@@ -150,7 +245,46 @@ async fn enrich_embed_params(mut args: EmbedParams) -> anyhow::Result<EmbedParam
         args.resolution = Some(resolution.to_string());
     }
 
-    Ok(args)
+    Ok(())
+}
+
+/// Reads the passphrase protecting an encrypted video, from a file if one is given,
+/// otherwise by prompting for it.
+///
+/// # Arguments
+/// * `file` - Optional file holding the passphrase on its first line.
+/// * `confirm` - Whether to ask twice, as when choosing a new passphrase.
+///
+/// # Returns
+/// The passphrase.
+pub fn read_passphrase(file: Option<&str>, confirm: bool) -> anyhow::Result<String> {
+    if let Some(file) = file {
+        let contents = std::fs::read_to_string(file)?;
+        return Ok(contents.lines().next().unwrap_or_default().to_string());
+    }
+
+    loop {
+        let passphrase = Password::new("What is the passphrase ?").prompt()?;
+        if !confirm {
+            return Ok(passphrase);
+        }
+
+        let repeated = Password::new("Please repeat the passphrase").prompt()?;
+        if passphrase == repeated {
+            return Ok(passphrase);
+        }
+        println!("The passphrases don't match, please try again");
+    }
+}
+
+/// Prompts for the identity file used to decrypt a video encrypted to recipients.
+///
+/// # Returns
+/// The path to the identity file.
+pub fn read_identity_path() -> anyhow::Result<String> {
+    Ok(Text::new("Which identity file should be used to decrypt the video ?")
+        .with_default("identity.key")
+        .prompt()?)
 }
 
 /// Enriches the parameters for the Download command by prompting the user for missing values.
@@ -170,16 +304,40 @@ async fn enrich_dislodge_params(mut args: DislodgeParams) -> anyhow::Result<Disl
     if args.in_path.is_none() {
         // Prompt user for input video path
         let in_path = Text::new("What is the path to your video ?")
-            .with_default("output.avi")
+            .with_default(crate::video::DEFAULT_OUTPUT)
             .prompt()
             .unwrap();
         args.in_path = Some(in_path);
     }
 
-    if args.out_path.is_none() {
-        // Prompt user for output file path
+    if args.out_path.is_none() && args.out_dir.is_none() {
+        // Prompt user for output file path, or fall back to the original file name
         let out_path = Text::new("Where should the output go ?")
-            .with_help_message("Please include name of file and extension")
+            .with_help_message("Please include name of file and extension, or leave empty to restore the original file name")
+            .prompt()
+            .unwrap();
+
+        if out_path.is_empty() {
+            let out_dir = Text::new("Which directory should the file be restored into ?")
+                .with_default(".")
+                .prompt()
+                .unwrap();
+            args.out_dir = Some(out_dir);
+        } else {
+            args.out_path = Some(out_path);
+        }
+    }
+
+    Ok(args)
+}
+
+/// Enriches the parameters for the Keygen command by prompting the user for missing values.
+async fn enrich_keygen_params(mut args: KeygenParams) -> anyhow::Result<KeygenParams> {
+    if args.out_path.is_none() {
+        // Prompt user for the key file to create
+        let out_path = Text::new("Where should the key be saved ?")
+            .with_default(if args.signing { "signing.key" } else { "identity.key" })
+            .with_help_message("Keep this file secret, share only the public key printed afterwards")
             .prompt()
             .unwrap();
         args.out_path = Some(out_path);
@@ -0,0 +1,466 @@
+use std::path::{Path, MAIN_SEPARATOR};
+
+use anyhow::bail;
+
+mod ffmpeg;
+mod images;
+#[cfg(feature = "opencv")]
+mod opencv;
+mod y4m;
+
+/// Path embedded videos are written to when none is given.
+#[cfg(feature = "opencv")]
+pub const DEFAULT_OUTPUT: &str = "output.avi";
+
+/// Path embedded videos are written to when none is given. Builds without OpenCV write
+/// Y4M by default, which needs no other program.
+#[cfg(not(feature = "opencv"))]
+pub const DEFAULT_OUTPUT: &str = "output.y4m";
+
+/// Backend videos go through when none is asked for and neither a directory nor a Y4M
+/// file is named.
+#[cfg(feature = "opencv")]
+const DEFAULT_BACKEND: Backend = Backend::OpenCv;
+
+/// Backend videos go through when none is asked for and neither a directory nor a Y4M
+/// file is named. Builds without OpenCV hand them to ffmpeg.
+#[cfg(not(feature = "opencv"))]
+const DEFAULT_BACKEND: Backend = Backend::Ffmpeg;
+
+/// A video frame as the backends exchange it: 8-bit BGR pixels, row after row with no padding.
+#[derive(Clone)]
+pub struct Frame {
+    pub width: i32,
+    pub height: i32,
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    /// A black frame of `width` x `height` pixels.
+    pub fn new(width: i32, height: i32) -> Frame {
+        Frame {
+            width,
+            height,
+            data: vec![0; width as usize * height as usize * 3],
+        }
+    }
+}
+
+/// Receives the frames of an embedded video, in order.
+pub trait FrameSink {
+    /// Appends a frame to the video.
+    fn write_frame(&mut self, frame: &Frame) -> anyhow::Result<()>;
+
+    /// Completes the video, once every frame was written.
+    fn finish(self: Box<Self>) -> anyhow::Result<()>;
+}
+
+/// Yields the frames of a video, in order. Sources are read from a thread of their own.
+pub trait FrameSource: Send {
+    /// Reads the next frame.
+    ///
+    /// # Returns
+    /// The frame, or `None` once the video ended.
+    fn read_frame(&mut self) -> anyhow::Result<Option<Frame>>;
+}
+
+/// Libraries videos can be read and written with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    /// OpenCV's `VideoCapture` and `VideoWriter`.
+    OpenCv,
+    /// A local ffmpeg process, exchanging raw BGR frames through a pipe.
+    Ffmpeg,
+}
+
+/// How an embedded video is encoded.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Encoder {
+    /// OpenCV's `VideoWriter`, with one of the codecs it was asked for.
+    OpenCv(Codec),
+    /// ffmpeg, with these encoder arguments placed between its input and the output path.
+    Ffmpeg(Vec<String>),
+    /// A Y4M file, written directly.
+    Y4m,
+    /// A directory of numbered PNG frames, written directly.
+    Images,
+}
+
+/// Codecs embedded videos can be written with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Codec {
+    /// Lossless PNG frames.
+    Png,
+    /// FFV1, lossless and smaller than PNG.
+    Ffv1,
+    /// Motion JPEG, lossy.
+    Mjpg,
+    /// H.264, lossy.
+    Avc1,
+    /// MPEG-4 Part 2, lossy.
+    Mp4v,
+    /// Uncompressed frames.
+    Raw,
+}
+
+impl Codec {
+    /// Name of the codec, as given to `--codec`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::Png => "png",
+            Codec::Ffv1 => "ffv1",
+            Codec::Mjpg => "mjpg",
+            Codec::Avc1 => "avc1",
+            Codec::Mp4v => "mp4v",
+            Codec::Raw => "raw",
+        }
+    }
+
+    /// Whether frames are read back exactly as they were written.
+    pub fn is_lossless(&self) -> bool {
+        matches!(self, Codec::Png | Codec::Ffv1 | Codec::Raw)
+    }
+
+    /// Containers the codec can be stored in.
+    fn containers(&self) -> &'static [Container] {
+        match self {
+            Codec::Png | Codec::Ffv1 => &[Container::Avi, Container::Mkv],
+            Codec::Raw => &[Container::Avi, Container::Mkv, Container::Y4m],
+            Codec::Mjpg => &[Container::Avi, Container::Mkv, Container::Mp4],
+            Codec::Avc1 | Codec::Mp4v => &[Container::Mkv, Container::Mp4],
+        }
+    }
+
+    /// ffmpeg encoder arguments matching the codec, used when none are given.
+    fn ffmpeg_args(&self) -> &'static [&'static str] {
+        match self {
+            Codec::Png => &["-c:v", "png"],
+            Codec::Ffv1 => &["-c:v", "ffv1"],
+            Codec::Mjpg => &["-c:v", "mjpeg", "-q:v", "2"],
+            Codec::Avc1 => &["-c:v", "libx264", "-pix_fmt", "yuv420p"],
+            Codec::Mp4v => &["-c:v", "mpeg4", "-q:v", "2"],
+            Codec::Raw => &["-c:v", "rawvideo"],
+        }
+    }
+}
+
+/// Containers embedded videos can be written in, chosen from the output's extension.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Container {
+    Avi,
+    Mkv,
+    Mp4,
+    Y4m,
+}
+
+impl Container {
+    /// Picks the container from the extension of `path`.
+    pub fn from_path(path: &str) -> anyhow::Result<Container> {
+        match extension(path).as_str() {
+            "avi" => Ok(Container::Avi),
+            "mkv" => Ok(Container::Mkv),
+            "mp4" => Ok(Container::Mp4),
+            "y4m" => Ok(Container::Y4m),
+            _ => bail!(
+                "Can't tell the container of {}: the output must end in .avi, .mkv, .mp4 or .y4m, or be a directory",
+                path
+            ),
+        }
+    }
+
+    /// The extension of the container.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Container::Avi => "avi",
+            Container::Mkv => "mkv",
+            Container::Mp4 => "mp4",
+            Container::Y4m => "y4m",
+        }
+    }
+
+    /// Codec used when none is given: the container's lossless codec, or H.264 for MP4,
+    /// which has none.
+    pub fn default_codec(&self) -> Codec {
+        match self {
+            Container::Avi => Codec::Png,
+            Container::Mkv => Codec::Ffv1,
+            Container::Mp4 => Codec::Avc1,
+            Container::Y4m => Codec::Raw,
+        }
+    }
+}
+
+/// The extension of `path`, in lower case.
+fn extension(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+/// Whether `path` names a directory, existing or to be created, rather than a file.
+fn is_directory(path: &str) -> bool {
+    path.ends_with('/') || path.ends_with(MAIN_SEPARATOR) || Path::new(path).is_dir()
+}
+
+/// Settles the codec an embedded video is written with, checking it fits the container
+/// before any work is done.
+///
+/// # Arguments
+/// * `path` - Path of the output video, whose extension picks the container.
+/// * `codec` - The codec asked for, or `None` for the container's default.
+///
+/// # Returns
+/// The codec to write the video with, or an error if the container can't hold it.
+pub fn select_codec(path: &str, codec: Option<Codec>) -> anyhow::Result<Codec> {
+    let container = Container::from_path(path)?;
+    let codec = codec.unwrap_or_else(|| container.default_codec());
+    if !codec.containers().contains(&container) {
+        let supported: Vec<&str> = codec.containers().iter().map(|c| c.name()).collect();
+        bail!(
+            "The {} codec can't be stored in .{} files; use .{} instead or pick another codec",
+            codec.name(),
+            container.name(),
+            supported.join(" or .")
+        );
+    }
+
+    if !codec.is_lossless() {
+        println!(
+            "Warning: {} is lossy, prefer binary mode, larger blocks and error correction so the data survives it",
+            codec.name()
+        );
+    }
+
+    Ok(codec)
+}
+
+/// Settles how an embedded video is encoded, checking the codec fits the container
+/// before any work is done.
+///
+/// Encoder arguments given for ffmpeg are passed on as they are, so they are free to
+/// pick any codec, pixel format, quality or container ffmpeg supports. Otherwise a
+/// directory gets a PNG image sequence, a `.y4m` file is written directly and other
+/// videos go through the chosen backend.
+///
+/// # Arguments
+/// * `path` - Path of the output video.
+/// * `codec` - The codec asked for, or `None` for the container's default.
+/// * `backend` - The backend asked for, or `None` to pick it from `ffmpeg_args`.
+/// * `ffmpeg_args` - Encoder arguments for ffmpeg, separated by whitespace.
+///
+/// # Returns
+/// The encoder to write the video with, or an error if the options don't fit together.
+pub fn select_encoder(
+    path: &str,
+    codec: Option<Codec>,
+    backend: Option<Backend>,
+    ffmpeg_args: Option<&str>,
+) -> anyhow::Result<Encoder> {
+    #[cfg(not(feature = "opencv"))]
+    if backend == Some(Backend::OpenCv) {
+        return Err(missing_opencv());
+    }
+
+    if let Some(args) = ffmpeg_args {
+        if backend == Some(Backend::OpenCv) {
+            bail!("--ffmpeg-args needs the ffmpeg backend, OpenCV can't take encoder arguments");
+        }
+        if codec.is_some() {
+            bail!("--codec can't be combined with --ffmpeg-args, pick the encoder in the ffmpeg arguments instead");
+        }
+        return Ok(Encoder::Ffmpeg(
+            args.split_whitespace().map(str::to_string).collect(),
+        ));
+    }
+
+    if is_directory(path) {
+        if backend.is_some() || codec.is_some_and(|codec| codec != Codec::Png) {
+            bail!("Image sequences are always written as PNG frames, drop --codec and --backend");
+        }
+        return Ok(Encoder::Images);
+    }
+
+    let codec = select_codec(path, codec)?;
+    match (Container::from_path(path)?, backend) {
+        (Container::Y4m, None) => Ok(Encoder::Y4m),
+        (Container::Y4m, Some(_)) => bail!("Y4M files are written directly, drop --backend"),
+        (_, backend) => match backend.unwrap_or(DEFAULT_BACKEND) {
+            Backend::OpenCv => Ok(Encoder::OpenCv(codec)),
+            Backend::Ffmpeg => Ok(Encoder::Ffmpeg(
+                codec
+                    .ffmpeg_args()
+                    .iter()
+                    .map(|arg| arg.to_string())
+                    .collect(),
+            )),
+        },
+    }
+}
+
+/// Opens a video for writing with the given encoder.
+///
+/// # Arguments
+/// * `path` - Path of the video to create.
+/// * `encoder` - How the frames are encoded, as settled by `select_encoder`.
+/// * `fps` - Frame rate of the video.
+/// * `width` - Width of every frame.
+/// * `height` - Height of every frame.
+///
+/// # Returns
+/// The sink to write the frames to, or an error if the encoder can't be started.
+pub fn open_sink(
+    path: &str,
+    encoder: &Encoder,
+    fps: f64,
+    width: i32,
+    height: i32,
+) -> anyhow::Result<Box<dyn FrameSink>> {
+    Ok(match encoder {
+        #[cfg(feature = "opencv")]
+        Encoder::OpenCv(codec) => {
+            Box::new(opencv::OpenCvSink::new(path, *codec, fps, width, height)?)
+        }
+        #[cfg(not(feature = "opencv"))]
+        Encoder::OpenCv(_) => return Err(missing_opencv()),
+        Encoder::Ffmpeg(args) => Box::new(ffmpeg::FfmpegSink::new(path, args, fps, width, height)?),
+        Encoder::Y4m => Box::new(y4m::Y4mSink::new(path, fps, width, height)?),
+        Encoder::Images => Box::new(images::ImageSink::new(path)?),
+    })
+}
+
+/// Opens a video for reading.
+///
+/// Directories are read as image sequences and `.y4m` files directly, unless a backend
+/// is asked for; other videos are decoded with OpenCV by default, or with ffmpeg in
+/// builds without OpenCV.
+///
+/// # Arguments
+/// * `path` - Path of the video.
+/// * `backend` - The backend decoding the video, or `None` to pick it from the path.
+///
+/// # Returns
+/// The source to read the frames from, or an error if the video can't be opened.
+pub fn open_source(path: &str, backend: Option<Backend>) -> anyhow::Result<Box<dyn FrameSource>> {
+    let backend = match backend {
+        Some(backend) => backend,
+        None if Path::new(path).is_dir() => return Ok(Box::new(images::ImageSource::new(path)?)),
+        None if extension(path) == "y4m" => return Ok(Box::new(y4m::Y4mSource::new(path)?)),
+        None => DEFAULT_BACKEND,
+    };
+    Ok(match backend {
+        #[cfg(feature = "opencv")]
+        Backend::OpenCv => Box::new(opencv::OpenCvSource::new(path)?),
+        #[cfg(not(feature = "opencv"))]
+        Backend::OpenCv => return Err(missing_opencv()),
+        Backend::Ffmpeg => Box::new(ffmpeg::FfmpegSource::new(path)?),
+    })
+}
+
+/// The error reported when the OpenCV backend is asked for in a build without it.
+#[cfg(not(feature = "opencv"))]
+fn missing_opencv() -> anyhow::Error {
+    anyhow::anyhow!(
+        "This build has no OpenCV support: rebuild with the opencv feature, or use --backend ffmpeg, a .y4m file or a directory of frames"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ffmpeg's encoder arguments for `codec`, as owned strings.
+    fn ffmpeg(codec: Codec) -> Encoder {
+        Encoder::Ffmpeg(
+            codec
+                .ffmpeg_args()
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn containers_default_to_their_codec() {
+        let cases = [
+            ("out.avi", Codec::Png),
+            ("out.mkv", Codec::Ffv1),
+            ("out.mp4", Codec::Avc1),
+            ("out.y4m", Codec::Raw),
+            ("OUT.MKV", Codec::Ffv1),
+        ];
+        for (path, codec) in cases {
+            assert_eq!(select_codec(path, None).unwrap(), codec, "{}", path);
+        }
+    }
+
+    #[test]
+    fn codecs_are_checked_against_the_container() {
+        let cases = [
+            ("out.avi", Codec::Mjpg, true),
+            ("out.mkv", Codec::Avc1, true),
+            ("out.mp4", Codec::Mp4v, true),
+            ("out.y4m", Codec::Raw, true),
+            ("out.mp4", Codec::Ffv1, false),
+            ("out.avi", Codec::Avc1, false),
+            ("out.y4m", Codec::Png, false),
+        ];
+        for (path, codec, fits) in cases {
+            let selected = select_codec(path, Some(codec));
+            assert_eq!(selected.is_ok(), fits, "{} in {}", codec.name(), path);
+        }
+    }
+
+    #[test]
+    fn unknown_extensions_are_rejected() {
+        for path in ["out.webm", "out", "out.avi.txt"] {
+            assert!(select_codec(path, None).is_err(), "{}", path);
+            assert!(
+                select_encoder(path, None, Some(Backend::Ffmpeg), None).is_err(),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn encoders_are_settled_from_the_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let directory = dir.path().to_str().unwrap();
+        let (png, ffv1) = (Some(Codec::Png), Some(Codec::Ffv1));
+        let ffmpeg_backend = Some(Backend::Ffmpeg);
+        let images = Some(Encoder::Images);
+        let vp9 = Some("-c:v libvpx-vp9  -lossless 1");
+        let vp9_encoder = Some(Encoder::Ffmpeg(
+            ["-c:v", "libvpx-vp9", "-lossless", "1"]
+                .map(String::from)
+                .to_vec(),
+        ));
+        let ffv1_encoder = Some(ffmpeg(Codec::Ffv1));
+
+        // Path, codec, backend and ffmpeg arguments, then the encoder or `None` for an error
+        let cases = [
+            ("out.y4m", None, None, None, Some(Encoder::Y4m)),
+            ("out.y4m", None, ffmpeg_backend, None, None),
+            (directory, None, None, None, images.clone()),
+            ("frames/", png, None, None, images),
+            ("frames/", ffv1, None, None, None),
+            ("frames/", None, ffmpeg_backend, None, None),
+            ("out.mkv", None, ffmpeg_backend, None, ffv1_encoder),
+            ("out.mp4", png, ffmpeg_backend, None, None),
+            ("out.webm", None, None, vp9, vp9_encoder),
+            ("out.mkv", ffv1, None, Some("-c:v ffv1"), None),
+        ];
+        for (path, codec, backend, ffmpeg_args, expected) in cases {
+            let selected = select_encoder(path, codec, backend, ffmpeg_args).ok();
+            assert_eq!(selected, expected, "{}", path);
+        }
+    }
+
+    #[test]
+    fn ffmpeg_arguments_need_the_ffmpeg_backend() {
+        let selected = select_encoder("out.mkv", None, Some(Backend::OpenCv), Some("-c:v ffv1"));
+        assert!(selected.is_err());
+    }
+}
@@ -0,0 +1,295 @@
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use anyhow::{anyhow, bail};
+
+use super::{Frame, FrameSink, FrameSource};
+
+/// Programs run by the ffmpeg backend, looked up on the `PATH`.
+const FFMPEG: &str = "ffmpeg";
+const FFPROBE: &str = "ffprobe";
+
+/// Encodes a video with a local ffmpeg process, feeding it raw BGR frames on its
+/// standard input. ffmpeg reports its own errors on the terminal.
+pub struct FfmpegSink {
+    child: Child,
+    /// ffmpeg's standard input, until the video is finished.
+    input: Option<BufWriter<ChildStdin>>,
+}
+
+impl FfmpegSink {
+    /// Starts ffmpeg, encoding the frames it is fed into `path` with `args`.
+    pub fn new(
+        path: &str,
+        args: &[String],
+        fps: f64,
+        width: i32,
+        height: i32,
+    ) -> anyhow::Result<FfmpegSink> {
+        let mut child = Command::new(FFMPEG)
+            .args(encode_args(path, args, fps, width, height))
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| run_error(FFMPEG, e))?;
+        let input = BufWriter::new(child.stdin.take().expect("stdin is piped"));
+
+        Ok(FfmpegSink {
+            child,
+            input: Some(input),
+        })
+    }
+}
+
+impl FrameSink for FfmpegSink {
+    fn write_frame(&mut self, frame: &Frame) -> anyhow::Result<()> {
+        let input = self
+            .input
+            .as_mut()
+            .expect("frames are only written before finishing");
+        if let Err(e) = input.write_all(&frame.data) {
+            // ffmpeg stopped reading, its exit status tells why
+            let status = self.child.wait()?;
+            bail!("{} stopped encoding the video ({}): {}", FFMPEG, status, e);
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> anyhow::Result<()> {
+        // Closing the input tells ffmpeg the video is complete
+        if let Some(mut input) = self.input.take() {
+            input.flush()?;
+        }
+        let status = self.child.wait()?;
+        if !status.success() {
+            bail!("{} failed to encode the video ({})", FFMPEG, status);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for FfmpegSink {
+    fn drop(&mut self) {
+        // Embedding failed before the video was finished: close the input without
+        // flushing what is left of it, and don't leave ffmpeg behind
+        if let Some(input) = self.input.take() {
+            drop(input.into_parts().0);
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// Decodes a video with a local ffmpeg process, which writes raw BGR frames to its
+/// standard output.
+pub struct FfmpegSource {
+    child: Child,
+    output: BufReader<ChildStdout>,
+    width: i32,
+    height: i32,
+}
+
+impl FfmpegSource {
+    /// Probes the frame size of the video at `path` and starts decoding it.
+    pub fn new(path: &str) -> anyhow::Result<FfmpegSource> {
+        let (width, height) = probe_frame_size(path)?;
+        let mut child = Command::new(FFMPEG)
+            .args(decode_args(path))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| run_error(FFMPEG, e))?;
+        let output = BufReader::new(child.stdout.take().expect("stdout is piped"));
+
+        Ok(FfmpegSource {
+            child,
+            output,
+            width,
+            height,
+        })
+    }
+}
+
+impl FrameSource for FfmpegSource {
+    fn read_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        let mut frame = Frame::new(self.width, self.height);
+        match self.output.read_exact(&mut frame.data) {
+            Ok(()) => Ok(Some(frame)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                let status = self.child.wait()?;
+                if !status.success() {
+                    bail!("{} failed to decode the video ({})", FFMPEG, status);
+                }
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Drop for FfmpegSource {
+    fn drop(&mut self) {
+        // Reading may stop before the end of the video, don't leave ffmpeg behind
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Arguments making ffmpeg encode raw BGR frames of `width` x `height` pixels from its
+/// standard input into `path`, with the encoder arguments `args`.
+fn encode_args(path: &str, args: &[String], fps: f64, width: i32, height: i32) -> Vec<String> {
+    let size = format!("{}x{}", width, height);
+    let fps = fps.to_string();
+    ["-hide_banner", "-loglevel", "error", "-y"]
+        .into_iter()
+        .chain(["-f", "rawvideo", "-pix_fmt", "bgr24"])
+        .chain(["-s", &size, "-r", &fps, "-i", "-"])
+        .map(str::to_string)
+        .chain(args.iter().cloned())
+        .chain([path.to_string()])
+        .collect()
+}
+
+/// Arguments making ffmpeg decode the first video stream of `path` into raw BGR frames
+/// on its standard output.
+fn decode_args(path: &str) -> Vec<String> {
+    ["-hide_banner", "-loglevel", "error", "-i", path]
+        .into_iter()
+        .chain(["-map", "0:v:0", "-f", "rawvideo", "-pix_fmt", "bgr24", "-"])
+        .map(str::to_string)
+        .collect()
+}
+
+/// Arguments making ffprobe print the frame size of the first video stream of `path`,
+/// as `<width>x<height>`.
+fn probe_args(path: &str) -> Vec<String> {
+    ["-v", "error", "-select_streams", "v:0"]
+        .into_iter()
+        .chain([
+            "-show_entries",
+            "stream=width,height",
+            "-of",
+            "csv=p=0:s=x",
+            path,
+        ])
+        .map(str::to_string)
+        .collect()
+}
+
+/// Reads the frame size printed by ffprobe with `probe_args`.
+fn parse_frame_size(probed: &str) -> Option<(i32, i32)> {
+    let (width, height) = probed.trim().split_once('x')?;
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+/// Asks ffprobe for the frame size of the first video stream of `path`.
+fn probe_frame_size(path: &str) -> anyhow::Result<(i32, i32)> {
+    let output = Command::new(FFPROBE)
+        .args(probe_args(path))
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| run_error(FFPROBE, e))?;
+    if !output.status.success() {
+        bail!("{} could not read {} ({})", FFPROBE, path, output.status);
+    }
+
+    parse_frame_size(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| anyhow!("{} found no video stream in {}", FFPROBE, path))
+}
+
+/// Explains why `program` could not be started.
+fn run_error(program: &str, error: io::Error) -> anyhow::Error {
+    anyhow!(
+        "Could not run {}, is it installed and on the PATH? ({})",
+        program,
+        error
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::video::Codec;
+
+    /// Whether ffmpeg and ffprobe can be run, so the tests using them can skip otherwise.
+    fn ffmpeg_available() -> bool {
+        [FFMPEG, FFPROBE].iter().all(|program| {
+            Command::new(program)
+                .arg("-version")
+                .stdout(Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success())
+        })
+    }
+
+    #[test]
+    fn encoding_feeds_raw_frames_before_the_encoder_arguments() {
+        let args = ["-c:v", "libx264", "-crf", "0"].map(String::from);
+        let command = encode_args("out.mkv", &args, 29.97, 640, 360);
+        assert_eq!(
+            command.join(" "),
+            "-hide_banner -loglevel error -y -f rawvideo -pix_fmt bgr24 -s 640x360 \
+             -r 29.97 -i - -c:v libx264 -crf 0 out.mkv"
+        );
+    }
+
+    #[test]
+    fn paths_are_passed_as_single_arguments() {
+        let path = "my videos/out -y.mkv";
+        assert_eq!(encode_args(path, &[], 10.0, 2, 2).last().unwrap(), path);
+        assert!(decode_args(path).iter().any(|arg| arg == path));
+        assert_eq!(probe_args(path).last().unwrap(), path);
+    }
+
+    #[test]
+    fn decoding_writes_raw_frames_of_the_first_video_stream() {
+        assert_eq!(
+            decode_args("in.mp4").join(" "),
+            "-hide_banner -loglevel error -i in.mp4 -map 0:v:0 -f rawvideo -pix_fmt bgr24 -"
+        );
+    }
+
+    #[test]
+    fn probed_frame_sizes_are_parsed() {
+        assert_eq!(parse_frame_size("1920x1080\n"), Some((1920, 1080)));
+        assert_eq!(parse_frame_size(""), None);
+        assert_eq!(parse_frame_size("1920x"), None);
+        assert_eq!(parse_frame_size("N/AxN/A"), None);
+    }
+
+    #[test]
+    fn lossless_videos_round_trip_through_ffmpeg() {
+        if !ffmpeg_available() {
+            eprintln!("Skipping the ffmpeg round trip, ffmpeg isn't installed");
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("video.mkv").to_str().unwrap().to_string();
+        let frames: Vec<Frame> = (0..3u8)
+            .map(|index| {
+                let mut frame = Frame::new(64, 48);
+                for (i, value) in frame.data.iter_mut().enumerate() {
+                    *value = (i * 7 + index as usize * 31) as u8;
+                }
+                frame
+            })
+            .collect();
+        let args: Vec<String> = Codec::Ffv1
+            .ffmpeg_args()
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let mut sink = Box::new(FfmpegSink::new(&path, &args, 10.0, 64, 48).unwrap());
+        for frame in &frames {
+            sink.write_frame(frame).unwrap();
+        }
+        sink.finish().unwrap();
+
+        let mut source = FfmpegSource::new(&path).unwrap();
+        for frame in &frames {
+            let read = source.read_frame().unwrap().unwrap();
+            assert!(read.data == frame.data);
+        }
+        assert!(source.read_frame().unwrap().is_none());
+    }
+}
@@ -0,0 +1,197 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+
+use super::{Frame, FrameSink, FrameSource};
+
+/// Writes every frame to a PNG file of its own, numbered from zero, in a directory.
+pub struct ImageSink {
+    dir: PathBuf,
+    index: usize,
+}
+
+impl ImageSink {
+    /// Creates the directory at `path`, refusing one that already holds frames, which
+    /// would get mixed up with the new ones.
+    pub fn new(path: &str) -> anyhow::Result<ImageSink> {
+        let dir = PathBuf::from(path);
+        fs::create_dir_all(&dir)?;
+        if !frame_paths(&dir)?.is_empty() {
+            bail!(
+                "{} already holds PNG frames, pick an empty directory",
+                dir.display()
+            );
+        }
+
+        Ok(ImageSink { dir, index: 0 })
+    }
+}
+
+impl FrameSink for ImageSink {
+    fn write_frame(&mut self, frame: &Frame) -> anyhow::Result<()> {
+        let path = self.dir.join(format!("{:06}.png", self.index));
+        self.index += 1;
+
+        let mut encoder = png::Encoder::new(
+            BufWriter::new(File::create(path)?),
+            frame.width as u32,
+            frame.height as u32,
+        );
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_compression(png::Compression::Fast);
+
+        // PNG stores RGB, frames are BGR
+        let mut rgb = frame.data.clone();
+        for pixel in rgb.chunks_exact_mut(3) {
+            pixel.swap(0, 2);
+        }
+        encoder.write_header()?.write_image_data(&rgb)?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads the PNG files of a directory as frames, in the order of their names.
+pub struct ImageSource {
+    paths: std::vec::IntoIter<PathBuf>,
+}
+
+impl ImageSource {
+    /// Lists the PNG files in the directory at `path`.
+    pub fn new(path: &str) -> anyhow::Result<ImageSource> {
+        let paths = frame_paths(Path::new(path))?;
+        if paths.is_empty() {
+            bail!("{} holds no PNG frames", path);
+        }
+
+        Ok(ImageSource {
+            paths: paths.into_iter(),
+        })
+    }
+}
+
+impl FrameSource for ImageSource {
+    fn read_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        let Some(path) = self.paths.next() else {
+            return Ok(None);
+        };
+
+        // Palettes and 16-bit samples are turned into plain 8-bit samples
+        let mut decoder = png::Decoder::new(BufReader::new(File::open(&path)?));
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info()?;
+        let mut samples = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut samples)?;
+
+        let mut frame = Frame::new(info.width as i32, info.height as i32);
+        let channels = info.color_type.samples();
+        for (row, pixels) in frame
+            .data
+            .chunks_exact_mut(info.width as usize * 3)
+            .enumerate()
+        {
+            let line = &samples[row * info.line_size..];
+            for (x, pixel) in pixels.chunks_exact_mut(3).enumerate() {
+                let sample = &line[x * channels..];
+                let [r, g, b] = match info.color_type {
+                    png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha => [sample[0]; 3],
+                    _ => [sample[0], sample[1], sample[2]],
+                };
+                pixel.copy_from_slice(&[b, g, r]);
+            }
+        }
+
+        Ok(Some(frame))
+    }
+}
+
+/// The PNG files in `dir`, sorted by name.
+fn frame_paths(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_png = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("png"));
+        if is_png && path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A frame whose every sample is different from its neighbours'.
+    fn pattern(width: i32, height: i32) -> Frame {
+        let mut frame = Frame::new(width, height);
+        for (i, value) in frame.data.iter_mut().enumerate() {
+            *value = (i * 37 + i / 11) as u8;
+        }
+        frame
+    }
+
+    #[test]
+    fn sink_and_source_round_trip_losslessly() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("frames");
+        let path = path.to_str().unwrap();
+        let frames = [pattern(33, 17), pattern(33, 17), Frame::new(33, 17)];
+
+        let mut sink = Box::new(ImageSink::new(path).unwrap());
+        for frame in &frames {
+            sink.write_frame(frame).unwrap();
+        }
+        sink.finish().unwrap();
+
+        let mut source = ImageSource::new(path).unwrap();
+        for frame in &frames {
+            let read = source.read_frame().unwrap().unwrap();
+            assert_eq!((read.width, read.height), (33, 17));
+            assert!(read.data == frame.data);
+        }
+        assert!(source.read_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn frames_are_read_in_the_order_of_their_names() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("notes.txt"), "not a frame").unwrap();
+        for (name, luma) in [("000002.png", 2u8), ("000000.png", 0), ("000001.png", 1)] {
+            let mut encoder = png::Encoder::new(File::create(dir.path().join(name)).unwrap(), 2, 1);
+            encoder.set_color(png::ColorType::Grayscale);
+            encoder
+                .write_header()
+                .unwrap()
+                .write_image_data(&[luma; 2])
+                .unwrap();
+        }
+
+        // Gray frames come back with the level in every channel
+        let mut source = ImageSource::new(dir.path().to_str().unwrap()).unwrap();
+        for luma in 0..3 {
+            assert_eq!(source.read_frame().unwrap().unwrap().data, [luma; 6]);
+        }
+        assert!(source.read_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn directories_holding_frames_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+        assert!(ImageSource::new(path).is_err());
+
+        let mut sink = Box::new(ImageSink::new(path).unwrap());
+        sink.write_frame(&pattern(4, 4)).unwrap();
+        assert!(ImageSink::new(path).is_err());
+    }
+}
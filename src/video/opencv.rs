@@ -0,0 +1,114 @@
+use anyhow::anyhow;
+use opencv::core::{Mat, Scalar, Size, CV_8UC3};
+use opencv::prelude::*;
+use opencv::videoio::{VideoCapture, VideoWriter, CAP_ANY};
+
+use super::{Codec, Frame, FrameSink, FrameSource};
+
+impl Frame {
+    /// Copies an 8-bit BGR image into a frame.
+    pub fn from_mat(image: &Mat) -> anyhow::Result<Frame> {
+        // Only images in one piece can be copied as a whole
+        let continuous;
+        let image = if image.is_continuous() {
+            image
+        } else {
+            continuous = image.try_clone()?;
+            &continuous
+        };
+
+        Ok(Frame {
+            width: image.cols(),
+            height: image.rows(),
+            data: image.data_bytes()?.to_vec(),
+        })
+    }
+
+    /// Copies the frame into an 8-bit BGR image.
+    pub fn to_mat(&self) -> anyhow::Result<Mat> {
+        let mut image =
+            Mat::new_rows_cols_with_default(self.height, self.width, CV_8UC3, Scalar::all(0.0))?;
+        image.data_bytes_mut()?.copy_from_slice(&self.data);
+        Ok(image)
+    }
+}
+
+impl Codec {
+    /// FourCC handed to OpenCV. Raw frames use 0, which OpenCV writes uncompressed.
+    fn fourcc(&self) -> anyhow::Result<i32> {
+        Ok(match self {
+            Codec::Png => VideoWriter::fourcc('p', 'n', 'g', ' ')?,
+            Codec::Ffv1 => VideoWriter::fourcc('F', 'F', 'V', '1')?,
+            Codec::Mjpg => VideoWriter::fourcc('M', 'J', 'P', 'G')?,
+            Codec::Avc1 => VideoWriter::fourcc('a', 'v', 'c', '1')?,
+            Codec::Mp4v => VideoWriter::fourcc('m', 'p', '4', 'v')?,
+            Codec::Raw => 0,
+        })
+    }
+}
+
+/// Writes a video with OpenCV's `VideoWriter`.
+pub struct OpenCvSink {
+    writer: VideoWriter,
+}
+
+impl OpenCvSink {
+    /// Opens `path` for writing with the given codec.
+    pub fn new(
+        path: &str,
+        codec: Codec,
+        fps: f64,
+        width: i32,
+        height: i32,
+    ) -> anyhow::Result<OpenCvSink> {
+        let writer = VideoWriter::new(path, codec.fourcc()?, fps, Size::new(width, height), true)
+            .ok()
+            .filter(|writer| writer.is_opened().unwrap_or(false))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Could not write {} with the {} codec; this OpenCV build may not support it, try another --codec",
+                    path,
+                    codec.name()
+                )
+            })?;
+
+        Ok(OpenCvSink { writer })
+    }
+}
+
+impl FrameSink for OpenCvSink {
+    fn write_frame(&mut self, frame: &Frame) -> anyhow::Result<()> {
+        Ok(self.writer.write(&frame.to_mat()?)?)
+    }
+
+    fn finish(mut self: Box<Self>) -> anyhow::Result<()> {
+        Ok(self.writer.release()?)
+    }
+}
+
+/// Reads a video with OpenCV's `VideoCapture`.
+pub struct OpenCvSource {
+    capture: VideoCapture,
+}
+
+impl OpenCvSource {
+    /// Opens the video at `path`.
+    pub fn new(path: &str) -> anyhow::Result<OpenCvSource> {
+        let capture = VideoCapture::from_file(path, CAP_ANY)
+            .ok()
+            .filter(|capture| capture.is_opened().unwrap_or(false))
+            .ok_or_else(|| anyhow!("Could not open the video {}", path))?;
+
+        Ok(OpenCvSource { capture })
+    }
+}
+
+impl FrameSource for OpenCvSource {
+    fn read_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        let mut image = Mat::default();
+        if !self.capture.read(&mut image)? || image.cols() == 0 {
+            return Ok(None);
+        }
+        Ok(Some(Frame::from_mat(&image)?))
+    }
+}
@@ -0,0 +1,412 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+
+use anyhow::{anyhow, bail};
+
+use super::{Frame, FrameSink, FrameSource};
+
+/// Writes frames to a Y4M file as full range BT.601 YCbCr 4:4:4 with 10 bits per sample.
+///
+/// Ten bits leave enough precision for every 8-bit BGR color to come back exactly, so the
+/// file is lossless for this program while staying a standard Y4M file other tools can
+/// play or encode.
+pub struct Y4mSink {
+    output: BufWriter<File>,
+    width: i32,
+    height: i32,
+    planes: Vec<u8>,
+}
+
+impl Y4mSink {
+    /// Creates the file at `path` and writes its header.
+    pub fn new(path: &str, fps: f64, width: i32, height: i32) -> anyhow::Result<Y4mSink> {
+        if !fps.is_finite() || fps <= 0.0 {
+            bail!("Y4M files need a positive frame rate, got {}", fps);
+        }
+
+        let mut output = BufWriter::new(File::create(path)?);
+
+        // Frame rates are written as fractions, to a thousandth of a frame
+        let (numerator, denominator) = if fps.fract() == 0.0 {
+            (fps as u64, 1)
+        } else {
+            ((fps * 1000.0).round() as u64, 1000)
+        };
+        writeln!(
+            output,
+            "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444p10 XCOLORRANGE=FULL",
+            width, height, numerator, denominator
+        )?;
+
+        Ok(Y4mSink {
+            output,
+            width,
+            height,
+            planes: vec![0; width as usize * height as usize * 6],
+        })
+    }
+}
+
+impl FrameSink for Y4mSink {
+    fn write_frame(&mut self, frame: &Frame) -> anyhow::Result<()> {
+        if (frame.width, frame.height) != (self.width, self.height) {
+            bail!(
+                "Frame of {}x{} doesn't fit a {}x{} video",
+                frame.width,
+                frame.height,
+                self.width,
+                self.height
+            );
+        }
+
+        // Three planes of little-endian 16-bit samples, one after the other
+        let plane_len = frame.data.len() / 3 * 2;
+        let (luma, chroma) = self.planes.split_at_mut(plane_len);
+        let (blue, red) = chroma.split_at_mut(plane_len);
+        for (index, pixel) in frame.data.chunks_exact(3).enumerate() {
+            let (y, cb, cr) = to_ycbcr(pixel[2] as i32, pixel[1] as i32, pixel[0] as i32);
+            let at = index * 2;
+            luma[at..at + 2].copy_from_slice(&(y as u16).to_le_bytes());
+            blue[at..at + 2].copy_from_slice(&(cb as u16).to_le_bytes());
+            red[at..at + 2].copy_from_slice(&(cr as u16).to_le_bytes());
+        }
+
+        self.output.write_all(b"FRAME\n")?;
+        self.output.write_all(&self.planes)?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> anyhow::Result<()> {
+        Ok(self.output.flush()?)
+    }
+}
+
+/// Sample layouts a Y4M file can use, as named by its `C` parameter.
+#[derive(Clone, Copy, PartialEq)]
+enum Chroma {
+    /// Full resolution chroma.
+    Full,
+    /// Chroma subsampled by two in both directions.
+    Half,
+    /// Luma only.
+    Mono,
+}
+
+/// Reads frames from a Y4M file: 8-bit 4:2:0, 4:4:4 or mono, or 10-bit 4:4:4 as
+/// written by `Y4mSink`.
+pub struct Y4mSource {
+    input: BufReader<File>,
+    width: i32,
+    height: i32,
+    chroma: Chroma,
+    /// Bits per sample, 8 or 10.
+    depth: u32,
+    full_range: bool,
+    samples: Vec<u8>,
+}
+
+impl Y4mSource {
+    /// Opens the file at `path` and reads its header.
+    pub fn new(path: &str) -> anyhow::Result<Y4mSource> {
+        let mut input = BufReader::new(File::open(path)?);
+        let mut header = String::new();
+        input.read_line(&mut header)?;
+
+        let mut parameters = header.split_whitespace();
+        if parameters.next() != Some("YUV4MPEG2") {
+            bail!("{} is not a Y4M file", path);
+        }
+        let (mut width, mut height) = (0, 0);
+        let mut colorspace = "420jpeg";
+        let mut full_range = false;
+        for parameter in parameters {
+            // Every parameter starts with a single letter tag
+            let (tag, value) = match parameter.chars().next() {
+                Some(tag) if tag.is_ascii_alphabetic() => parameter.split_at(1),
+                _ => bail!(
+                    "{} has a malformed Y4M header parameter {:?}",
+                    path,
+                    parameter
+                ),
+            };
+            match tag {
+                "W" => width = value.parse()?,
+                "H" => height = value.parse()?,
+                "C" => colorspace = value,
+                "X" if value == "COLORRANGE=FULL" => full_range = true,
+                _ => {}
+            }
+        }
+        if width <= 0 || height <= 0 {
+            bail!("{} has no valid frame size in its Y4M header", path);
+        }
+
+        let (chroma, depth) = match colorspace {
+            "444p10" => (Chroma::Full, 10),
+            "444" => (Chroma::Full, 8),
+            "420" | "420jpeg" | "420mpeg2" | "420paldv" => (Chroma::Half, 8),
+            "mono" => (Chroma::Mono, 8),
+            _ => bail!("Y4M colorspace {} is not supported", colorspace),
+        };
+
+        let pixels = width as usize * height as usize;
+        let chroma_len = match chroma {
+            Chroma::Full => pixels,
+            Chroma::Half => (width as usize).div_ceil(2) * (height as usize).div_ceil(2),
+            Chroma::Mono => 0,
+        };
+        let sample_len = if depth > 8 { 2 } else { 1 };
+
+        Ok(Y4mSource {
+            input,
+            width,
+            height,
+            chroma,
+            depth,
+            full_range,
+            samples: vec![0; (pixels + chroma_len * 2) * sample_len],
+        })
+    }
+
+    /// The sample at `index` of the frame's samples, whatever their size.
+    fn sample(&self, index: usize) -> i32 {
+        if self.depth > 8 {
+            u16::from_le_bytes([self.samples[index * 2], self.samples[index * 2 + 1]]) as i32
+        } else {
+            self.samples[index] as i32
+        }
+    }
+}
+
+impl FrameSource for Y4mSource {
+    fn read_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        let mut marker = Vec::new();
+        if self.input.read_until(b'\n', &mut marker)? == 0 {
+            return Ok(None);
+        }
+        if !marker.starts_with(b"FRAME") {
+            return Err(anyhow!("Y4M frame is missing its FRAME marker"));
+        }
+        match self.input.read_exact(&mut self.samples) {
+            Ok(()) => {}
+            // A frame cut short ends the video
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        let (width, height) = (self.width as usize, self.height as usize);
+        let pixels = width * height;
+        let chroma_width = match self.chroma {
+            Chroma::Full => width,
+            _ => width.div_ceil(2),
+        };
+        let chroma_len = match self.chroma {
+            Chroma::Full => pixels,
+            Chroma::Half => chroma_width * height.div_ceil(2),
+            Chroma::Mono => 0,
+        };
+
+        let mut frame = Frame::new(self.width, self.height);
+        for y in 0..height {
+            for x in 0..width {
+                let index = y * width + x;
+                let luma = self.sample(index);
+                let (cb, cr) = match self.chroma {
+                    Chroma::Mono => (None, None),
+                    chroma => {
+                        let at = match chroma {
+                            Chroma::Full => index,
+                            _ => (y / 2) * chroma_width + x / 2,
+                        };
+                        (
+                            Some(self.sample(pixels + at)),
+                            Some(self.sample(pixels + chroma_len + at)),
+                        )
+                    }
+                };
+
+                let (r, g, b) = match (cb, cr) {
+                    // Exact inverse of what `Y4mSink` writes
+                    (Some(cb), Some(cr)) if self.depth == 10 && self.full_range => {
+                        to_rgb(luma, cb, cr)
+                    }
+                    _ => to_rgb_approximate(luma, cb, cr, self.depth, self.full_range),
+                };
+                frame.data[index * 3..index * 3 + 3].copy_from_slice(&[b, g, r]);
+            }
+        }
+
+        Ok(Some(frame))
+    }
+}
+
+/// Converts an 8-bit RGB color to full range BT.601 YCbCr with 10 bits per sample.
+fn to_ycbcr(r: i32, g: i32, b: i32) -> (i32, i32, i32) {
+    // Coefficients scaled by 4 (two more bits) and 2^16, rounded so every row sums exactly
+    let y = (78381 * r + 153879 * g + 29884 * b + 32768) >> 16;
+    let cb = 512 + ((-44234 * r - 86838 * g + 131072 * b + 32768) >> 16);
+    let cr = 512 + ((131072 * r - 109756 * g - 21316 * b + 32768) >> 16);
+    (y, cb, cr)
+}
+
+/// Converts full range 10-bit YCbCr back to 8-bit RGB. Colors from `to_ycbcr` come
+/// back exactly, as the rounding errors of both steps stay under half a step.
+fn to_rgb(y: i32, cb: i32, cr: i32) -> (u8, u8, u8) {
+    let (cb, cr) = (cb - 512, cr - 512);
+    let r = (16384 * y + 22970 * cr + 32768) >> 16;
+    let g = (16384 * y - 5638 * cb - 11700 * cr + 32768) >> 16;
+    let b = (16384 * y + 29032 * cb + 32768) >> 16;
+    (
+        r.clamp(0, 255) as u8,
+        g.clamp(0, 255) as u8,
+        b.clamp(0, 255) as u8,
+    )
+}
+
+/// Converts BT.601 YCbCr of any depth and range to 8-bit RGB, for files written elsewhere.
+/// Mono files have no chroma.
+fn to_rgb_approximate(
+    y: i32,
+    cb: Option<i32>,
+    cr: Option<i32>,
+    depth: u32,
+    full_range: bool,
+) -> (u8, u8, u8) {
+    let scale = (1 << (depth - 8)) as f32;
+    let (mut luma, mut cb, mut cr) = (
+        y as f32 / scale,
+        cb.map_or(128.0, |cb| cb as f32 / scale) - 128.0,
+        cr.map_or(128.0, |cr| cr as f32 / scale) - 128.0,
+    );
+    if !full_range {
+        luma = (luma - 16.0) * 255.0 / 219.0;
+        cb *= 255.0 / 224.0;
+        cr *= 255.0 / 224.0;
+    }
+
+    let channel = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+    (
+        channel(luma + 1.402 * cr),
+        channel(luma - 0.344136 * cb - 0.714136 * cr),
+        channel(luma + 1.772 * cb),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// A frame whose pixels run through many distinct colors.
+    fn gradient(width: i32, height: i32, seed: usize) -> Frame {
+        let mut frame = Frame::new(width, height);
+        for (index, value) in frame.data.iter_mut().enumerate() {
+            *value = (index * 37 + seed * 101 + index / 97) as u8;
+        }
+        frame
+    }
+
+    #[test]
+    fn every_color_survives_the_conversion() {
+        for r in 0..256 {
+            for g in 0..256 {
+                for b in 0..256 {
+                    let (y, cb, cr) = to_ycbcr(r, g, b);
+                    assert!([y, cb, cr].iter().all(|sample| (0..1024).contains(sample)));
+                    assert_eq!(to_rgb(y, cb, cr), (r as u8, g as u8, b as u8));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sink_and_source_round_trip_losslessly() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("video.y4m");
+        let path = path.to_str().unwrap();
+
+        // Odd dimensions and a fractional frame rate
+        let frames = [gradient(37, 23, 0), gradient(37, 23, 1)];
+        let mut sink = Box::new(Y4mSink::new(path, 29.97, 37, 23).unwrap());
+        for frame in &frames {
+            sink.write_frame(frame).unwrap();
+        }
+        sink.finish().unwrap();
+
+        assert!(fs::read(path)
+            .unwrap()
+            .starts_with(b"YUV4MPEG2 W37 H23 F29970:1000 Ip A1:1 C444p10 XCOLORRANGE=FULL\n"));
+        let mut source = Y4mSource::new(path).unwrap();
+        for frame in &frames {
+            let read = source.read_frame().unwrap().unwrap();
+            assert_eq!((read.width, read.height), (37, 23));
+            assert_eq!(read.data, frame.data);
+        }
+        assert!(source.read_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn frames_of_another_size_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("video.y4m");
+        let mut sink = Y4mSink::new(path.to_str().unwrap(), 30.0, 16, 16).unwrap();
+
+        assert!(sink.write_frame(&Frame::new(16, 8)).is_err());
+    }
+
+    #[test]
+    fn frame_rates_that_are_not_positive_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("video.y4m");
+        for fps in [0.0, -10.0, f64::NAN, f64::INFINITY] {
+            assert!(
+                Y4mSink::new(path.to_str().unwrap(), fps, 16, 16).is_err(),
+                "{}",
+                fps
+            );
+        }
+    }
+
+    #[test]
+    fn reads_8_bit_420_files_approximately() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("video.y4m");
+
+        // A 4x2 frame, white on the left and black on the right, in limited range
+        let mut file = b"YUV4MPEG2 W4 H2 F25:1 Ip A1:1 C420jpeg\nFRAME\n".to_vec();
+        file.extend([235, 235, 16, 16, 235, 235, 16, 16]);
+        file.extend([128, 128, 128, 128]);
+        fs::write(&path, file).unwrap();
+
+        let frame = Y4mSource::new(path.to_str().unwrap())
+            .unwrap()
+            .read_frame()
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.data[..3], [255, 255, 255]);
+        assert_eq!(frame.data[9..12], [0, 0, 0]);
+        assert_eq!(frame.data[12..15], [255, 255, 255]);
+    }
+
+    #[test]
+    fn malformed_headers_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("video.y4m");
+        for header in [
+            "MPEG4 W4 H4\n",
+            "YUV4MPEG2 W4\n",
+            "YUV4MPEG2 W4 H4 C422\n",
+            "YUV4MPEG2 W4 H4 Wx\n",
+            "YUV4MPEG2 W4 H4 \u{e9}4\n",
+            "YUV4MPEG2 W4 H4 4\n",
+        ] {
+            fs::write(&path, header).unwrap();
+            assert!(
+                Y4mSource::new(path.to_str().unwrap()).is_err(),
+                "{:?}",
+                header
+            );
+        }
+    }
+}